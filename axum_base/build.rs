@@ -1,7 +1,18 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
-        .compile(&["../protos/voting.proto"], &["../protos"])?;
+        .compile(
+            &[
+                "../protos/voting.proto",
+                "../protos/user_events.proto",
+                "../protos/analytics.proto",
+                "../protos/users.proto",
+            ],
+            &["../protos"],
+        )?;
     println!("cargo:rerun-if-changed=../protos/voting.proto");
+    println!("cargo:rerun-if-changed=../protos/user_events.proto");
+    println!("cargo:rerun-if-changed=../protos/analytics.proto");
+    println!("cargo:rerun-if-changed=../protos/users.proto");
     Ok(())
-} 
\ No newline at end of file
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::time::Instant;
+use crate::error::AppError;
+
+/// 进程内的查询结果缓存，结果按 JSON 保存以便不同类型共用
+pub(crate) struct QueryCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, (Instant, Vec<u8>)>>,
+}
+
+impl QueryCache {
+    pub(crate) fn new(ttl: Duration, capacity: usize) -> Self {
+        Self { ttl, capacity, entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// 命中未过期的结果时直接返回，否则执行 `load` 并缓存成功的结果
+    pub(crate) async fn get_or_load<T, F>(&self, key: String, load: F) -> Result<T, AppError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T, AppError>>,
+    {
+        if self.ttl.is_zero() || self.capacity == 0 {
+            return load.await;
+        }
+        if let Some(bytes) = self.get(&key) {
+            return Ok(serde_json::from_slice(&bytes)?);
+        }
+        let value = load.await?;
+        self.insert(key, serde_json::to_vec(&value)?);
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(expires_at, _)| *expires_at > Instant::now())
+            .map(|(_, bytes)| bytes.clone())
+    }
+
+    fn insert(&self, key: String, bytes: Vec<u8>) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (expires_at, _)| *expires_at > now);
+            // 仍然已满时淘汰最早过期的一项
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (expires_at, _))| *expires_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key, (now + self.ttl, bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cache_expiry_and_capacity() {
+        let cache = QueryCache::new(Duration::from_secs(30), 2);
+        let load = |value: i32| async move { Ok::<_, AppError>(value) };
+
+        assert_eq!(cache.get_or_load("a".into(), load(1)).await.unwrap(), 1);
+        assert_eq!(cache.get_or_load("a".into(), load(2)).await.unwrap(), 1);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        // 加载失败的结果不缓存
+        let failed = cache.get_or_load("b".into(), async { Err::<i32, _>(AppError::Unauthorized) });
+        assert!(failed.await.is_err());
+        assert_eq!(cache.get_or_load("b".into(), load(3)).await.unwrap(), 3);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        // 已满时淘汰最早过期的 a
+        assert_eq!(cache.get_or_load("c".into(), load(4)).await.unwrap(), 4);
+        assert_eq!(cache.get_or_load("a".into(), load(5)).await.unwrap(), 5);
+        assert_eq!(cache.get_or_load("c".into(), load(6)).await.unwrap(), 4);
+
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert_eq!(cache.get_or_load("c".into(), load(7)).await.unwrap(), 7);
+    }
+}
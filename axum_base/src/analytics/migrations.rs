@@ -0,0 +1,98 @@
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+struct Migration {
+    version: &'static str,
+    sql: &'static str,
+}
+
+// 按版本顺序追加，已发布的文件不要修改
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: "0001_events",
+    sql: include_str!("../../../clickhouse/migrations/0001_events.sql"),
+}];
+
+#[derive(Debug, Row, Serialize, Deserialize)]
+struct AppliedMigration {
+    version: String,
+}
+
+/// 按 `;` 拆分语句，跳过只有注释的片段
+fn statements(sql: &str) -> impl Iterator<Item = &str> {
+    sql.split(';').map(str::trim).filter(|statement| {
+        statement
+            .lines()
+            .any(|line| !line.trim().is_empty() && !line.trim_start().starts_with("--"))
+    })
+}
+
+/// 执行尚未应用的迁移，返回本次应用的版本。
+///
+/// 语句都是幂等的，执行到一半失败后可以整体重跑。
+pub async fn run(client: &Client) -> clickhouse::error::Result<Vec<&'static str>> {
+    client
+        .query(
+            r"
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version String,
+                applied_at DateTime DEFAULT now()
+            ) ENGINE = MergeTree
+            ORDER BY version
+            ",
+        )
+        .execute()
+        .await?;
+    let applied: Vec<String> = client
+        .query("SELECT version FROM schema_migrations")
+        .fetch_all()
+        .await?;
+
+    let mut newly_applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| !applied.iter().any(|v| v == m.version)) {
+        for statement in statements(migration.sql) {
+            client.query(statement).execute().await?;
+        }
+        let mut insert = client.insert::<AppliedMigration>("schema_migrations")?;
+        insert.write(&AppliedMigration { version: migration.version.to_string() }).await?;
+        insert.end().await?;
+        info!(version = migration.version, "applied clickhouse migration");
+        newly_applied.push(migration.version);
+    }
+    Ok(newly_applied)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use clickhouse::test::{handlers, Mock};
+
+    /// 让 mock 服务端应答一次已全部应用的迁移检查
+    pub(crate) fn expect_migrated(mock: &Mock) {
+        mock.add(handlers::record_ddl());
+        mock.add(handlers::provide(MIGRATIONS.iter().map(|m| m.version.to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_run_pending_migrations() {
+        let mock = Mock::new();
+        let client = Client::default().with_url(mock.url());
+
+        let create = mock.add(handlers::record_ddl());
+        mock.add(handlers::provide(Vec::<String>::new()));
+        let tables = [mock.add(handlers::record_ddl()), mock.add(handlers::record_ddl())];
+        let recorded = mock.add(handlers::record::<AppliedMigration>());
+        assert_eq!(run(&client).await.unwrap(), vec!["0001_events"]);
+
+        assert!(create.query().await.contains("schema_migrations"));
+        let [votes, users] = tables;
+        assert!(votes.query().await.contains("CREATE TABLE IF NOT EXISTS vote_events"));
+        assert!(users.query().await.contains("CREATE TABLE IF NOT EXISTS user_events"));
+        let rows: Vec<AppliedMigration> = recorded.collect().await;
+        assert_eq!(rows[0].version, "0001_events");
+
+        // 已应用的版本不会重复执行
+        expect_migrated(&mock);
+        assert!(run(&client).await.unwrap().is_empty());
+    }
+}
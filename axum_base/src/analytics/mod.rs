@@ -0,0 +1,207 @@
+mod cache;
+pub mod migrations;
+pub mod query;
+mod spill;
+pub mod writer;
+
+use std::sync::Arc;
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use crate::config::{AnalyticsConfig, ConsumerConfig, UserEventTopics};
+use crate::constant::OUTBOX_ID_HEADER;
+use crate::event::event_type;
+use crate::kafka::bus::{BusError, EventBus, RawMessage};
+use crate::kafka::consumer::{ConsumerBuilder, ConsumerRuntime, Delivery, HandlerError, Proto};
+use crate::protos::user_events::{user_event::Event, UserEvent};
+use crate::protos::voting::VoteCast;
+use writer::AnalyticsWriter;
+
+pub const VOTE_EVENTS_TABLE: &str = "vote_events";
+pub const USER_EVENTS_TABLE: &str = "user_events";
+
+/// `vote_events` 表的一行，时间为 unix 毫秒
+#[derive(Debug, Clone, PartialEq, Eq, Row, Serialize, Deserialize)]
+pub struct VoteEventRow {
+    pub vote_id: i64,
+    pub url: String,
+    /// 对应 `VotingRequest.Vote`
+    pub vote: i8,
+    pub occurred_at: i64,
+}
+
+/// `user_events` 表的一行，时间为 unix 毫秒
+#[derive(Debug, Clone, PartialEq, Eq, Row, Serialize, Deserialize)]
+pub struct UserEventRow {
+    pub event_id: String,
+    pub event_type: String,
+    pub user_id: Option<i32>,
+    pub username: String,
+    /// 仅登录失败事件有值
+    pub reason: String,
+    pub occurred_at: i64,
+}
+
+pub enum AnalyticsRow {
+    Vote(VoteEventRow),
+    User(UserEventRow),
+}
+
+/// 写入同一张表的一批行，也是溢写文件的格式
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "table", content = "rows")]
+pub(crate) enum Batch {
+    Votes(Vec<VoteEventRow>),
+    Users(Vec<UserEventRow>),
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        match self {
+            Batch::Votes(rows) => rows.len(),
+            Batch::Users(rows) => rows.len(),
+        }
+    }
+}
+
+impl From<VoteCast> for VoteEventRow {
+    fn from(event: VoteCast) -> Self {
+        Self {
+            vote_id: event.vote_id,
+            url: event.url,
+            vote: event.vote as i8,
+            occurred_at: event.occurred_at,
+        }
+    }
+}
+
+/// 发件箱发出的事件用发件箱 id 去重，直接发布的事件退化为消息位置
+fn event_id(message: &RawMessage) -> String {
+    match message.header(OUTBOX_ID_HEADER) {
+        Some(id) => String::from_utf8_lossy(id).into_owned(),
+        None => format!("{}/{}/{}", message.topic, message.partition, message.offset),
+    }
+}
+
+fn user_event_row(event_id: String, event: UserEvent) -> Option<UserEventRow> {
+    let event_data = event.event?;
+    let event_type = event_type(&event_data).to_string();
+    let (user_id, username, reason) = match event_data {
+        Event::Registered(e) => (Some(e.user_id), e.username, String::new()),
+        Event::LoggedIn(e) => (Some(e.user_id), e.username, String::new()),
+        Event::LoginFailed(e) => (e.user_id, e.username, e.reason),
+        Event::Deleted(e) => (Some(e.user_id), String::new(), String::new()),
+    };
+    Some(UserEventRow {
+        event_id,
+        event_type,
+        user_id,
+        username,
+        reason,
+        occurred_at: event.occurred_at,
+    })
+}
+
+fn clickhouse_client(config: &AnalyticsConfig, url: &str) -> Client {
+    let mut client = Client::default().with_url(url).with_database(&config.database);
+    if let Some(user) = &config.user {
+        client = client.with_user(user);
+    }
+    if let Some(password) = &config.password {
+        client = client.with_password(password);
+    }
+    client
+}
+
+/// 消费投票事件和用户事件并交给后台的 [`AnalyticsWriter`]。
+///
+/// 行进入写入缓冲后即提交 offset，进程崩溃时最多丢失一个 `flush_interval` 内的行；
+/// 重复投递的行由 ClickHouse 表引擎去重。未配置 ClickHouse 时返回 `None`。
+pub fn consumer(
+    config: AnalyticsConfig,
+    consumer: ConsumerConfig,
+    votes_topic: &str,
+    user_topics: &UserEventTopics,
+    bus: Arc<dyn EventBus>,
+) -> Result<Option<ConsumerRuntime>, BusError> {
+    let Some(url) = config.clickhouse_url.clone() else {
+        return Ok(None);
+    };
+    let (tx, rx) = mpsc::channel(config.batch_rows.max(1) * 2);
+    let consumer = ConsumerConfig { group_id: config.group_id.clone(), ..consumer };
+    let mut builder = ConsumerBuilder::new(consumer);
+
+    let votes = tx.clone();
+    builder = builder.handler(votes_topic, move |delivery: Delivery<Proto<VoteCast>>| {
+        let votes = votes.clone();
+        async move {
+            let Proto(event) = delivery.value;
+            send(&votes, AnalyticsRow::Vote(event.into())).await
+        }
+    });
+    for topic in [
+        &user_topics.registered,
+        &user_topics.logged_in,
+        &user_topics.login_failed,
+        &user_topics.deleted,
+    ] {
+        let users = tx.clone();
+        builder = builder.handler(topic.clone(), move |delivery: Delivery<Proto<UserEvent>>| {
+            let users = users.clone();
+            async move {
+                let Proto(event) = delivery.value;
+                let row = user_event_row(event_id(&delivery.message), event)
+                    .ok_or_else(|| HandlerError::Permanent("user event has no payload".into()))?;
+                send(&users, AnalyticsRow::User(row)).await
+            }
+        });
+    }
+    let runtime = builder.build(bus)?;
+
+    tokio::spawn(AnalyticsWriter::new(clickhouse_client(&config, &url), config).run(rx));
+    Ok(Some(runtime))
+}
+
+async fn send(tx: &mpsc::Sender<AnalyticsRow>, row: AnalyticsRow) -> Result<(), HandlerError> {
+    tx.send(row)
+        .await
+        .map_err(|_| HandlerError::Retryable("analytics writer has stopped".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::user_events::LoginFailed;
+
+    #[test]
+    fn test_user_event_row() {
+        let event = UserEvent::new(Event::LoginFailed(LoginFailed {
+            username: "nobody".into(),
+            reason: "unknown_user".into(),
+            user_id: None,
+        }));
+        let occurred_at = event.occurred_at;
+        let message = RawMessage {
+            topic: "user.login_failed".into(),
+            partition: 1,
+            offset: 9,
+            ..RawMessage::default()
+        };
+        let row = user_event_row(event_id(&message), event).unwrap();
+        assert_eq!(row, UserEventRow {
+            event_id: "user.login_failed/1/9".into(),
+            event_type: "LoginFailed".into(),
+            user_id: None,
+            username: "nobody".into(),
+            reason: "unknown_user".into(),
+            occurred_at,
+        });
+
+        let message = RawMessage {
+            headers: vec![(OUTBOX_ID_HEADER.to_string(), b"42".to_vec())],
+            ..message
+        };
+        assert_eq!(event_id(&message), "42");
+        assert!(user_event_row("1".into(), UserEvent::default()).is_none());
+    }
+}
@@ -0,0 +1,372 @@
+use std::time::Duration;
+use chrono::{DateTime, DurationRound, NaiveDate, TimeDelta, Utc};
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::config::AnalyticsConfig;
+use crate::error::AppError;
+use super::cache::QueryCache;
+use super::{clickhouse_client, USER_EVENTS_TABLE, VOTE_EVENTS_TABLE};
+
+/// 未指定开始时间时各查询回溯的时长
+pub const DEFAULT_VOTE_SPAN: Duration = Duration::from_secs(24 * 3600);
+pub const DEFAULT_DAILY_SPAN: Duration = Duration::from_secs(30 * 24 * 3600);
+pub const DEFAULT_BUCKET: Duration = Duration::from_secs(3600);
+const MIN_BUCKET: Duration = Duration::from_secs(60);
+pub const DEFAULT_TOP_LIMIT: u32 = 10;
+const MAX_TOP_LIMIT: u32 = 100;
+
+/// 左闭右开的查询时间范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl Window {
+    fn cache_key(&self) -> String {
+        format!("{}|{}", self.from.timestamp_millis(), self.to.timestamp_millis())
+    }
+}
+
+/// 以 ClickHouse 能解析的格式绑定时间，精确到毫秒
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct VoteTrendPoint {
+    pub bucket_start: DateTime<Utc>,
+    pub up_votes: u64,
+    pub down_votes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct UrlVotes {
+    pub url: String,
+    pub up_votes: u64,
+    pub down_votes: u64,
+    /// 赞成数减反对数
+    pub score: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DailyCount {
+    /// UTC 日期
+    pub day: NaiveDate,
+    pub count: u64,
+}
+
+#[derive(Debug, Row, Serialize, Deserialize)]
+struct TrendRow {
+    bucket: i64,
+    up_votes: u64,
+    down_votes: u64,
+}
+
+#[derive(Debug, Row, Serialize, Deserialize)]
+struct UrlVotesRow {
+    url: String,
+    up_votes: u64,
+    down_votes: u64,
+    score: i64,
+}
+
+impl From<UrlVotesRow> for UrlVotes {
+    fn from(row: UrlVotesRow) -> Self {
+        Self {
+            url: row.url,
+            up_votes: row.up_votes,
+            down_votes: row.down_votes,
+            score: row.score,
+        }
+    }
+}
+
+#[derive(Debug, Row, Serialize, Deserialize)]
+struct DailyRow {
+    day: String,
+    count: u64,
+}
+
+/// 在 ClickHouse 上执行统计查询，参数都通过绑定传入，结果按 `cache_ttl` 缓存。
+///
+/// 默认的结束时间取下一个整分钟，相同参数的请求在一分钟内命中同一条缓存。
+pub struct AnalyticsQueries {
+    client: Option<Client>,
+    cache: QueryCache,
+    config: AnalyticsConfig,
+}
+
+impl AnalyticsQueries {
+    pub fn new(config: AnalyticsConfig) -> Self {
+        let client = config
+            .clickhouse_url
+            .as_deref()
+            .map(|url| clickhouse_client(&config, url));
+        Self {
+            client,
+            cache: QueryCache::new(config.cache_ttl, config.cache_capacity),
+            config,
+        }
+    }
+
+    fn client(&self) -> Result<&Client, AppError> {
+        self.client
+            .as_ref()
+            .ok_or_else(|| AppError::Unavailable("analytics is not configured".into()))
+    }
+
+    /// 补全并校验时间范围，`span` 为未指定开始时间时的回溯时长
+    pub fn window(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        span: Duration,
+    ) -> Result<Window, AppError> {
+        let to = match to {
+            Some(to) => to,
+            None => {
+                let now = Utc::now();
+                now.duration_trunc(TimeDelta::minutes(1)).unwrap_or(now) + TimeDelta::minutes(1)
+            }
+        };
+        let from = from.unwrap_or_else(|| to - TimeDelta::from_std(span).unwrap_or(TimeDelta::zero()));
+        if from >= to {
+            return Err(AppError::BadRequest("from must be earlier than to".into()));
+        }
+        if (to - from).to_std().unwrap_or_default() > self.config.max_window {
+            return Err(AppError::BadRequest(format!(
+                "time range must not exceed {} seconds",
+                self.config.max_window.as_secs()
+            )));
+        }
+        Ok(Window { from, to })
+    }
+
+    /// 某个 URL 在时间范围内按 `bucket` 分桶的投票数，没有投票的桶不返回
+    pub async fn vote_trend(&self, url: &str, window: Window, bucket: Duration) -> Result<Vec<VoteTrendPoint>, AppError> {
+        if bucket < MIN_BUCKET {
+            return Err(AppError::BadRequest(format!("bucket must be at least {} seconds", MIN_BUCKET.as_secs())));
+        }
+        let span = (window.to - window.from).to_std().unwrap_or_default();
+        if span.as_secs().div_ceil(bucket.as_secs()) > self.config.max_buckets {
+            return Err(AppError::BadRequest(format!(
+                "time range covers more than {} buckets",
+                self.config.max_buckets
+            )));
+        }
+        let client = self.client()?;
+        let key = format!("trend|{:?}|{}|{}", url, window.cache_key(), bucket.as_secs());
+        self.cache
+            .get_or_load(key, async {
+                let rows = client
+                    .query(
+                        r"
+                        SELECT
+                            toInt64(toUnixTimestamp(toStartOfInterval(occurred_at, toIntervalSecond(?)))) AS bucket,
+                            countIf(vote = 'up') AS up_votes,
+                            countIf(vote = 'down') AS down_votes
+                        FROM ? FINAL
+                        WHERE url = ?
+                          AND occurred_at >= toDateTime64(?, 3, 'UTC')
+                          AND occurred_at < toDateTime64(?, 3, 'UTC')
+                        GROUP BY bucket
+                        ORDER BY bucket
+                        ",
+                    )
+                    .bind(bucket.as_secs())
+                    .bind(clickhouse::sql::Identifier(VOTE_EVENTS_TABLE))
+                    .bind(url)
+                    .bind(timestamp(window.from))
+                    .bind(timestamp(window.to))
+                    .fetch_all::<TrendRow>()
+                    .await?;
+                Ok(rows
+                    .into_iter()
+                    .map(|row| VoteTrendPoint {
+                        bucket_start: DateTime::from_timestamp(row.bucket, 0).unwrap_or_default(),
+                        up_votes: row.up_votes,
+                        down_votes: row.down_votes,
+                    })
+                    .collect())
+            })
+            .await
+    }
+
+    /// 时间范围内得分最高的 URL，得分相同时按 URL 排序
+    pub async fn top_urls(&self, window: Window, limit: u32) -> Result<Vec<UrlVotes>, AppError> {
+        if !(1..=MAX_TOP_LIMIT).contains(&limit) {
+            return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_TOP_LIMIT)));
+        }
+        let client = self.client()?;
+        let key = format!("top|{}|{}", window.cache_key(), limit);
+        self.cache
+            .get_or_load(key, async {
+                let rows = client
+                    .query(
+                        r"
+                        SELECT
+                            url,
+                            countIf(vote = 'up') AS up_votes,
+                            countIf(vote = 'down') AS down_votes,
+                            toInt64(up_votes) - toInt64(down_votes) AS score
+                        FROM ? FINAL
+                        WHERE occurred_at >= toDateTime64(?, 3, 'UTC')
+                          AND occurred_at < toDateTime64(?, 3, 'UTC')
+                        GROUP BY url
+                        ORDER BY score DESC, url
+                        LIMIT ?
+                        ",
+                    )
+                    .bind(clickhouse::sql::Identifier(VOTE_EVENTS_TABLE))
+                    .bind(timestamp(window.from))
+                    .bind(timestamp(window.to))
+                    .bind(limit)
+                    .fetch_all::<UrlVotesRow>()
+                    .await?;
+                Ok(rows.into_iter().map(UrlVotes::from).collect())
+            })
+            .await
+    }
+
+    /// 每天登录过的不同用户数
+    pub async fn daily_active_users(&self, window: Window) -> Result<Vec<DailyCount>, AppError> {
+        self.daily_counts("dau", "uniqExact(user_id)", "UserLoggedIn", window).await
+    }
+
+    /// 每天的注册数
+    pub async fn daily_signups(&self, window: Window) -> Result<Vec<DailyCount>, AppError> {
+        self.daily_counts("signups", "count()", "UserRegistered", window).await
+    }
+
+    /// `aggregate` 只能是本模块内的常量表达式，其余参数通过绑定传入
+    async fn daily_counts(
+        &self,
+        name: &str,
+        aggregate: &'static str,
+        event_type: &str,
+        window: Window,
+    ) -> Result<Vec<DailyCount>, AppError> {
+        let client = self.client()?;
+        let key = format!("{}|{}", name, window.cache_key());
+        self.cache
+            .get_or_load(key, async {
+                let sql = format!(
+                    r"
+                    SELECT toString(toDate(occurred_at)) AS day, toUInt64({}) AS count
+                    FROM ? FINAL
+                    WHERE event_type = ?
+                      AND occurred_at >= toDateTime64(?, 3, 'UTC')
+                      AND occurred_at < toDateTime64(?, 3, 'UTC')
+                    GROUP BY day
+                    ORDER BY day
+                    ",
+                    aggregate
+                );
+                let rows = client
+                    .query(&sql)
+                    .bind(clickhouse::sql::Identifier(USER_EVENTS_TABLE))
+                    .bind(event_type)
+                    .bind(timestamp(window.from))
+                    .bind(timestamp(window.to))
+                    .fetch_all::<DailyRow>()
+                    .await?;
+                rows.into_iter()
+                    .map(|row| {
+                        let day = NaiveDate::parse_from_str(&row.day, "%Y-%m-%d")
+                            .map_err(|e| AppError::Unavailable(format!("unexpected day from clickhouse: {}", e)))?;
+                        Ok(DailyCount { day, count: row.count })
+                    })
+                    .collect()
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clickhouse::test::{handlers, Mock};
+
+    fn queries(mock: &Mock) -> AnalyticsQueries {
+        AnalyticsQueries::new(AnalyticsConfig {
+            clickhouse_url: Some(mock.url().to_string()),
+            ..AnalyticsConfig::default()
+        })
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_vote_trend_is_cached() {
+        let mock = Mock::new();
+        let queries = queries(&mock);
+        let window = queries
+            .window(Some(at("2025-06-01T00:00:00Z")), Some(at("2025-06-02T00:00:00Z")), DEFAULT_VOTE_SPAN)
+            .unwrap();
+
+        mock.add(handlers::provide(vec![
+            TrendRow { bucket: 1748736000, up_votes: 3, down_votes: 1 },
+            TrendRow { bucket: 1748739600, up_votes: 0, down_votes: 2 },
+        ]));
+        let points = queries.vote_trend("https://test.com", window, DEFAULT_BUCKET).await.unwrap();
+        assert_eq!(points[0], VoteTrendPoint {
+            bucket_start: at("2025-06-01T00:00:00Z"),
+            up_votes: 3,
+            down_votes: 1,
+        });
+        assert_eq!(points[1].bucket_start, at("2025-06-01T01:00:00Z"));
+
+        // 第二次命中缓存，mock 上没有多余的应答
+        let cached = queries.vote_trend("https://test.com", window, DEFAULT_BUCKET).await.unwrap();
+        assert_eq!(cached, points);
+
+        // 参数不同则重新查询
+        mock.add(handlers::provide(vec![UrlVotesRow {
+            url: "https://test.com".into(),
+            up_votes: 3,
+            down_votes: 3,
+            score: 0,
+        }]));
+        let top = queries.top_urls(window, 5).await.unwrap();
+        assert_eq!(top[0].url, "https://test.com");
+
+        mock.add(handlers::provide(vec![DailyRow { day: "2025-06-01".into(), count: 4 }]));
+        let days = queries.daily_signups(window).await.unwrap();
+        assert_eq!(days, vec![DailyCount { day: NaiveDate::from_ymd_opt(2025, 6, 1).unwrap(), count: 4 }]);
+    }
+
+    #[tokio::test]
+    async fn test_reject_invalid_parameters() {
+        let queries = AnalyticsQueries::new(AnalyticsConfig {
+            max_buckets: 24,
+            ..AnalyticsConfig::default()
+        });
+        let day = queries
+            .window(Some(at("2025-06-01T00:00:00Z")), Some(at("2025-06-02T00:00:00Z")), DEFAULT_VOTE_SPAN)
+            .unwrap();
+
+        assert!(matches!(
+            queries.window(Some(day.to), Some(day.from), DEFAULT_VOTE_SPAN),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            queries.window(Some(at("2020-01-01T00:00:00Z")), Some(day.to), DEFAULT_VOTE_SPAN),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            queries.vote_trend("u", day, Duration::from_secs(1800)).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(queries.top_urls(day, 0).await, Err(AppError::BadRequest(_))));
+        // 参数合法但未配置 ClickHouse
+        assert!(matches!(queries.vote_trend("u", day, DEFAULT_BUCKET).await, Err(AppError::Unavailable(_))));
+
+        let recent = queries.window(None, None, DEFAULT_VOTE_SPAN).unwrap();
+        assert_eq!(recent.to - recent.from, TimeDelta::hours(24));
+        assert_eq!(recent.to.timestamp() % 60, 0);
+    }
+}
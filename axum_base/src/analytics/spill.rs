@@ -0,0 +1,120 @@
+use std::io;
+use std::path::PathBuf;
+use tracing::warn;
+use super::Batch;
+
+/// ClickHouse 不可用时暂存批次的本地目录，每个批次一个 JSON 文件。
+///
+/// 文件名以写入时间开头，按名称排序即按写入顺序重放。先写临时文件再改名，
+/// 进程中途退出不会留下不完整的批次。
+pub(crate) struct SpillBuffer {
+    dir: PathBuf,
+    max_bytes: u64,
+    seq: u64,
+}
+
+impl SpillBuffer {
+    pub(crate) fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes, seq: 0 }
+    }
+
+    /// 写入一个批次，超过容量上限时丢弃最早的批次
+    pub(crate) async fn push(&mut self, batch: &Batch) -> io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        self.seq += 1;
+        let name = format!("{:013}-{:06}", chrono::Utc::now().timestamp_millis(), self.seq);
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        tokio::fs::write(&tmp, serde_json::to_vec(batch)?).await?;
+        tokio::fs::rename(&tmp, self.dir.join(format!("{}.json", name))).await?;
+
+        let files = self.files().await?;
+        let mut total: u64 = files.iter().map(|(_, size)| size).sum();
+        for (path, size) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            warn!(path = %path.display(), "analytics spill buffer is full, dropping oldest batch");
+            tokio::fs::remove_file(&path).await?;
+            total -= size;
+        }
+        Ok(())
+    }
+
+    /// 最早写入的批次；无法解析的文件直接删除
+    pub(crate) async fn oldest(&self) -> io::Result<Option<(PathBuf, Batch)>> {
+        for (path, _) in self.files().await? {
+            let bytes = tokio::fs::read(&path).await?;
+            match serde_json::from_slice(&bytes) {
+                Ok(batch) => return Ok(Some((path, batch))),
+                Err(e) => {
+                    warn!(path = %path.display(), "discarding corrupt analytics spill file: {}", e);
+                    tokio::fs::remove_file(&path).await?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) async fn remove(&self, path: &PathBuf) -> io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+
+    /// 已完成写入的批次文件及大小，按写入顺序排列
+    async fn files(&self) -> io::Result<Vec<(PathBuf, u64)>> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                files.push((path, entry.metadata().await?.len()));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::analytics::VoteEventRow;
+
+    /// 每个测试独占的临时目录
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("axum_base-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn votes(vote_id: i64) -> Batch {
+        Batch::Votes(vec![VoteEventRow {
+            vote_id,
+            url: "https://test.com".into(),
+            vote: 0,
+            occurred_at: 0,
+        }])
+    }
+
+    #[tokio::test]
+    async fn test_spill_drops_oldest_over_capacity() {
+        let dir = temp_dir("spill-capacity");
+        let size = serde_json::to_vec(&votes(1)).unwrap().len() as u64;
+        let mut spill = SpillBuffer::new(dir.clone(), size * 2);
+
+        for vote_id in 1..=3 {
+            spill.push(&votes(vote_id)).await.unwrap();
+        }
+        std::fs::write(dir.join("0000000000000-000000.json"), b"not json").unwrap();
+
+        let (path, batch) = spill.oldest().await.unwrap().unwrap();
+        assert!(matches!(batch, Batch::Votes(rows) if rows[0].vote_id == 2));
+        spill.remove(&path).await.unwrap();
+        let (_, batch) = spill.oldest().await.unwrap().unwrap();
+        assert!(matches!(batch, Batch::Votes(rows) if rows[0].vote_id == 3));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
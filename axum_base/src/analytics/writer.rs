@@ -0,0 +1,261 @@
+use clickhouse::{Client, Row};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, error, warn};
+use crate::config::AnalyticsConfig;
+use super::spill::SpillBuffer;
+use super::{migrations, AnalyticsRow, Batch, UserEventRow, VoteEventRow, USER_EVENTS_TABLE, VOTE_EVENTS_TABLE};
+
+/// 把收到的行按表缓冲，达到 `batch_rows` 或每隔 `flush_interval` 批量写入 ClickHouse。
+///
+/// 写入失败的批次转存到本地溢写目录，按 [`AnalyticsConfig::backoff`] 退避期间新批次
+/// 直接溢写，恢复后按写入顺序重放。首次写入前执行 ClickHouse 迁移。
+pub struct AnalyticsWriter {
+    client: Client,
+    config: AnalyticsConfig,
+    spill: SpillBuffer,
+    migrated: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+    votes: Vec<VoteEventRow>,
+    users: Vec<UserEventRow>,
+}
+
+impl AnalyticsWriter {
+    pub fn new(client: Client, config: AnalyticsConfig) -> Self {
+        Self {
+            spill: SpillBuffer::new(config.spill_dir.clone(), config.spill_max_bytes),
+            client,
+            config,
+            migrated: false,
+            failures: 0,
+            retry_at: None,
+            votes: Vec::new(),
+            users: Vec::new(),
+        }
+    }
+
+    /// 所有发送端关闭后写出剩余的行并退出
+    pub async fn run(mut self, mut rows: mpsc::Receiver<AnalyticsRow>) {
+        let mut ticker = tokio::time::interval(self.config.flush_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                row = rows.recv() => match row {
+                    Some(row) => {
+                        let full = match row {
+                            AnalyticsRow::Vote(row) => {
+                                self.votes.push(row);
+                                self.votes.len() >= self.config.batch_rows
+                            }
+                            AnalyticsRow::User(row) => {
+                                self.users.push(row);
+                                self.users.len() >= self.config.batch_rows
+                            }
+                        };
+                        if full {
+                            self.flush().await;
+                        }
+                    }
+                    None => {
+                        self.flush().await;
+                        return;
+                    }
+                },
+                _ = ticker.tick() => {
+                    self.flush().await;
+                    self.replay_spill().await;
+                }
+            }
+        }
+    }
+
+    async fn flush(&mut self) {
+        if !self.votes.is_empty() {
+            let batch = Batch::Votes(std::mem::take(&mut self.votes));
+            self.write(batch).await;
+        }
+        if !self.users.is_empty() {
+            let batch = Batch::Users(std::mem::take(&mut self.users));
+            self.write(batch).await;
+        }
+    }
+
+    async fn write(&mut self, batch: Batch) {
+        if self.available() && self.try_insert(&batch).await {
+            return;
+        }
+        if let Err(e) = self.spill.push(&batch).await {
+            error!(rows = batch.len(), "failed to spill analytics batch, dropping it: {}", e);
+        }
+    }
+
+    /// 退避期间不访问 ClickHouse
+    fn available(&self) -> bool {
+        self.retry_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    async fn try_insert(&mut self, batch: &Batch) -> bool {
+        match self.insert(batch).await {
+            Ok(()) => {
+                self.failures = 0;
+                self.retry_at = None;
+                true
+            }
+            Err(e) => {
+                self.failures += 1;
+                let backoff = self.config.backoff(self.failures);
+                warn!(rows = batch.len(), ?backoff, "failed to write analytics batch: {}", e);
+                self.retry_at = Some(Instant::now() + backoff);
+                false
+            }
+        }
+    }
+
+    async fn insert(&mut self, batch: &Batch) -> clickhouse::error::Result<()> {
+        if !self.migrated {
+            migrations::run(&self.client).await?;
+            self.migrated = true;
+        }
+        match batch {
+            Batch::Votes(rows) => insert_rows(&self.client, VOTE_EVENTS_TABLE, rows).await,
+            Batch::Users(rows) => insert_rows(&self.client, USER_EVENTS_TABLE, rows).await,
+        }
+    }
+
+    /// 按写入顺序重放溢写的批次，遇到失败时停止等待下次退避结束
+    async fn replay_spill(&mut self) {
+        while self.available() {
+            let (path, batch) = match self.spill.oldest().await {
+                Ok(Some(next)) => next,
+                Ok(None) => return,
+                Err(e) => {
+                    error!("failed to read analytics spill buffer: {}", e);
+                    return;
+                }
+            };
+            if !self.try_insert(&batch).await {
+                return;
+            }
+            debug!(rows = batch.len(), "replayed spilled analytics batch");
+            if let Err(e) = self.spill.remove(&path).await {
+                // 文件留在目录中会被再次写入，表引擎合并时去重
+                error!(path = %path.display(), "failed to remove replayed spill file: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn insert_rows<T: Row + Serialize>(client: &Client, table: &str, rows: &[T]) -> clickhouse::error::Result<()> {
+    let mut insert = client.insert::<T>(table)?;
+    for row in rows {
+        insert.write(row).await?;
+    }
+    insert.end().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use clickhouse::test::{handlers, status, Mock};
+    use crate::analytics::migrations::tests::expect_migrated;
+    use crate::analytics::spill::tests::temp_dir;
+
+    fn vote(vote_id: i64) -> VoteEventRow {
+        VoteEventRow {
+            vote_id,
+            url: "https://test.com".into(),
+            vote: 0,
+            occurred_at: 1_700_000_000_000 + vote_id,
+        }
+    }
+
+    fn start(mock: &Mock, config: AnalyticsConfig) -> mpsc::Sender<AnalyticsRow> {
+        let (tx, rx) = mpsc::channel(16);
+        let client = Client::default().with_url(mock.url());
+        tokio::spawn(AnalyticsWriter::new(client, config).run(rx));
+        tx
+    }
+
+    async fn timeout<F: std::future::Future>(future: F) -> F::Output {
+        tokio::time::timeout(Duration::from_secs(5), future)
+            .await
+            .expect("timed out waiting for insert")
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_size_and_interval() {
+        let mock = Mock::new();
+        let dir = temp_dir("analytics-flush");
+        let tx = start(&mock, AnalyticsConfig {
+            batch_rows: 2,
+            flush_interval: Duration::from_millis(100),
+            spill_dir: dir.clone(),
+            ..AnalyticsConfig::default()
+        });
+
+        expect_migrated(&mock);
+        let votes = mock.add(handlers::record::<VoteEventRow>());
+        let users = mock.add(handlers::record::<UserEventRow>());
+        // 达到行数阈值立即写入
+        tx.send(AnalyticsRow::Vote(vote(1))).await.unwrap();
+        tx.send(AnalyticsRow::Vote(vote(2))).await.unwrap();
+        let rows: Vec<VoteEventRow> = timeout(votes.collect()).await;
+        assert_eq!(rows, vec![vote(1), vote(2)]);
+
+        // 未达到阈值的行在下一个间隔写入
+        let user = UserEventRow {
+            event_id: "42".into(),
+            event_type: "UserRegistered".into(),
+            user_id: Some(7),
+            username: "alice".into(),
+            reason: String::new(),
+            occurred_at: 1_700_000_000_000,
+        };
+        tx.send(AnalyticsRow::User(user.clone())).await.unwrap();
+        let rows: Vec<UserEventRow> = timeout(users.collect()).await;
+        assert_eq!(rows, vec![user]);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_spill_and_replay_during_outage() {
+        let mock = Mock::new();
+        let dir = temp_dir("analytics-outage");
+        let tx = start(&mock, AnalyticsConfig {
+            batch_rows: 1,
+            flush_interval: Duration::from_millis(50),
+            spill_dir: dir.clone(),
+            retry_backoff: Duration::from_millis(300),
+            ..AnalyticsConfig::default()
+        });
+
+        expect_migrated(&mock);
+        mock.add(handlers::failure(status::SERVICE_UNAVAILABLE));
+        let first = mock.add(handlers::record::<VoteEventRow>());
+        let second = mock.add(handlers::record::<VoteEventRow>());
+
+        // 第一批写入失败后溢写，退避期间第二批直接溢写
+        tx.send(AnalyticsRow::Vote(vote(1))).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tx.send(AnalyticsRow::Vote(vote(2))).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // 退避结束后按顺序重放
+        let rows: Vec<VoteEventRow> = timeout(first.collect()).await;
+        assert_eq!(rows, vec![vote(1)]);
+        let rows: Vec<VoteEventRow> = timeout(second.collect()).await;
+        assert_eq!(rows, vec![vote(2)]);
+        timeout(async {
+            while std::fs::read_dir(&dir).unwrap().count() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
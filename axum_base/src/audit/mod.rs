@@ -0,0 +1,322 @@
+use std::borrow::Cow;
+use std::convert::Infallible;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use tracing::{warn, Instrument};
+use utoipa::{IntoParams, ToSchema};
+use crate::controller::auth::{request_user, JwtSecret};
+use crate::error::AppError;
+use crate::model::cursor::{fetch_page, CursorCodec, CursorPage, CursorQuery, Keyset, Order};
+use crate::ratelimit::client_ip;
+use crate::request_id::RequestId;
+use crate::telemetry::db_span;
+
+/// 一次导出的最大行数，超出时需要缩小时间范围
+pub const EXPORT_LIMIT: i64 = 10_000;
+/// 超出部分截断，避免客户端写入任意长度的内容
+const MAX_USER_AGENT_LEN: usize = 512;
+
+const SELECT_EVENTS: &str =
+    "SELECT id, occurred_at, actor_id, action, target, ip, user_agent, request_id, diff FROM audit_events WHERE TRUE";
+
+/// 需要审计的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuditAction {
+    Login,
+    LoginFailed,
+    UserCreated,
+    UserDeleted,
+    Broadcast,
+    LogLevelChanged,
+}
+
+impl AuditAction {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Login => "user.login",
+            AuditAction::LoginFailed => "user.login_failed",
+            AuditAction::UserCreated => "user.created",
+            AuditAction::UserDeleted => "user.deleted",
+            AuditAction::Broadcast => "broadcast.sent",
+            AuditAction::LogLevelChanged => "admin.log_level_changed",
+        }
+    }
+}
+
+/// 字段变化，只保留前后不同的字段；创建时 `old` 为 null，删除时 `new` 为 null
+pub(crate) fn diff<'a>(changes: impl IntoIterator<Item = (&'a str, Value, Value)>) -> Value {
+    let map: Map<_, _> = changes
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| (field.to_string(), json!({ "old": old, "new": new })))
+        .collect();
+    map.into()
+}
+
+/// 审计记录的存储
+#[derive(Clone)]
+pub(crate) struct AuditLog {
+    pool: PgPool,
+    trust_forwarded: bool,
+}
+
+impl AuditLog {
+    /// `trust_forwarded` 与限流相同，只在可信的反向代理之后取 `X-Forwarded-For`
+    pub(crate) fn new(pool: PgPool, trust_forwarded: bool) -> Self {
+        Self { pool, trust_forwarded }
+    }
+}
+
+/// 请求的来源信息，handler 通过它写入审计记录。
+///
+/// 请求携带有效 token 时以该用户作为操作者，没有或无效时不拒绝请求。
+pub(crate) struct Audit {
+    log: AuditLog,
+    actor: Option<i32>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for Audit
+where
+    AuditLog: FromRef<S>,
+    JwtSecret: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let log = AuditLog::from_ref(state);
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).chars().take(MAX_USER_AGENT_LEN).collect());
+        Ok(Self {
+            actor: request_user(parts, &JwtSecret::from_ref(state)).map(|user| user.id),
+            ip: client_ip(parts, log.trust_forwarded),
+            request_id: parts.extensions.get::<RequestId>().map(ToString::to_string),
+            user_agent,
+            log,
+        })
+    }
+}
+
+impl Audit {
+    /// 以登录、注册等操作确定的用户作为操作者
+    pub(crate) fn actor(mut self, id: i32) -> Self {
+        self.actor = Some(id);
+        self
+    }
+
+    /// 写入失败只记录日志，不影响已经完成的操作
+    pub(crate) async fn record(&self, action: AuditAction, target: Option<String>, diff: Value) {
+        let result = sqlx::query(
+            r"
+            INSERT INTO audit_events (actor_id, action, target, ip, user_agent, request_id, diff)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+        )
+        .bind(self.actor)
+        .bind(action.as_str())
+        .bind(target)
+        .bind(&self.ip)
+        .bind(&self.user_agent)
+        .bind(&self.request_id)
+        .bind(diff)
+        .execute(&self.log.pool)
+        .instrument(db_span("INSERT audit_events"))
+        .await;
+        if let Err(e) = result {
+            warn!(action = action.as_str(), actor = ?self.actor, "failed to write audit event: {}", e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow, PartialEq)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    /// 操作对象，例如 `user:42`、`room:lobby`
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    /// `{"字段": {"old": ..., "new": ...}}`
+    #[schema(value_type = Object)]
+    pub diff: Value,
+}
+
+impl Keyset for AuditEvent {
+    type Key = (i64,);
+    const KEY_COLUMNS: &'static [&'static str] = &["id"];
+    const ORDER: Order = Order::Desc;
+
+    fn key(&self) -> Self::Key {
+        (self.id,)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditFilter {
+    /// 操作者的用户 id
+    pub actor: Option<i32>,
+    /// 例如 `user.login_failed`
+    pub action: Option<String>,
+    /// RFC 3339，包含该时间
+    pub from: Option<DateTime<Utc>>,
+    /// RFC 3339，不包含该时间
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    fn query(&self) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                return Err(AppError::BadRequest("from must not be later than to".into()));
+            }
+        }
+        let mut query = QueryBuilder::new(SELECT_EVENTS);
+        if let Some(actor) = self.actor {
+            query.push(" AND actor_id = ").push_bind(actor);
+        }
+        if let Some(action) = &self.action {
+            query.push(" AND action = ").push_bind(action.clone());
+        }
+        if let Some(from) = self.from {
+            query.push(" AND occurred_at >= ").push_bind(from);
+        }
+        if let Some(to) = self.to {
+            query.push(" AND occurred_at < ").push_bind(to);
+        }
+        Ok(query)
+    }
+}
+
+impl AuditEvent {
+    /// 按 id 倒序翻页
+    pub(crate) async fn list(
+        pool: &PgPool,
+        codec: &CursorCodec,
+        filter: &AuditFilter,
+        request: &CursorQuery,
+        limit: i64,
+    ) -> Result<CursorPage<AuditEvent>, AppError> {
+        fetch_page(pool, filter.query()?, codec, "audit", request, limit).await
+    }
+
+    /// 按时间顺序返回全部匹配的记录，超过 [`EXPORT_LIMIT`] 条时拒绝
+    pub(crate) async fn export(pool: &PgPool, filter: &AuditFilter) -> Result<Vec<AuditEvent>, AppError> {
+        let mut query = filter.query()?;
+        query.push(" ORDER BY id ASC LIMIT ").push_bind(EXPORT_LIMIT + 1);
+        let events: Vec<AuditEvent> = query
+            .build_query_as()
+            .fetch_all(pool)
+            .instrument(db_span("SELECT audit_events"))
+            .await?;
+        if events.len() as i64 > EXPORT_LIMIT {
+            return Err(AppError::Unprocessable(format!(
+                "more than {} events match, narrow the time range",
+                EXPORT_LIMIT
+            )));
+        }
+        Ok(events)
+    }
+}
+
+/// 以 `= + - @` 等开头的内容会被表格软件当作公式执行，前面加单引号
+fn csv_cell(value: &str) -> Cow<'_, str> {
+    match value.chars().next() {
+        Some('=' | '+' | '-' | '@' | '\t' | '\r') => Cow::Owned(format!("'{}", value)),
+        _ => Cow::Borrowed(value),
+    }
+}
+
+/// 第一行为列名，`diff` 为 JSON 字符串
+pub(crate) fn to_csv(events: &[AuditEvent]) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(["id", "occurred_at", "actor_id", "action", "target", "ip", "user_agent", "request_id", "diff"])
+        .expect("writing to a Vec does not fail");
+    for event in events {
+        let optional = |value: &Option<String>| csv_cell(value.as_deref().unwrap_or_default()).into_owned();
+        writer
+            .write_record([
+                event.id.to_string(),
+                event.occurred_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+                event.actor_id.map(|id| id.to_string()).unwrap_or_default(),
+                csv_cell(&event.action).into_owned(),
+                optional(&event.target),
+                optional(&event.ip),
+                optional(&event.user_agent),
+                optional(&event.request_id),
+                event.diff.to_string(),
+            ])
+            .expect("writing to a Vec does not fail");
+    }
+    writer.into_inner().expect("writing to a Vec does not fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::test_utils::TestDatabase;
+
+    #[test]
+    fn test_to_csv() {
+        let event = AuditEvent {
+            id: 7,
+            occurred_at: DateTime::parse_from_rfc3339("2025-06-25T09:00:00Z").unwrap().to_utc(),
+            actor_id: None,
+            action: AuditAction::LoginFailed.as_str().to_string(),
+            target: Some("user:alice".into()),
+            ip: Some("127.0.0.1".into()),
+            user_agent: Some("=HYPERLINK(\"x\")".into()),
+            request_id: None,
+            diff: diff([("username", Value::Null, json!("alice")), ("email", json!("a"), json!("a"))]),
+        };
+        let output = String::from_utf8(to_csv(&[event])).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "id,occurred_at,actor_id,action,target,ip,user_agent,request_id,diff");
+        assert_eq!(
+            lines[1],
+            r#"7,2025-06-25T09:00:00.000Z,,user.login_failed,user:alice,127.0.0.1,"'=HYPERLINK(""x"")",,"{""username"":{""new"":""alice"",""old"":null}}""#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_and_filter() {
+        let db = TestDatabase::new().await;
+        let log = AuditLog::new(db.pool.clone(), false);
+        let audit = |actor| Audit { log: log.clone(), actor, ip: None, user_agent: None, request_id: Some("req".into()) };
+        audit(None).record(AuditAction::LoginFailed, Some("user:bob".into()), json!({})).await;
+        audit(Some(1)).record(AuditAction::Login, Some("user:1".into()), json!({})).await;
+        audit(Some(1)).record(AuditAction::UserDeleted, Some("user:1".into()), json!({})).await;
+
+        let filter = AuditFilter { actor: Some(1), ..Default::default() };
+        let codec = CursorCodec::new("secret");
+        let page = AuditEvent::list(&db.pool, &codec, &filter, &CursorQuery::default(), 1).await.unwrap();
+        assert_eq!(page.records[0].action, "user.deleted");
+        let request = CursorQuery { after: page.next_cursor, ..Default::default() };
+        let page = AuditEvent::list(&db.pool, &codec, &filter, &request, 1).await.unwrap();
+        assert_eq!(page.records[0].action, "user.login");
+        assert!(page.next_cursor.is_none());
+
+        let filter = AuditFilter { action: Some("user.login_failed".into()), ..Default::default() };
+        let events = AuditEvent::export(&db.pool, &filter).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].actor_id, None);
+        assert_eq!(events[0].request_id.as_deref(), Some("req"));
+
+        // 记录不能修改
+        assert!(sqlx::query("UPDATE audit_events SET action = 'x'").execute(&db.pool).await.is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::{extract::State, Json};
+use rand::Rng;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{AsyncCommands, RedisResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::warn;
+use utoipa::ToSchema;
+use crate::config::CacheConfig;
+use crate::constant::CACHE_KEY_PREFIX;
+use crate::error::AppError;
+use crate::telemetry::redis::TracedConnection;
+
+pub fn user_key(id: i32) -> String {
+    format!("user:{}", id)
+}
+
+#[derive(Debug, Default)]
+struct CacheMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct CacheStats {
+    /// 从 Redis 读到的次数
+    pub hits: u64,
+    /// 未命中并从数据源加载的次数
+    pub misses: u64,
+    /// 未命中但等待了同一 key 正在进行的加载的次数
+    pub coalesced: u64,
+    /// Redis 读写或编解码失败的次数，失败时直接使用数据源
+    pub errors: u64,
+}
+
+type Flight = Arc<OnceCell<Vec<u8>>>;
+
+/// 基于 Redis 的读穿缓存，值按 JSON 保存。
+///
+/// 同一进程内同一 key 同时只有一个调用方加载数据源，其他调用方等待它的结果。
+/// 过期时间在 `ttl` 上加减 `ttl_jitter` 比例的随机量，避免同时写入的 key 同时过期。
+/// Redis 不可用时退化为直接读取数据源。数据变更后需要调用 [`RedisCache::invalidate`]。
+pub struct RedisCache {
+    client: redis::Client,
+    conn: OnceCell<ConnectionManager>,
+    config: CacheConfig,
+    flights: Mutex<HashMap<String, Flight>>,
+    metrics: CacheMetrics,
+}
+
+impl RedisCache {
+    pub fn new(client: redis::Client, config: CacheConfig) -> Self {
+        Self {
+            client,
+            conn: OnceCell::new(),
+            config,
+            flights: Mutex::new(HashMap::new()),
+            metrics: CacheMetrics::default(),
+        }
+    }
+
+    /// 不重试连接，Redis 不可用时尽快退化为读取数据源
+    async fn conn(&self) -> RedisResult<TracedConnection> {
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(0)
+            .set_connection_timeout(self.config.timeout)
+            .set_response_timeout(self.config.timeout);
+        self.conn
+            .get_or_try_init(|| self.client.get_connection_manager_with_config(config))
+            .await
+            .cloned()
+            .map(TracedConnection::new)
+    }
+
+    fn redis_key(key: &str) -> String {
+        format!("{}{}", CACHE_KEY_PREFIX, key)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.metrics.hits.load(Ordering::Relaxed),
+            misses: self.metrics.misses.load(Ordering::Relaxed),
+            coalesced: self.metrics.coalesced.load(Ordering::Relaxed),
+            errors: self.metrics.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 命中时返回缓存的值，否则执行 `load` 并写入缓存；`load` 的错误原样返回且不缓存
+    pub async fn get_or_load<T, F, Fut>(&self, key: &str, load: F) -> Result<T, AppError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        if let Some(bytes) = self.get(key).await {
+            match serde_json::from_slice(&bytes) {
+                Ok(value) => {
+                    self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                Err(e) => {
+                    self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+                    warn!(key, "discarding undecodable cache entry: {}", e);
+                }
+            }
+        }
+
+        let flight = self.flights.lock().unwrap().entry(key.to_string()).or_default().clone();
+        let loaded = AtomicBool::new(false);
+        let result = flight
+            .get_or_try_init(|| async {
+                loaded.store(true, Ordering::Relaxed);
+                self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+                let bytes = serde_json::to_vec(&load().await?)?;
+                // 加载期间被 invalidate 时读到的可能是旧数据，不写入缓存
+                if self.in_flight(key, &flight) {
+                    self.set(key, &bytes).await;
+                    // invalidate 的删除可能早于这次写入完成，再删除一次
+                    if !self.in_flight(key, &flight) {
+                        self.delete(key).await;
+                    }
+                }
+                Ok::<_, AppError>(bytes)
+            })
+            .await
+            .cloned();
+        if !loaded.load(Ordering::Relaxed) {
+            self.metrics.coalesced.fetch_add(1, Ordering::Relaxed);
+        }
+        // 结果已经在 Redis 中，之后的调用方不再需要等待这次加载
+        let mut flights = self.flights.lock().unwrap();
+        if flights.get(key).is_some_and(|f| Arc::ptr_eq(f, &flight)) {
+            flights.remove(key);
+        }
+        drop(flights);
+
+        Ok(serde_json::from_slice(&result?)?)
+    }
+
+    /// `flight` 仍然是 `key` 当前的加载，没有被 invalidate 移除
+    fn in_flight(&self, key: &str, flight: &Flight) -> bool {
+        self.flights.lock().unwrap().get(key).is_some_and(|f| Arc::ptr_eq(f, flight))
+    }
+
+    /// 删除缓存的值，数据源更新或删除后调用
+    pub async fn invalidate(&self, key: &str) {
+        // 正在进行的加载可能读到了旧数据，之后的调用方不再等待它，它的结果也不会写入缓存
+        self.flights.lock().unwrap().remove(key);
+        self.delete(key).await;
+    }
+
+    async fn delete(&self, key: &str) {
+        let result: RedisResult<()> = async {
+            let mut conn = self.conn().await?;
+            conn.del(Self::redis_key(key)).await
+        }
+        .await;
+        if let Err(e) = result {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+            warn!(key, "failed to invalidate cache entry: {}", e);
+        }
+    }
+
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let result: RedisResult<Option<Vec<u8>>> = async {
+            let mut conn = self.conn().await?;
+            conn.get(Self::redis_key(key)).await
+        }
+        .await;
+        result.unwrap_or_else(|e| {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+            warn!(key, "failed to read cache entry: {}", e);
+            None
+        })
+    }
+
+    async fn set(&self, key: &str, bytes: &[u8]) {
+        let ttl = jittered(self.config.ttl, self.config.ttl_jitter);
+        let result: RedisResult<()> = async {
+            let mut conn = self.conn().await?;
+            conn.pset_ex(Self::redis_key(key), bytes, ttl.as_millis().max(1) as u64).await
+        }
+        .await;
+        if let Err(e) = result {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+            warn!(key, "failed to write cache entry: {}", e);
+        }
+    }
+}
+
+/// 在 `ttl` 上随机加减不超过 `jitter` 比例的时间
+fn jittered(ttl: Duration, jitter: f64) -> Duration {
+    let jitter = jitter.clamp(0.0, 1.0);
+    if jitter == 0.0 {
+        return ttl;
+    }
+    ttl.mul_f64(1.0 + rand::thread_rng().gen_range(-jitter..=jitter))
+}
+
+pub async fn cache_stats(State(cache): State<Arc<RedisCache>>) -> Json<CacheStats> {
+    Json(cache.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use crate::init::test_utils::TestRedis;
+
+    #[test]
+    fn test_jittered_ttl() {
+        let ttl = Duration::from_secs(100);
+        assert_eq!(jittered(ttl, 0.0), ttl);
+        for _ in 0..100 {
+            let value = jittered(ttl, 0.1);
+            assert!(value >= Duration::from_secs(90) && value <= Duration::from_secs(110));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_without_redis() {
+        // 连接不上 Redis 时仍然合并并发加载
+        let client = redis::Client::open("redis://127.0.0.1:1/").unwrap();
+        let cache = Arc::new(RedisCache::new(client, CacheConfig::default()));
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, loads) = (cache.clone(), loads.clone());
+                tokio::spawn(async move {
+                    cache
+                        .get_or_load("k", || async {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                            Ok(42)
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), 42);
+        }
+
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.coalesced), (0, 1, 7));
+        assert!(stats.errors > 0);
+        assert!(cache.flights.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_through_and_invalidate() {
+        let test_redis = TestRedis::new().await;
+        let cache = RedisCache::new(test_redis.client.clone(), CacheConfig::default());
+        let key = user_key(7);
+
+        let value: String = cache.get_or_load(&key, || async { Ok("alice".to_string()) }).await.unwrap();
+        assert_eq!(value, "alice");
+        let value: String = cache.get_or_load(&key, || async { Ok("bob".to_string()) }).await.unwrap();
+        assert_eq!(value, "alice");
+        let failed = cache.get_or_load::<String, _, _>("missing", || async { Err(AppError::NotFound("x".into())) });
+        assert!(matches!(failed.await, Err(AppError::NotFound(_))));
+
+        cache.invalidate(&key).await;
+        let value: String = cache.get_or_load(&key, || async { Ok("bob".to_string()) }).await.unwrap();
+        assert_eq!(value, "bob");
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3, coalesced: 0, errors: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_during_load() {
+        let test_redis = TestRedis::new().await;
+        let cache = Arc::new(RedisCache::new(test_redis.client.clone(), CacheConfig::default()));
+        let key = user_key(8);
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+
+        // 加载读到旧数据后，数据源更新并调用 invalidate，加载才完成
+        let slow = tokio::spawn({
+            let (cache, key) = (cache.clone(), key.clone());
+            async move {
+                cache
+                    .get_or_load(&key, || async {
+                        started_tx.send(()).unwrap();
+                        release_rx.await.unwrap();
+                        Ok("old".to_string())
+                    })
+                    .await
+                    .unwrap()
+            }
+        });
+        started_rx.await.unwrap();
+        cache.invalidate(&key).await;
+        release_tx.send(()).unwrap();
+        assert_eq!(slow.await.unwrap(), "old");
+
+        let value: String = cache.get_or_load(&key, || async { Ok("new".to_string()) }).await.unwrap();
+        assert_eq!(value, "new");
+        assert!(cache.flights.lock().unwrap().is_empty());
+    }
+}
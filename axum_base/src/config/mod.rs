@@ -0,0 +1,1091 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// 读取环境变量并解析，缺失或格式错误时使用默认值
+pub(crate) fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+fn env_secs(key: &str, default: u64) -> Duration {
+    Duration::from_secs(env_or(key, default))
+}
+
+/// 读取定时器周期（秒），`tokio::time::interval` 不接受零周期，为 0 时也使用默认值
+fn env_period(key: &str, default: Duration) -> Duration {
+    Some(env_secs(key, default.as_secs())).filter(|d| !d.is_zero()).unwrap_or(default)
+}
+
+/// 读取容量类配置，`tokio::sync::broadcast::channel` 等不接受 0，为 0 时也使用默认值
+fn env_nonzero(key: &str, default: usize) -> usize {
+    Some(env_or(key, default)).filter(|n| *n > 0).unwrap_or(default)
+}
+
+/// 读取必须为正数的上限，缺失、格式错误或不大于 0 时使用默认值
+fn env_positive(key: &str, default: i64) -> i64 {
+    Some(env_or(key, default)).filter(|n| *n > 0).unwrap_or(default)
+}
+
+fn env_millis(key: &str, default: Duration) -> Duration {
+    Duration::from_millis(env_or(key, default.as_millis() as u64))
+}
+
+/// 与 [`env_period`] 相同，单位为毫秒
+fn env_period_millis(key: &str, default: Duration) -> Duration {
+    Some(env_millis(key, default)).filter(|d| !d.is_zero()).unwrap_or(default)
+}
+
+/// 第 `failures` 次失败后等待 `base * 2^(failures-1)`，不超过 `max`
+fn exponential_backoff(base: Duration, max: Duration, failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    base.saturating_mul(factor).min(max)
+}
+
+/// 订阅者落后于广播超过 channel 容量时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// 跳过丢失的消息，并发送一条 `{"type":"lagged","missed":N}` 通知
+    SkipAndNotify,
+    /// 以 `WS_CLOSE_LAGGED` 关闭连接
+    Disconnect,
+    /// 先转存到容量为 N 的连接级队列，队列溢出时断开
+    Buffer(usize),
+}
+
+impl FromStr for LagPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "skip" => Ok(Self::SkipAndNotify),
+            "disconnect" => Ok(Self::Disconnect),
+            other => other
+                .strip_prefix("buffer:")
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .map(Self::Buffer)
+                .ok_or_else(|| format!("invalid lag policy: {}", other)),
+        }
+    }
+}
+
+/// 解析 `room=policy,room=policy` 格式，忽略无法解析的项
+fn parse_lag_policies(raw: &str) -> HashMap<String, LagPolicy> {
+    raw.split(',')
+        .filter_map(|item| {
+            let (room, policy) = item.split_once('=')?;
+            Some((room.trim().to_string(), policy.parse().ok()?))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct WsConfig {
+    /// 服务端发送 Ping 的间隔，不能为 0
+    pub ping_interval: Duration,
+    /// 发送 Ping 后等待 Pong 的最长时间
+    pub pong_timeout: Duration,
+    /// 双向都没有数据帧时断开连接，`Duration::ZERO` 表示不启用
+    pub idle_timeout: Duration,
+    /// 每个房间广播 channel 的容量，不能为 0
+    pub channel_capacity: usize,
+    /// 未单独配置的房间使用的落后策略
+    pub default_lag_policy: LagPolicy,
+    /// 按房间名配置的落后策略
+    pub lag_policies: HashMap<String, LagPolicy>,
+    /// 在线状态在 Redis 中的有效期，需大于 `ping_interval`，每次 Ping 时续期
+    pub presence_ttl: Duration,
+    /// 在线状态读写 Redis 的超时，超时后跳过本次更新，避免阻塞连接的心跳和转发
+    pub presence_timeout: Duration,
+    /// 每个房间为 SSE 续传保留的最近消息条数，0 表示不保留
+    pub replay_capacity: usize,
+    /// 房间没有订阅者后续传缓冲的保留时长
+    pub replay_retention: Duration,
+    /// SSE 保活注释的发送间隔，不能为 0
+    pub sse_keep_alive: Duration,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(300),
+            channel_capacity: 100,
+            default_lag_policy: LagPolicy::SkipAndNotify,
+            lag_policies: HashMap::new(),
+            presence_ttl: Duration::from_secs(90),
+            presence_timeout: Duration::from_millis(500),
+            replay_capacity: 100,
+            replay_retention: Duration::from_secs(300),
+            sse_keep_alive: Duration::from_secs(15),
+        }
+    }
+}
+
+impl WsConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            ping_interval: env_period("WS_PING_INTERVAL_SECS", default.ping_interval),
+            pong_timeout: env_secs("WS_PONG_TIMEOUT_SECS", default.pong_timeout.as_secs()),
+            idle_timeout: env_secs("WS_IDLE_TIMEOUT_SECS", default.idle_timeout.as_secs()),
+            channel_capacity: env_nonzero("WS_CHANNEL_CAPACITY", default.channel_capacity),
+            default_lag_policy: env_or("WS_LAG_POLICY", default.default_lag_policy),
+            lag_policies: env::var("WS_LAG_POLICIES")
+                .map(|raw| parse_lag_policies(&raw))
+                .unwrap_or_default(),
+            presence_ttl: env_secs("PRESENCE_TTL_SECS", default.presence_ttl.as_secs()),
+            presence_timeout: env_millis("PRESENCE_TIMEOUT_MS", default.presence_timeout),
+            replay_capacity: env_or("WS_REPLAY_CAPACITY", default.replay_capacity),
+            replay_retention: env_secs(
+                "WS_REPLAY_RETENTION_SECS",
+                default.replay_retention.as_secs(),
+            ),
+            sse_keep_alive: env_period("SSE_KEEP_ALIVE_SECS", default.sse_keep_alive),
+        }
+    }
+
+    pub fn lag_policy(&self, room: &str) -> LagPolicy {
+        self.lag_policies
+            .get(room)
+            .copied()
+            .unwrap_or(self.default_lag_policy)
+    }
+}
+
+/// 把 Kafka topic 的消息转发到 WebSocket 房间
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeMapping {
+    pub topic: String,
+    pub room: String,
+    /// 消息 key 为用户 id，只推送给该用户的连接
+    pub per_user: bool,
+}
+
+impl FromStr for BridgeMapping {
+    type Err = String;
+
+    /// `topic=room` 或 `topic=room:user`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (topic, room) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid bridge mapping: {}", s))?;
+        let (room, per_user) = match room.trim().strip_suffix(":user") {
+            Some(room) => (room, true),
+            None => (room.trim(), false),
+        };
+        let (topic, room) = (topic.trim(), room.trim());
+        if topic.is_empty() || room.is_empty() {
+            return Err(format!("invalid bridge mapping: {}", s));
+        }
+        Ok(Self {
+            topic: topic.to_string(),
+            room: room.to_string(),
+            per_user,
+        })
+    }
+}
+
+/// 本机的节点标识，优先使用主机名，重启后不变
+fn local_node_id() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("pid{}", std::process::id()))
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    pub mappings: Vec<BridgeMapping>,
+    /// 消费组名前缀，实际的消费组为 `{group_id}-{node_id}`
+    pub group_id: String,
+    /// 每个实例都要收到全部消息，因此各自使用独立的消费组，新消费组只读取之后的消息。
+    /// 部署时应通过 `WS_BRIDGE_NODE_ID` 设置不随容器重建变化的标识，
+    /// 重启后才能从上次提交的位置继续，否则会遗留无人使用的消费组
+    pub node_id: String,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            mappings: Vec::new(),
+            group_id: "axum_base-ws-bridge".to_string(),
+            node_id: local_node_id(),
+        }
+    }
+}
+
+impl BridgeConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        // 忽略无法解析的项
+        let mappings = env::var("WS_BRIDGE_MAPPINGS")
+            .map(|raw| raw.split(',').filter_map(|item| item.parse().ok()).collect())
+            .unwrap_or(default.mappings);
+        Self {
+            mappings,
+            group_id: env_or("WS_BRIDGE_GROUP_ID", default.group_id),
+            node_id: env::var("WS_BRIDGE_NODE_ID")
+                .ok()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+                .unwrap_or(default.node_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatConfig {
+    /// 单条消息内容的最大字符数
+    pub max_content_len: usize,
+    /// 单次拉取历史消息的最大条数
+    pub max_page_size: i64,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_content_len: 2000,
+            max_page_size: 100,
+        }
+    }
+}
+
+impl ChatConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            max_content_len: env_or("CHAT_MAX_CONTENT_LEN", default.max_content_len),
+            max_page_size: env_positive("CHAT_MAX_PAGE_SIZE", default.max_page_size),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    /// 逗号分隔的 broker 地址
+    pub brokers: String,
+    pub client_id: String,
+    /// 消息在本地等待凑批的最长时间
+    pub linger: Duration,
+    /// 单个批次的最大消息数
+    pub batch_size: usize,
+    /// 本地发送队列的容量，队满后发送方等待 `enqueue_timeout`
+    pub queue_capacity: usize,
+    pub enqueue_timeout: Duration,
+    /// 从入队到 broker 确认的最长时间，超时后投递失败
+    pub delivery_timeout: Duration,
+    /// `0`、`1` 或 `all`
+    pub acks: String,
+    /// librdkafka 上报统计信息（用于消费延迟指标）的间隔，`Duration::ZERO` 表示不上报
+    pub stats_interval: Duration,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            client_id: "axum_base".to_string(),
+            linger: Duration::from_millis(5),
+            batch_size: 1000,
+            queue_capacity: 10000,
+            enqueue_timeout: Duration::from_millis(1000),
+            delivery_timeout: Duration::from_millis(30000),
+            acks: "all".to_string(),
+            stats_interval: Duration::from_secs(15),
+        }
+    }
+}
+
+impl KafkaConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            brokers: env::var("KAFKA_URL").expect("KAFKA_URL must be set"),
+            client_id: env_or("KAFKA_CLIENT_ID", default.client_id),
+            linger: env_millis("KAFKA_LINGER_MS", default.linger),
+            batch_size: env_or("KAFKA_BATCH_SIZE", default.batch_size),
+            queue_capacity: env_or("KAFKA_QUEUE_CAPACITY", default.queue_capacity),
+            enqueue_timeout: env_millis("KAFKA_ENQUEUE_TIMEOUT_MS", default.enqueue_timeout),
+            delivery_timeout: env_millis("KAFKA_DELIVERY_TIMEOUT_MS", default.delivery_timeout),
+            acks: env_or("KAFKA_ACKS", default.acks),
+            stats_interval: env_millis("KAFKA_STATS_INTERVAL_MS", default.stats_interval),
+        }
+    }
+}
+
+/// 用户领域事件各自发布到的 topic
+#[derive(Debug, Clone)]
+pub struct UserEventTopics {
+    pub registered: String,
+    pub logged_in: String,
+    pub login_failed: String,
+    pub deleted: String,
+}
+
+impl Default for UserEventTopics {
+    fn default() -> Self {
+        Self {
+            registered: "user.registered".to_string(),
+            logged_in: "user.logged_in".to_string(),
+            login_failed: "user.login_failed".to_string(),
+            deleted: "user.deleted".to_string(),
+        }
+    }
+}
+
+impl UserEventTopics {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            registered: env_or("KAFKA_TOPIC_USER_REGISTERED", default.registered),
+            logged_in: env_or("KAFKA_TOPIC_USER_LOGGED_IN", default.logged_in),
+            login_failed: env_or("KAFKA_TOPIC_LOGIN_FAILED", default.login_failed),
+            deleted: env_or("KAFKA_TOPIC_USER_DELETED", default.deleted),
+        }
+    }
+}
+
+/// 事件总线的实现
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventBusKind {
+    Kafka,
+    /// 进程内实现，消息不落盘，用于测试和本地开发
+    Memory,
+}
+
+impl FromStr for EventBusKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "kafka" => Ok(Self::Kafka),
+            "memory" => Ok(Self::Memory),
+            other => Err(format!("invalid event bus: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBusConfig {
+    pub kind: EventBusKind,
+    /// 进程内实现每个 topic 的分区数
+    pub memory_partitions: i32,
+}
+
+impl Default for EventBusConfig {
+    fn default() -> Self {
+        Self {
+            kind: EventBusKind::Kafka,
+            memory_partitions: 3,
+        }
+    }
+}
+
+impl EventBusConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            kind: env_or("EVENT_BUS", default.kind),
+            memory_partitions: env_or("EVENT_BUS_MEMORY_PARTITIONS", default.memory_partitions),
+        }
+    }
+}
+
+/// 消费组在分区上没有已提交的位置时从哪里开始读取
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OffsetReset {
+    /// 从保留的最早一条开始
+    #[default]
+    Earliest,
+    /// 只读取订阅之后发布的消息
+    Latest,
+}
+
+impl OffsetReset {
+    /// librdkafka `auto.offset.reset` 的取值
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Earliest => "earliest",
+            Self::Latest => "latest",
+        }
+    }
+}
+
+/// Kafka 消费者运行时的配置
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    pub group_id: String,
+    /// 新消费组的起始位置，由使用方决定，不从环境变量读取
+    pub offset_reset: OffsetReset,
+    /// 所有分区合计同时执行的处理函数数量，不能为 0
+    pub max_concurrency: usize,
+    /// 每个分区已拉取但尚未处理的消息上限，满了之后暂停拉取该分区，不能为 0
+    pub partition_buffer: usize,
+    /// 处理失败后的重试次数，用完后转入死信 topic
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        Self {
+            group_id: "axum_base".to_string(),
+            offset_reset: OffsetReset::Earliest,
+            max_concurrency: 16,
+            partition_buffer: 64,
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ConsumerConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            group_id: env_or("KAFKA_GROUP_ID", default.group_id),
+            offset_reset: default.offset_reset,
+            max_concurrency: env_nonzero("KAFKA_CONSUMER_CONCURRENCY", default.max_concurrency),
+            partition_buffer: env_nonzero("KAFKA_CONSUMER_PARTITION_BUFFER", default.partition_buffer),
+            max_retries: env_or("KAFKA_CONSUMER_MAX_RETRIES", default.max_retries),
+            retry_backoff: env_millis("KAFKA_CONSUMER_RETRY_BACKOFF_MS", default.retry_backoff),
+            max_backoff: env_millis("KAFKA_CONSUMER_MAX_BACKOFF_MS", default.max_backoff),
+        }
+    }
+
+    /// 第 `failures` 次失败后的等待时间
+    pub fn backoff(&self, failures: u32) -> Duration {
+        exponential_backoff(self.retry_backoff, self.max_backoff, failures)
+    }
+}
+
+/// 发件箱转发任务的配置
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    /// 没有待发送消息时的轮询间隔
+    pub poll_interval: Duration,
+    /// 每批最多读取的行数
+    pub batch_size: i64,
+    /// 连续失败时的退避起点，每次翻倍直到 `max_backoff`
+    pub retry_backoff: Duration,
+    pub max_backoff: Duration,
+    /// 已发送的行保留多久后删除
+    pub retention: Duration,
+    pub prune_interval: Duration,
+    /// 认领一批行后独占的时长，需要长于发布一批的时间；转发任务退出后过期由其他实例接手
+    pub lease: Duration,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(500),
+            batch_size: 100,
+            retry_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(60),
+            retention: Duration::from_secs(7 * 24 * 3600),
+            prune_interval: Duration::from_secs(3600),
+            lease: Duration::from_secs(60),
+        }
+    }
+}
+
+impl OutboxConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            poll_interval: env_millis("OUTBOX_POLL_INTERVAL_MS", default.poll_interval),
+            batch_size: env_or("OUTBOX_BATCH_SIZE", default.batch_size),
+            retry_backoff: env_millis("OUTBOX_RETRY_BACKOFF_MS", default.retry_backoff),
+            max_backoff: env_millis("OUTBOX_MAX_BACKOFF_MS", default.max_backoff),
+            retention: env_secs("OUTBOX_RETENTION_SECS", default.retention.as_secs()),
+            prune_interval: env_secs("OUTBOX_PRUNE_INTERVAL_SECS", default.prune_interval.as_secs()),
+            lease: env_secs("OUTBOX_LEASE_SECS", default.lease.as_secs()),
+        }
+    }
+
+    /// 第 `failures` 次连续失败后的等待时间
+    pub fn backoff(&self, failures: u32) -> Duration {
+        exponential_backoff(self.retry_backoff, self.max_backoff, failures)
+    }
+}
+
+/// Redis 读穿缓存的配置
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub ttl: Duration,
+    /// 过期时间随机浮动的比例，取值 0 到 1
+    pub ttl_jitter: f64,
+    /// 连接和单条命令的超时，超时后直接读取数据源
+    pub timeout: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(300),
+            ttl_jitter: 0.1,
+            timeout: Duration::from_millis(200),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            ttl: env_secs("CACHE_TTL_SECS", default.ttl.as_secs()),
+            ttl_jitter: env_or("CACHE_TTL_JITTER", default.ttl_jitter),
+            timeout: env_millis("CACHE_TIMEOUT_MS", default.timeout),
+        }
+    }
+}
+
+/// 投票排行榜的配置
+#[derive(Debug, Clone)]
+pub struct LeaderboardConfig {
+    /// 用 Postgres 中的投票重建排行榜的间隔，不能为 0
+    pub reconcile_interval: Duration,
+    /// 连接和单条命令的超时，投票时更新排行榜失败不影响投票本身
+    pub timeout: Duration,
+}
+
+impl Default for LeaderboardConfig {
+    fn default() -> Self {
+        Self {
+            reconcile_interval: Duration::from_secs(300),
+            timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+impl LeaderboardConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            reconcile_interval: env_period("LEADERBOARD_RECONCILE_SECS", default.reconcile_interval),
+            timeout: env_millis("LEADERBOARD_TIMEOUT_MS", default.timeout),
+        }
+    }
+}
+
+/// 限流计数、幂等记录等共享状态保存的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreBackend {
+    /// 多个实例共享同一份状态
+    Redis,
+    /// 只在进程内保存，用于单节点和测试
+    Memory,
+}
+
+impl FromStr for StoreBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "redis" => Ok(Self::Redis),
+            "memory" => Ok(Self::Memory),
+            other => Err(format!("invalid store backend: {}", other)),
+        }
+    }
+}
+
+/// 限流时区分调用方的方式，取不到用户或 API key 时按 IP 计数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    Ip,
+    User,
+    ApiKey,
+}
+
+impl FromStr for RateLimitKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "ip" => Ok(Self::Ip),
+            "user" => Ok(Self::User),
+            "api_key" => Ok(Self::ApiKey),
+            other => Err(format!("invalid rate limit key: {}", other)),
+        }
+    }
+}
+
+/// 一个路由组的限流策略，每 `period` 允许 `limit` 次请求，最多连续 `burst` 次
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    pub limit: u32,
+    pub period: Duration,
+    pub burst: u32,
+    pub key: RateLimitKey,
+}
+
+impl RateLimitPolicy {
+    pub fn new(limit: u32, period: Duration, key: RateLimitKey) -> Self {
+        Self { limit, period, burst: limit, key }
+    }
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = String;
+
+    /// `次数/秒数[,burst=N][,key=ip|user|api_key]`，burst 默认等于次数
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid rate limit policy: {}", s);
+        let mut items = s.split(',');
+        let (limit, period) = items.next().and_then(|rate| rate.split_once('/')).ok_or_else(invalid)?;
+        let limit: u32 = limit.trim().parse().map_err(|_| invalid())?;
+        let period: u64 = period.trim().parse().map_err(|_| invalid())?;
+        if limit == 0 || period == 0 {
+            return Err(invalid());
+        }
+        let mut policy = Self::new(limit, Duration::from_secs(period), RateLimitKey::Ip);
+        for item in items {
+            match item.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                Some(("burst", burst)) => policy.burst = burst.parse().ok().filter(|b| *b > 0).ok_or_else(invalid)?,
+                Some(("key", key)) => policy.key = key.parse()?,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// 读取路由组的限流策略，`off` 表示不限流，缺失或格式错误时使用默认值
+fn env_policy(key: &str, default: Option<RateLimitPolicy>) -> Option<RateLimitPolicy> {
+    match env::var(key) {
+        Ok(v) if v.trim() == "off" => None,
+        Ok(v) => v.parse().ok().or(default),
+        Err(_) => default,
+    }
+}
+
+/// 按路由组配置的限流
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub backend: StoreBackend,
+    /// 取 `X-Forwarded-For` 的第一个地址作为客户端 IP，只应在可信的反向代理之后开启
+    pub trust_forwarded: bool,
+    /// 允许单独计数的 `X-Api-Key`，逗号分隔；不在其中的 key 按 IP 计数
+    pub api_keys: Vec<String>,
+    pub users: Option<RateLimitPolicy>,
+    pub websocket: Option<RateLimitPolicy>,
+    pub chat: Option<RateLimitPolicy>,
+    pub analytics: Option<RateLimitPolicy>,
+    pub votes: Option<RateLimitPolicy>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let minute = Duration::from_secs(60);
+        Self {
+            backend: StoreBackend::Redis,
+            trust_forwarded: false,
+            api_keys: Vec::new(),
+            users: Some(RateLimitPolicy { burst: 10, ..RateLimitPolicy::new(60, minute, RateLimitKey::Ip) }),
+            websocket: Some(RateLimitPolicy::new(120, minute, RateLimitKey::User)),
+            chat: Some(RateLimitPolicy::new(120, minute, RateLimitKey::User)),
+            analytics: Some(RateLimitPolicy::new(60, minute, RateLimitKey::User)),
+            votes: Some(RateLimitPolicy::new(120, minute, RateLimitKey::Ip)),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            backend: env_or("RATE_LIMIT_BACKEND", default.backend),
+            trust_forwarded: env_or("RATE_LIMIT_TRUST_FORWARDED", default.trust_forwarded),
+            api_keys: env::var("RATE_LIMIT_API_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(String::from)
+                .collect(),
+            users: env_policy("RATE_LIMIT_USERS", default.users),
+            websocket: env_policy("RATE_LIMIT_WEBSOCKET", default.websocket),
+            chat: env_policy("RATE_LIMIT_CHAT", default.chat),
+            analytics: env_policy("RATE_LIMIT_ANALYTICS", default.analytics),
+            votes: env_policy("RATE_LIMIT_VOTES", default.votes),
+        }
+    }
+}
+
+/// 带 `Idempotency-Key` 的 POST 请求的去重配置
+#[derive(Debug, Clone)]
+pub struct IdempotencyConfig {
+    pub backend: StoreBackend,
+    /// 保存首次响应、重放给重试请求的时长
+    pub ttl: Duration,
+    /// 首次请求处理中的占位时长，超时后同一 key 的请求可以重新执行
+    pub lock_ttl: Duration,
+    /// 计算请求指纹时读取的请求体上限
+    pub max_body_bytes: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            backend: StoreBackend::Redis,
+            ttl: Duration::from_secs(24 * 3600),
+            lock_ttl: Duration::from_secs(30),
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl IdempotencyConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            backend: env_or("IDEMPOTENCY_BACKEND", default.backend),
+            ttl: env_secs("IDEMPOTENCY_TTL_SECS", default.ttl.as_secs()),
+            lock_ttl: env_secs("IDEMPOTENCY_LOCK_SECS", default.lock_ttl.as_secs()),
+            max_body_bytes: env_or("IDEMPOTENCY_MAX_BODY_BYTES", default.max_body_bytes),
+        }
+    }
+}
+
+/// Voting gRPC 服务的配置
+#[derive(Debug, Clone)]
+pub struct VotingConfig {
+    pub addr: SocketAddr,
+    /// 投票事件经发件箱发布到的 topic
+    pub topic: String,
+}
+
+impl Default for VotingConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 50051)),
+            topic: "votes".to_string(),
+        }
+    }
+}
+
+impl VotingConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            addr: env_or("GRPC_ADDR", default.addr),
+            topic: env_or("KAFKA_TOPIC_VOTES", default.topic),
+        }
+    }
+}
+
+/// 把事件写入 ClickHouse 的分析任务的配置
+#[derive(Debug, Clone)]
+pub struct AnalyticsConfig {
+    /// ClickHouse HTTP 地址，未配置时不启动分析任务
+    pub clickhouse_url: Option<String>,
+    pub database: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub group_id: String,
+    /// 任一张表缓冲的行数达到该值时立即写入
+    pub batch_rows: usize,
+    /// 缓冲中的行最长等待多久写入，不能为 0
+    pub flush_interval: Duration,
+    /// ClickHouse 不可用时批次写入的本地目录
+    pub spill_dir: PathBuf,
+    /// 溢写目录的容量上限，超过后丢弃最早的批次
+    pub spill_max_bytes: u64,
+    /// 写入失败后的退避起点，每次翻倍直到 `max_backoff`
+    pub retry_backoff: Duration,
+    pub max_backoff: Duration,
+    /// 查询结果的缓存时长，`Duration::ZERO` 表示不缓存
+    pub cache_ttl: Duration,
+    /// 最多缓存的查询结果数
+    pub cache_capacity: usize,
+    /// 单次查询的最大时间范围
+    pub max_window: Duration,
+    /// 趋势查询最多返回的时间桶数
+    pub max_buckets: u64,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            clickhouse_url: None,
+            database: "default".to_string(),
+            user: None,
+            password: None,
+            group_id: "axum_base-analytics".to_string(),
+            batch_rows: 1000,
+            flush_interval: Duration::from_secs(1),
+            spill_dir: PathBuf::from("data/analytics-spill"),
+            spill_max_bytes: 256 * 1024 * 1024,
+            retry_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            cache_ttl: Duration::from_secs(30),
+            cache_capacity: 1000,
+            max_window: Duration::from_secs(366 * 24 * 3600),
+            max_buckets: 1000,
+        }
+    }
+}
+
+impl AnalyticsConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            clickhouse_url: env::var("CLICKHOUSE_URL").ok().filter(|url| !url.trim().is_empty()),
+            database: env_or("CLICKHOUSE_DATABASE", default.database),
+            user: env::var("CLICKHOUSE_USER").ok(),
+            password: env::var("CLICKHOUSE_PASSWORD").ok(),
+            group_id: env_or("ANALYTICS_GROUP_ID", default.group_id),
+            batch_rows: env_or("ANALYTICS_BATCH_ROWS", default.batch_rows),
+            flush_interval: env_period_millis("ANALYTICS_FLUSH_INTERVAL_MS", default.flush_interval),
+            spill_dir: env_or("ANALYTICS_SPILL_DIR", default.spill_dir),
+            spill_max_bytes: env_or("ANALYTICS_SPILL_MAX_BYTES", default.spill_max_bytes),
+            retry_backoff: env_millis("ANALYTICS_RETRY_BACKOFF_MS", default.retry_backoff),
+            max_backoff: env_millis("ANALYTICS_MAX_BACKOFF_MS", default.max_backoff),
+            cache_ttl: env_secs("ANALYTICS_CACHE_TTL_SECS", default.cache_ttl.as_secs()),
+            cache_capacity: env_or("ANALYTICS_CACHE_CAPACITY", default.cache_capacity),
+            max_window: env_secs("ANALYTICS_MAX_WINDOW_SECS", default.max_window.as_secs()),
+            max_buckets: env_or("ANALYTICS_MAX_BUCKETS", default.max_buckets),
+        }
+    }
+
+    /// 第 `failures` 次连续写入失败后的等待时间
+    pub fn backoff(&self, failures: u32) -> Duration {
+        exponential_backoff(self.retry_backoff, self.max_backoff, failures)
+    }
+}
+
+/// 链路追踪数据的导出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceExporter {
+    /// 只输出日志，不导出
+    None,
+    /// 通过 gRPC 发送到 OTLP collector
+    Otlp,
+    /// 每个 span 一行 JSON 写到标准输出
+    Stdout,
+    /// 每个 span 一行 JSON 追加到文件
+    File,
+}
+
+impl FromStr for TraceExporter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "none" | "" => Ok(Self::None),
+            "otlp" => Ok(Self::Otlp),
+            "stdout" => Ok(Self::Stdout),
+            "file" => Ok(Self::File),
+            other => Err(format!("invalid traces exporter: {}", other)),
+        }
+    }
+}
+
+/// OpenTelemetry 链路追踪的配置，使用 OpenTelemetry 约定的环境变量
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    pub exporter: TraceExporter,
+    pub service_name: String,
+    /// OTLP collector 的 gRPC 地址
+    pub otlp_endpoint: String,
+    /// `File` 导出方式写入的文件
+    pub file: PathBuf,
+    /// 没有上游采样决定时的采样比例
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            exporter: TraceExporter::None,
+            service_name: "axum_base".to_string(),
+            otlp_endpoint: "http://localhost:4317".to_string(),
+            file: PathBuf::from("data/traces.jsonl"),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            exporter: env_or("OTEL_TRACES_EXPORTER", default.exporter),
+            service_name: env_or("OTEL_SERVICE_NAME", default.service_name),
+            otlp_endpoint: env_or("OTEL_EXPORTER_OTLP_ENDPOINT", default.otlp_endpoint),
+            file: env_or("OTEL_TRACES_FILE", default.file),
+            sample_ratio: env_or("OTEL_TRACES_SAMPLER_ARG", default.sample_ratio).clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// 日志的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// 便于阅读的单行文本
+    Text,
+    /// 每条一行 JSON，供日志收集使用
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("invalid log format: {}", other)),
+        }
+    }
+}
+
+/// 日志文件的滚动周期
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "minutely" => Ok(Self::Minutely),
+            "hourly" => Ok(Self::Hourly),
+            "daily" => Ok(Self::Daily),
+            "never" => Ok(Self::Never),
+            other => Err(format!("invalid log rotation: {}", other)),
+        }
+    }
+}
+
+/// 日志的配置
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// `RUST_LOG` 格式的过滤规则，运行时可以通过管理接口修改
+    pub filter: String,
+    pub format: LogFormat,
+    /// 设置后同时写入该目录下按周期滚动的文件
+    pub dir: Option<PathBuf>,
+    pub file_prefix: String,
+    pub rotation: LogRotation,
+    /// 最多保留的日志文件数，`0` 表示不清理
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+            format: LogFormat::Text,
+            dir: None,
+            file_prefix: "axum_base".to_string(),
+            rotation: LogRotation::Daily,
+            max_files: 7,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            filter: env::var("RUST_LOG").ok().filter(|v| !v.trim().is_empty()).unwrap_or(default.filter),
+            format: env_or("LOG_FORMAT", default.format),
+            dir: env::var("LOG_DIR").ok().filter(|v| !v.trim().is_empty()).map(PathBuf::from),
+            file_prefix: env_or("LOG_FILE_PREFIX", default.file_prefix),
+            rotation: env_or("LOG_ROTATION", default.rotation),
+            max_files: env_or("LOG_MAX_FILES", default.max_files),
+        }
+    }
+}
+
+/// 管理接口的访问控制
+#[derive(Debug, Clone, Default)]
+pub struct AdminConfig {
+    /// 可以调用管理接口的用户 id，逗号分隔；为空时管理接口拒绝所有请求
+    pub user_ids: Vec<i32>,
+    /// 抓取 `/metrics` 使用的 Bearer token，未设置时只有管理员可以访问
+    pub metrics_token: Option<String>,
+}
+
+impl AdminConfig {
+    pub fn from_env() -> Self {
+        let user_ids = env::var("ADMIN_USER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|id| id.trim().parse().ok())
+            .collect();
+        Self {
+            user_ids,
+            metrics_token: env::var("METRICS_TOKEN").ok().filter(|v| !v.trim().is_empty()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lag_policies() {
+        let policies = parse_lag_policies("alerts=disconnect, chat=buffer:256,bad=buffer:0,feed=skip");
+        assert_eq!(policies.get("alerts"), Some(&LagPolicy::Disconnect));
+        assert_eq!(policies.get("chat"), Some(&LagPolicy::Buffer(256)));
+        assert_eq!(policies.get("feed"), Some(&LagPolicy::SkipAndNotify));
+        assert!(!policies.contains_key("bad"));
+    }
+
+    #[test]
+    fn test_parse_bridge_mapping() {
+        let mapping: BridgeMapping = "notifications = alerts:user".parse().unwrap();
+        assert_eq!(mapping.topic, "notifications");
+        assert_eq!(mapping.room, "alerts");
+        assert!(mapping.per_user);
+        let mapping: BridgeMapping = "news=feed".parse().unwrap();
+        assert!(!mapping.per_user);
+        assert!("news".parse::<BridgeMapping>().is_err());
+        assert!("news=:user".parse::<BridgeMapping>().is_err());
+    }
+
+    #[test]
+    fn test_parse_rate_limit_policy() {
+        let policy: RateLimitPolicy = "100/60, burst=20, key=user".parse().unwrap();
+        assert_eq!(policy, RateLimitPolicy {
+            limit: 100,
+            period: Duration::from_secs(60),
+            burst: 20,
+            key: RateLimitKey::User,
+        });
+        let policy: RateLimitPolicy = "5/1".parse().unwrap();
+        assert_eq!((policy.burst, policy.key), (5, RateLimitKey::Ip));
+        for invalid in ["0/60", "10", "10/60,burst=0", "10/60,key=cookie", "10/60,foo"] {
+            assert!(invalid.parse::<RateLimitPolicy>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_outbox_backoff() {
+        let config = OutboxConfig::default();
+        assert_eq!(config.backoff(1), Duration::from_millis(500));
+        assert_eq!(config.backoff(3), Duration::from_secs(2));
+        assert_eq!(config.backoff(40), config.max_backoff);
+    }
+}
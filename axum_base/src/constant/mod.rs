@@ -0,0 +1,58 @@
+// WebSocket 关闭码，4000-4999 为应用自定义区间
+pub const WS_CLOSE_PONG_TIMEOUT: u16 = 4000;
+pub const WS_CLOSE_PONG_TIMEOUT_REASON: &str = "pong timeout";
+pub const WS_CLOSE_IDLE_TIMEOUT: u16 = 4001;
+pub const WS_CLOSE_IDLE_TIMEOUT_REASON: &str = "idle timeout";
+pub const WS_CLOSE_LAGGED: u16 = 4002;
+pub const WS_CLOSE_LAGGED_REASON: &str = "lagged behind broadcast";
+
+// 未指定房间时使用的默认广播房间
+pub const WS_DEFAULT_ROOM: &str = "global";
+
+// 由服务端生成的帧类型前缀，`POST /broadcast` 不能发送，避免伪造聊天和在线事件
+pub const WS_RESERVED_FRAME_TYPES: &[&str] = &["chat.", "presence.", "lagged"];
+
+// 在线状态的 Redis key 和发布订阅频道
+pub const PRESENCE_ROOM_KEY_PREFIX: &str = "presence:room:";
+pub const PRESENCE_ROOMS_KEY: &str = "presence:rooms";
+pub const PRESENCE_EVENTS_CHANNEL: &str = "presence:events";
+
+// 用户领域事件的 protobuf 结构版本，不兼容变更时递增
+pub const USER_EVENT_SCHEMA_VERSION: u32 = 1;
+
+// 发件箱消息发布时附带的行 id，消费方据此去重
+pub const OUTBOX_ID_HEADER: &str = "outbox-id";
+
+// 请求标识，HTTP 头、gRPC metadata 和 Kafka 消息头使用同一名称
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// 死信 topic 的后缀和附带失败信息的消息头
+pub const DLQ_TOPIC_SUFFIX: &str = ".dlq";
+pub const DLQ_ORIGINAL_TOPIC_HEADER: &str = "dlq-original-topic";
+pub const DLQ_ORIGINAL_PARTITION_HEADER: &str = "dlq-original-partition";
+pub const DLQ_ORIGINAL_OFFSET_HEADER: &str = "dlq-original-offset";
+pub const DLQ_CONSUMER_GROUP_HEADER: &str = "dlq-consumer-group";
+pub const DLQ_ERROR_HEADER: &str = "dlq-error";
+pub const DLQ_ATTEMPTS_HEADER: &str = "dlq-attempts";
+pub const DLQ_FAILED_AT_HEADER: &str = "dlq-failed-at";
+
+// 读穿缓存在 Redis 中的 key 前缀
+pub const CACHE_KEY_PREFIX: &str = "cache:";
+
+// 投票排行榜在 Redis 中的 key，按小时分桶的 key 后接小时序号
+pub const LEADERBOARD_ALL_KEY: &str = "leaderboard:all";
+pub const LEADERBOARD_HOUR_KEY_PREFIX: &str = "leaderboard:hour:";
+pub const LEADERBOARD_RECONCILE_LOCK_KEY: &str = "leaderboard:reconcile-lock";
+
+// 排行榜查询滚动窗口时合并分桶用的临时 key，只在脚本内使用
+pub const LEADERBOARD_UNION_KEY: &str = "leaderboard:union";
+
+// 限流计数在 Redis 中的 key 前缀，后接路由组和调用方
+pub const RATE_LIMIT_KEY_PREFIX: &str = "ratelimit:";
+// 按 API key 限流时读取的请求头
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+// 幂等请求的请求头、重放响应附带的请求头和 Redis key 前缀
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+pub const IDEMPOTENCY_KEY_PREFIX: &str = "idempotency:";
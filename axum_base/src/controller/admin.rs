@@ -0,0 +1,113 @@
+use axum::{
+    extract::{Query, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+use utoipa::ToSchema;
+use crate::audit::{diff, to_csv, Audit, AuditAction, AuditEvent, AuditFilter};
+use crate::controller::auth::AdminUser;
+use crate::error::AppError;
+use crate::init::app_state::AppState;
+use crate::init::logging;
+use crate::model::cursor::{CursorPage, CursorQuery, DEFAULT_LIMIT, MAX_LIMIT};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogLevel {
+    /// `RUST_LOG` 语法的过滤规则，例如 `info,sqlx=debug`
+    pub filter: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/log-level",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Current log filter", body = LogLevel),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub(crate) async fn get_log_level(_admin: AdminUser) -> Result<impl IntoResponse, AppError> {
+    let filter = logging::current_filter()
+        .ok_or_else(|| AppError::Unavailable("logging is not initialized".into()))?;
+    Ok(Json(LogLevel { filter }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/log-level",
+    tag = "admin",
+    request_body = LogLevel,
+    responses(
+        (status = 200, description = "Log filter replaced", body = LogLevel),
+        (status = 400, description = "Invalid filter"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub(crate) async fn set_log_level(
+    AdminUser(admin): AdminUser,
+    audit: Audit,
+    Json(body): Json<LogLevel>,
+) -> Result<impl IntoResponse, AppError> {
+    let previous = logging::current_filter();
+    let filter = logging::set_filter(&body.filter)?;
+    info!(admin = admin.id, filter = %filter, "log filter changed");
+    let changes = diff([("filter", json!(previous), json!(filter))]);
+    audit.record(AuditAction::LogLevelChanged, None, changes).await;
+    Ok(Json(LogLevel { filter }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Audit events newest first", body = CursorPage<AuditEvent>),
+        (status = 400, description = "Invalid time range or cursor"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    ),
+    params(AuditFilter, CursorQuery)
+)]
+pub(crate) async fn list_audit_events(
+    _admin: AdminUser,
+    State(context): State<AppState>,
+    Query(filter): Query<AuditFilter>,
+    Query(query): Query<CursorQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = query.limit(DEFAULT_LIMIT, MAX_LIMIT);
+    Ok(Json(AuditEvent::list(&context.pool, &context.cursor, &filter, &query, limit).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit/export",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Matching audit events oldest first as CSV", content_type = "text/csv"),
+        (status = 400, description = "Invalid time range"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 422, description = "Too many events match")
+    ),
+    params(AuditFilter)
+)]
+pub(crate) async fn export_audit_events(
+    _admin: AdminUser,
+    State(context): State<AppState>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    let events = AuditEvent::export(&context.pool, &filter).await?;
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"audit_events.csv\""),
+        ],
+        to_csv(&events),
+    ))
+}
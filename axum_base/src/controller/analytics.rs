@@ -0,0 +1,147 @@
+use std::time::Duration;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+use crate::analytics::query::{DEFAULT_DAILY_SPAN, DEFAULT_TOP_LIMIT, DEFAULT_VOTE_SPAN};
+use crate::controller::auth::AuthUser;
+use crate::error::AppError;
+use crate::init::app_state::AppState;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TrendBucket {
+    Minute,
+    #[default]
+    Hour,
+    Day,
+}
+
+impl TrendBucket {
+    fn duration(self) -> Duration {
+        match self {
+            TrendBucket::Minute => Duration::from_secs(60),
+            TrendBucket::Hour => Duration::from_secs(3600),
+            TrendBucket::Day => Duration::from_secs(24 * 3600),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct VoteTrendQuery {
+    url: String,
+    /// RFC 3339，默认为结束时间前 24 小时
+    from: Option<DateTime<Utc>>,
+    /// RFC 3339，默认为当前时间
+    to: Option<DateTime<Utc>>,
+    /// 默认 `hour`
+    bucket: Option<TrendBucket>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/analytics/votes/trend",
+    tag = "analytics",
+    responses(
+        (status = 200, description = "Vote counts per time bucket", body = [crate::analytics::query::VoteTrendPoint]),
+        (status = 400, description = "Invalid time range or bucket"),
+        (status = 401, description = "Unauthorized"),
+        (status = 503, description = "Analytics is not configured")
+    ),
+    params(VoteTrendQuery)
+)]
+pub(crate) async fn vote_trend(
+    _user: AuthUser,
+    State(context): State<AppState>,
+    Query(query): Query<VoteTrendQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let window = context.analytics.window(query.from, query.to, DEFAULT_VOTE_SPAN)?;
+    let bucket = query.bucket.unwrap_or_default().duration();
+    Ok(Json(context.analytics.vote_trend(&query.url, window, bucket).await?))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TopUrlsQuery {
+    /// RFC 3339，默认为结束时间前 24 小时
+    from: Option<DateTime<Utc>>,
+    /// RFC 3339，默认为当前时间
+    to: Option<DateTime<Utc>>,
+    /// 默认 10，最大 100
+    limit: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/analytics/votes/top",
+    tag = "analytics",
+    responses(
+        (status = 200, description = "URLs with the highest score", body = [crate::analytics::query::UrlVotes]),
+        (status = 400, description = "Invalid time range or limit"),
+        (status = 401, description = "Unauthorized"),
+        (status = 503, description = "Analytics is not configured")
+    ),
+    params(TopUrlsQuery)
+)]
+pub(crate) async fn top_urls(
+    _user: AuthUser,
+    State(context): State<AppState>,
+    Query(query): Query<TopUrlsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let window = context.analytics.window(query.from, query.to, DEFAULT_VOTE_SPAN)?;
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_LIMIT);
+    Ok(Json(context.analytics.top_urls(window, limit).await?))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DailyQuery {
+    /// RFC 3339，默认为结束时间前 30 天
+    from: Option<DateTime<Utc>>,
+    /// RFC 3339，默认为当前时间
+    to: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/analytics/users/active",
+    tag = "analytics",
+    responses(
+        (status = 200, description = "Distinct users logged in per day", body = [crate::analytics::query::DailyCount]),
+        (status = 400, description = "Invalid time range"),
+        (status = 401, description = "Unauthorized"),
+        (status = 503, description = "Analytics is not configured")
+    ),
+    params(DailyQuery)
+)]
+pub(crate) async fn daily_active_users(
+    _user: AuthUser,
+    State(context): State<AppState>,
+    Query(query): Query<DailyQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let window = context.analytics.window(query.from, query.to, DEFAULT_DAILY_SPAN)?;
+    Ok(Json(context.analytics.daily_active_users(window).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/analytics/users/signups",
+    tag = "analytics",
+    responses(
+        (status = 200, description = "Registrations per day", body = [crate::analytics::query::DailyCount]),
+        (status = 400, description = "Invalid time range"),
+        (status = 401, description = "Unauthorized"),
+        (status = 503, description = "Analytics is not configured")
+    ),
+    params(DailyQuery)
+)]
+pub(crate) async fn daily_signups(
+    _user: AuthUser,
+    State(context): State<AppState>,
+    Query(query): Query<DailyQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let window = context.analytics.window(query.from, query.to, DEFAULT_DAILY_SPAN)?;
+    Ok(Json(context.analytics.daily_signups(window).await?))
+}
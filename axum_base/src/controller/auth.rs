@@ -0,0 +1,231 @@
+use std::sync::Arc;
+use axum::extract::{FromRef, FromRequestParts, OptionalFromRequestParts, Query};
+use axum::http::{header, request::Parts};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::error::AppError;
+use crate::model::user::validate_token;
+
+/// JWT 签名密钥，由 `AppState::pem` 提供
+#[derive(Clone)]
+pub(crate) struct JwtSecret(pub(crate) Arc<str>);
+
+/// 通过 JWT 校验的当前用户
+///
+/// token 取自 `Authorization: Bearer <token>`，浏览器的 WebSocket 无法设置请求头，
+/// 因此也接受 `?token=<token>` 查询参数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AuthUser {
+    pub(crate) id: i32,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+fn extract_token(parts: &Parts) -> Option<String> {
+    let bearer = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string());
+    bearer.or_else(|| {
+        Query::<TokenQuery>::try_from_uri(&parts.uri)
+            .ok()
+            .and_then(|q| q.0.token)
+    })
+}
+
+fn authenticate(token: &str, secret: &JwtSecret) -> Result<AuthUser, AppError> {
+    let claims = validate_token(token, &secret.0).map_err(|_| AppError::Unauthorized)?;
+    let id = claims.user_id().ok_or(AppError::Unauthorized)?;
+    Ok(AuthUser { id })
+}
+
+/// 请求携带的有效 token 对应的用户，供不需要拒绝请求的场景使用
+pub(crate) fn request_user(parts: &Parts, secret: &JwtSecret) -> Option<AuthUser> {
+    authenticate(&extract_token(parts)?, secret).ok()
+}
+
+impl<S> FromRequestParts<S> for AuthUser
+where
+    JwtSecret: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = extract_token(parts).ok_or(AppError::Unauthorized)?;
+        authenticate(&token, &JwtSecret::from_ref(state))
+    }
+}
+
+/// 未携带 token 时为 `None`，携带了无效 token 仍然拒绝
+impl<S> OptionalFromRequestParts<S> for AuthUser
+where
+    JwtSecret: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        match extract_token(parts) {
+            Some(token) => authenticate(&token, &JwtSecret::from_ref(state)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// 可以调用管理接口的用户 id，由 `ADMIN_USER_IDS` 配置
+#[derive(Clone, Default)]
+pub(crate) struct AdminIds(pub(crate) Arc<[i32]>);
+
+/// 在 [`AdminIds`] 中的当前用户，其他已登录用户返回 403
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AdminUser(pub(crate) AuthUser);
+
+impl<S> FromRequestParts<S> for AdminUser
+where
+    JwtSecret: FromRef<S>,
+    AdminIds: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = extract_token(parts).ok_or(AppError::Unauthorized)?;
+        authenticate_admin(&token, &JwtSecret::from_ref(state), &AdminIds::from_ref(state))
+    }
+}
+
+/// 校验 token 并要求其用户在 [`AdminIds`] 中，gRPC 拦截器也使用
+pub(crate) fn authenticate_admin(token: &str, secret: &JwtSecret, admins: &AdminIds) -> Result<AdminUser, AppError> {
+    let user = authenticate(token, secret)?;
+    if admins.0.contains(&user.id) {
+        Ok(AdminUser(user))
+    } else {
+        Err(AppError::Forbidden)
+    }
+}
+
+/// Prometheus 抓取 `/metrics` 时携带的 token，由 `METRICS_TOKEN` 配置
+#[derive(Clone, Default)]
+pub(crate) struct MetricsToken(pub(crate) Option<Arc<str>>);
+
+/// 携带 [`MetricsToken`] 的抓取方或管理员，未配置 token 时只允许管理员
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MetricsScraper;
+
+impl<S> FromRequestParts<S> for MetricsScraper
+where
+    JwtSecret: FromRef<S>,
+    AdminIds: FromRef<S>,
+    MetricsToken: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let (Some(expected), Some(token)) = (MetricsToken::from_ref(state).0, extract_token(parts)) {
+            // 比较摘要，耗时与 token 的内容无关
+            if Sha256::digest(expected.as_bytes()) == Sha256::digest(token.as_bytes()) {
+                return Ok(MetricsScraper);
+            }
+        }
+        AdminUser::from_request_parts(parts, state).await.map(|_| MetricsScraper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    fn token(sub: &str, secret: &str) -> String {
+        let claims = serde_json::json!({
+            "sub": sub,
+            "exp": (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp(),
+        });
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
+
+    async fn extract(uri: &str, bearer: Option<&str>) -> Result<Option<AuthUser>, AppError> {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(bearer) = bearer {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", bearer));
+        }
+        let (mut parts, _) = builder.body(()).unwrap().into_parts();
+        let secret = JwtSecret("secret".into());
+        <AuthUser as OptionalFromRequestParts<JwtSecret>>::from_request_parts(&mut parts, &secret).await
+    }
+
+    #[tokio::test]
+    async fn test_extract_auth_user() {
+        let valid = token("42", "secret");
+        assert_eq!(extract("/ws", Some(&valid)).await.unwrap(), Some(AuthUser { id: 42 }));
+        let uri = format!("/ws?room=lobby&token={}", valid);
+        assert_eq!(extract(&uri, None).await.unwrap(), Some(AuthUser { id: 42 }));
+        assert_eq!(extract("/ws", None).await.unwrap(), None);
+        assert!(extract("/ws", Some(&token("42", "other"))).await.is_err());
+    }
+
+    #[derive(Clone)]
+    struct AdminState;
+
+    impl FromRef<AdminState> for JwtSecret {
+        fn from_ref(_: &AdminState) -> Self {
+            JwtSecret("secret".into())
+        }
+    }
+
+    impl FromRef<AdminState> for AdminIds {
+        fn from_ref(_: &AdminState) -> Self {
+            AdminIds([0].into())
+        }
+    }
+
+    impl FromRef<AdminState> for MetricsToken {
+        fn from_ref(_: &AdminState) -> Self {
+            MetricsToken(Some("scrape".into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extract_admin_user() {
+        async fn extract_admin(sub: &str) -> Result<AdminUser, AppError> {
+            let (mut parts, _) = Request::builder()
+                .header(header::AUTHORIZATION, format!("Bearer {}", token(sub, "secret")))
+                .body(())
+                .unwrap()
+                .into_parts();
+            AdminUser::from_request_parts(&mut parts, &AdminState).await
+        }
+
+        assert_eq!(extract_admin("0").await.unwrap(), AdminUser(AuthUser { id: 0 }));
+        assert!(matches!(extract_admin("42").await, Err(AppError::Forbidden)));
+    }
+
+    #[tokio::test]
+    async fn test_extract_metrics_scraper() {
+        async fn extract_scraper(bearer: Option<String>) -> Result<MetricsScraper, AppError> {
+            let mut builder = Request::builder().uri("/metrics");
+            if let Some(bearer) = bearer {
+                builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", bearer));
+            }
+            let (mut parts, _) = builder.body(()).unwrap().into_parts();
+            MetricsScraper::from_request_parts(&mut parts, &AdminState).await
+        }
+
+        assert!(extract_scraper(Some("scrape".into())).await.is_ok());
+        assert!(extract_scraper(Some(token("0", "secret"))).await.is_ok());
+        assert!(matches!(extract_scraper(Some(token("42", "secret"))).await, Err(AppError::Forbidden)));
+        assert!(matches!(extract_scraper(Some("wrong".into())).await, Err(AppError::Unauthorized)));
+        assert!(matches!(extract_scraper(None).await, Err(AppError::Unauthorized)));
+    }
+}
@@ -1,23 +1,126 @@
-use axum::extract::WebSocketUpgrade;
-use axum::extract::ws::WebSocket;
-use axum::response::Response;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use tracing::debug;
+use crate::controller::auth::AuthUser;
+use crate::error::AppError;
+use crate::init::app_state::AppState;
+use crate::model::chat::{check_content, check_room, ChatEvent, ChatMessage, SendChatMessage};
+use crate::model::cursor::{CursorPage, CursorQuery};
 
-pub(crate) async fn handler(ws: WebSocketUpgrade) -> Response {
-    ws.on_upgrade(handle_socket)
+/// 把聊天事件推送给房间内的 WebSocket 连接，房间内没有连接时忽略
+fn publish(context: &AppState, event: ChatEvent) {
+    match serde_json::to_string(&event) {
+        Ok(payload) => {
+            if context.ws.broadcast_to(event.room(), payload).is_err() {
+                debug!(room = event.room(), "no websocket subscribers for chat event");
+            }
+        }
+        Err(e) => debug!("failed to serialize chat event: {}", e),
+    }
 }
 
-async fn handle_socket(mut socket: WebSocket) {
-    while let Some(msg) = socket.recv().await {
-        let msg = if let Ok(msg) = msg {
-            msg
-        } else {
-            // client disconnected
-            return;
-        };
+#[utoipa::path(
+    post,
+    path = "/chat/{room}/messages",
+    tag = "chat",
+    request_body = SendChatMessage,
+    responses(
+        (status = 201, description = "Message sent", body = ChatMessage),
+        (status = 400, description = "Invalid content"),
+        (status = 401, description = "Unauthorized")
+    ),
+    params(
+        ("room" = String, Path, description = "Room name")
+    )
+)]
+pub(crate) async fn send_message(
+    user: AuthUser,
+    State(context): State<AppState>,
+    Path(room): Path<String>,
+    Json(payload): Json<SendChatMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    check_room(&room)?;
+    check_content(&payload.content, context.chat_config.max_content_len)?;
+    let message = ChatMessage::insert(&context.pool, &room, user.id, &payload.content).await?;
+    publish(&context, ChatEvent::Created { message: message.clone() });
+    Ok((StatusCode::CREATED, Json(message)))
+}
 
-        if socket.send(msg).await.is_err() {
-            // client disconnected
-            return;
-        }
-    }
+#[utoipa::path(
+    get,
+    path = "/chat/{room}/messages",
+    tag = "chat",
+    responses(
+        (status = 200, description = "Messages newest first", body = CursorPage<ChatMessage>),
+        (status = 400, description = "Invalid cursor"),
+        (status = 401, description = "Unauthorized")
+    ),
+    params(
+        ("room" = String, Path, description = "Room name"),
+        CursorQuery
+    )
+)]
+pub(crate) async fn message_history(
+    _user: AuthUser,
+    State(context): State<AppState>,
+    Path(room): Path<String>,
+    Query(query): Query<CursorQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    check_room(&room)?;
+    let limit = query.limit(50, context.chat_config.max_page_size);
+    let page = ChatMessage::history(&context.pool, &context.cursor, &room, &query, limit).await?;
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/chat/messages/{id}",
+    tag = "chat",
+    request_body = SendChatMessage,
+    responses(
+        (status = 200, description = "Message edited", body = ChatMessage),
+        (status = 403, description = "Not the sender"),
+        (status = 404, description = "Message not found")
+    ),
+    params(
+        ("id" = i64, Path, description = "Message ID")
+    )
+)]
+pub(crate) async fn edit_message(
+    user: AuthUser,
+    State(context): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<SendChatMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    check_content(&payload.content, context.chat_config.max_content_len)?;
+    let message = ChatMessage::update_content(&context.pool, id, user.id, &payload.content).await?;
+    publish(&context, ChatEvent::Edited { message: message.clone() });
+    Ok(Json(message))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/messages/{id}",
+    tag = "chat",
+    responses(
+        (status = 204, description = "Message deleted"),
+        (status = 403, description = "Not the sender"),
+        (status = 404, description = "Message not found")
+    ),
+    params(
+        ("id" = i64, Path, description = "Message ID")
+    )
+)]
+pub(crate) async fn delete_message(
+    user: AuthUser,
+    State(context): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let room = ChatMessage::delete(&context.pool, id, user.id).await?;
+    publish(&context, ChatEvent::Deleted { id, room });
+    Ok(StatusCode::NO_CONTENT)
 }
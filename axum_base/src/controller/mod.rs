@@ -1,2 +1,6 @@
 pub mod user_controller;
-mod chat;
\ No newline at end of file
+pub(crate) mod auth;
+pub(crate) mod chat;
+pub(crate) mod analytics;
+pub(crate) mod votes;
+pub(crate) mod admin;
@@ -1,15 +1,25 @@
 use axum::{
-    extract::{State, Path},
+    extract::{State, Path, Query},
+    http::StatusCode,
     response::IntoResponse,
     Json,
 };
+use crate::audit::{diff, Audit, AuditAction};
+use crate::controller::auth::{AdminUser, AuthUser};
 use crate::error::AppError;
 use crate::init::app_state::AppState;
-use utoipa::OpenApi;
+use crate::model::cursor::{CursorPage, CursorQuery, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::model::search::UserSearchHit;
+use chrono::{DateTime, Utc};
+use sqlx_paginated::{PaginatedResponse, QueryParams, QueryParamsBuilder, QuerySortDirection};
+use utoipa::{IntoParams, OpenApi};
 use utoipa::ToSchema;
-use crate::model::user::{BaseUserInfo, CreateUser, LoginUser};
+use crate::model::user::{
+    BaseUserInfo, CreateUser, LoginUser, UserSummary, USER_PREFIX_FILTERS, USER_SORT_COLUMNS,
+};
 use validator::Validate;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 
 #[derive(OpenApi)]
@@ -19,7 +29,24 @@ use serde::{Deserialize, Serialize};
         create_user,
         login_user,
         verify_user,
-        page_user
+        page_user,
+        list_users,
+        search_users,
+        delete_user,
+        crate::controller::chat::send_message,
+        crate::controller::chat::message_history,
+        crate::controller::chat::edit_message,
+        crate::controller::chat::delete_message,
+        crate::controller::analytics::vote_trend,
+        crate::controller::analytics::top_urls,
+        crate::controller::analytics::daily_active_users,
+        crate::controller::analytics::daily_signups,
+        crate::controller::votes::top_voted,
+        crate::controller::votes::list_votes,
+        crate::controller::admin::get_log_level,
+        crate::controller::admin::set_log_level,
+        crate::controller::admin::list_audit_events,
+        crate::controller::admin::export_audit_events
     ),
     components(
         schemas(
@@ -27,11 +54,27 @@ use serde::{Deserialize, Serialize};
             crate::model::user::BaseUserInfo, 
             crate::model::user::LoginUser,
             crate::model::user::LoginUser,
-            crate::controller::user_controller::PageUserQuery
+            crate::model::user::UserSummary,
+            crate::model::search::UserSearchHit,
+            crate::controller::user_controller::UserPage,
+            crate::model::chat::ChatMessage,
+            crate::model::chat::SendChatMessage,
+            crate::controller::analytics::TrendBucket,
+            crate::analytics::query::VoteTrendPoint,
+            crate::analytics::query::UrlVotes,
+            crate::analytics::query::DailyCount,
+            crate::leaderboard::LeaderboardWindow,
+            crate::leaderboard::LeaderboardEntry,
+            crate::model::vote::Vote,
+            crate::controller::admin::LogLevel,
+            crate::audit::AuditEvent
         )
     ),
     tags(
-        (name = "users", description = "User management endpoints.")
+        (name = "users", description = "User management endpoints."),
+        (name = "chat", description = "Chat rooms and message history."),
+        (name = "analytics", description = "Vote trends and user activity from ClickHouse."),
+        (name = "votes", description = "Votes and the most voted URLs from the Redis leaderboard.")
     )
 )]
 pub struct ApiDoc;
@@ -51,8 +94,7 @@ pub(crate) async fn find_user_by_id(
     State(context): State<AppState>,
     Path(params): Path<i32>,
 ) -> Result<impl IntoResponse, AppError> {
-    let pg_pool = &context.pool;
-    let result = BaseUserInfo::select_user(pg_pool, params).await?;
+    let result = BaseUserInfo::find_user(&context.pool, &context.cache, params).await?;
     Ok(Json(result))
 }
 
@@ -62,20 +104,47 @@ pub(crate) async fn find_user_by_id(
     request_body = CreateUser,
     responses(
         (status = 201, description = "User created", body = String),
-        (status = 400, description = "Invalid input")
+        (status = 400, description = "Invalid input"),
+        (status = 409, description = "A request with the same Idempotency-Key is in progress"),
+        (status = 422, description = "Idempotency-Key was used with a different request body")
+    ),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key replay the first response")
     )
 )]
 pub(crate) async fn create_user(
     State(context): State<AppState>,
+    audit: Audit,
     Json(user): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     user.validate()?;
     let pg_pool = &context.pool;
     let pem = &context.pem;
-    let token = CreateUser::create_user(pg_pool,pem, &user).await?;
+    let (user_id, token) = CreateUser::create_user(pg_pool, pem, &user, &context.user_events).await?;
+    let changes = diff([
+        ("username", Value::Null, json!(user.username)),
+        ("email", Value::Null, json!(user.email)),
+    ]);
+    audit.actor(user_id).record(AuditAction::UserCreated, Some(format!("user:{}", user_id)), changes).await;
     Ok(Json(token))
 }
 
+/// 登录成功时以该用户作为操作者，失败时只记录用户名
+async fn login(context: &AppState, audit: Audit, user: &LoginUser) -> Result<String, AppError> {
+    match LoginUser::verify_user(&context.pool, &context.pem, user, &context.user_events).await {
+        Ok((user_id, token)) => {
+            audit.actor(user_id).record(AuditAction::Login, Some(format!("user:{}", user_id)), json!({})).await;
+            Ok(token)
+        }
+        Err(AppError::InvalidCredentials) => {
+            let target = format!("username:{}", user.username);
+            audit.record(AuditAction::LoginFailed, Some(target), json!({})).await;
+            Err(AppError::InvalidCredentials)
+        }
+        Err(e) => Err(e),
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/user/login",
@@ -87,12 +156,10 @@ pub(crate) async fn create_user(
 )]
 pub(crate) async fn login_user(
     State(context): State<AppState>,
+    audit: Audit,
     Json(user): Json<LoginUser>,
 ) -> Result<impl IntoResponse, AppError> {
-    let pg_pool = &context.pool;
-    let pem = &context.pem;
-    let token = LoginUser::verify_user(pg_pool, pem, &user).await?;
-    Ok(Json(token))
+    Ok(Json(login(&context, audit, &user).await?))
 }
 
 #[utoipa::path(
@@ -106,34 +173,217 @@ pub(crate) async fn login_user(
 )]
 pub(crate) async fn verify_user(
     State(context): State<AppState>,
+    audit: Audit,
     Json(user): Json<LoginUser>,
 ) -> Result<impl IntoResponse, AppError> {
-    let pg_pool = &context.pool;
-    let pem = &context.pem;
-    let token = LoginUser::verify_user(pg_pool, pem, &user).await?;
-    Ok(Json(token))
+    Ok(Json(login(&context, audit, &user).await?))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Default, Deserialize, IntoParams, Validate)]
+#[into_params(parameter_in = Query)]
 pub struct PageUserQuery {
-    page: i32,
-    page_size: i32,
+    /// 从 1 开始，默认 1
+    #[validate(range(min = 1))]
+    page: Option<i64>,
+    /// 默认 10，超出 10 到 50 时取最近的边界
+    page_size: Option<i64>,
+    /// `列:方向`，列为 id、username、email、created_at，方向为 asc 或 desc，默认 `created_at:desc`
+    sort: Option<String>,
+    /// 用户名前缀，不区分大小写
+    username: Option<String>,
+    /// 邮箱前缀，不区分大小写
+    email: Option<String>,
+    /// 在用户名和邮箱中查找包含该字符串的用户，不区分大小写
+    q: Option<String>,
+    /// RFC 3339，包含该时间
+    created_after: Option<DateTime<Utc>>,
+    /// RFC 3339，包含该时间
+    created_before: Option<DateTime<Utc>>,
+}
+
+fn parse_sort(sort: &str) -> Result<(&str, QuerySortDirection), AppError> {
+    let (column, direction) = sort.split_once(':').unwrap_or((sort, "desc"));
+    if !USER_SORT_COLUMNS.contains(&column) {
+        return Err(AppError::BadRequest(format!("cannot sort by {}", column)));
+    }
+    let direction = match direction {
+        "asc" => QuerySortDirection::Ascending,
+        "desc" => QuerySortDirection::Descending,
+        other => return Err(AppError::BadRequest(format!("invalid sort direction: {}", other))),
+    };
+    Ok((column, direction))
+}
+
+impl PageUserQuery {
+    fn params(&self) -> Result<QueryParams<'static, UserSummary>, AppError> {
+        if let (Some(after), Some(before)) = (self.created_after, self.created_before) {
+            if after > before {
+                return Err(AppError::BadRequest("created_after must not be later than created_before".into()));
+            }
+        }
+        let (column, direction) = parse_sort(self.sort.as_deref().unwrap_or("created_at:desc"))?;
+        let mut builder = QueryParamsBuilder::<UserSummary>::new()
+            .with_pagination(self.page.unwrap_or(1), self.page_size.unwrap_or(10))
+            .with_sort(column, direction)
+            .with_date_range(self.created_after, self.created_before, Some("created_at"));
+        if let Some(q) = self.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            builder = builder.with_search(q, USER_PREFIX_FILTERS.to_vec());
+        }
+        for (column, prefix) in USER_PREFIX_FILTERS.into_iter().zip([&self.username, &self.email]) {
+            if let Some(prefix) = prefix.as_deref().filter(|p| !p.is_empty()) {
+                builder = builder.with_filter(column, Some(prefix));
+            }
+        }
+        Ok(builder.build())
+    }
+}
+
+/// 一页用户
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserPage {
+    records: Vec<UserSummary>,
+    page: i64,
+    page_size: i64,
+    total: i64,
+    total_pages: i64,
+}
+
+impl From<PaginatedResponse<UserSummary>> for UserPage {
+    fn from(response: PaginatedResponse<UserSummary>) -> Self {
+        let pagination = response.pagination.unwrap_or_default();
+        Self {
+            records: response.records,
+            page: pagination.page,
+            page_size: pagination.page_size,
+            total: response.total.unwrap_or_default(),
+            total_pages: response.total_pages.unwrap_or_default(),
+        }
+    }
 }
 
 #[utoipa::path(
     get,
     path = "/user/page",
     responses(
-        (status = 200, description = "Page found", body = BaseUserInfo)
-    )
+        (status = 200, description = "Page found", body = UserPage),
+        (status = 400, description = "Invalid query parameters")
+    ),
+    params(PageUserQuery)
 )]
 pub(crate) async fn page_user(
     State(context): State<AppState>,
-    Json(query): Json<PageUserQuery>,
+    Query(query): Query<PageUserQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let pg_pool = &context.pool;
-    let result = BaseUserInfo::page_user(pg_pool, query.page, query.page_size).await?;
-    Ok(Json(result))
+    query.validate()?;
+    let result = UserSummary::page_user(&context.pool, query.params()?).await?;
+    Ok(Json(UserPage::from(result)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/list",
+    responses(
+        (status = 200, description = "Users newest first", body = CursorPage<UserSummary>),
+        (status = 400, description = "Invalid cursor")
+    ),
+    params(CursorQuery)
+)]
+pub(crate) async fn list_users(
+    State(context): State<AppState>,
+    Query(query): Query<CursorQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = query.limit(DEFAULT_LIMIT, MAX_LIMIT);
+    Ok(Json(UserSummary::list(&context.pool, &context.cursor, &query, limit).await?))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchUserQuery {
+    /// 用户名或邮箱的片段，多个词时需要全部按前缀匹配
+    q: String,
+    /// 默认 20，最大 50
+    limit: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/search",
+    responses(
+        (status = 200, description = "Matching users, most relevant first", body = [UserSearchHit]),
+        (status = 400, description = "Empty or too long query"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    ),
+    params(SearchUserQuery)
+)]
+pub(crate) async fn search_users(
+    _admin: AdminUser,
+    State(context): State<AppState>,
+    Query(query): Query<SearchUserQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(UserSearchHit::search(&context.pool, &query.q, query.limit).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/{id}",
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Can only delete yourself"),
+        (status = 404, description = "User not found")
+    ),
+    params(
+        ("id" = i32, Path, description = "User ID")
+    )
+)]
+pub(crate) async fn delete_user(
+    user: AuthUser,
+    audit: Audit,
+    State(context): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    if user.id != id {
+        return Err(AppError::Forbidden);
+    }
+    let deleted = BaseUserInfo::delete_user(&context.pool, &context.cache, id, &context.user_events).await?;
+    let changes = diff([
+        ("username", json!(deleted.username), Value::Null),
+        ("email", json!(deleted.email), Value::Null),
+    ]);
+    audit.record(AuditAction::UserDeleted, Some(format!("user:{}", id)), changes).await;
+    Ok(StatusCode::NO_CONTENT)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn test_page_user_query() {
+        assert_eq!(parse_sort("username:asc").unwrap(), ("username", QuerySortDirection::Ascending));
+        assert_eq!(parse_sort("id").unwrap(), ("id", QuerySortDirection::Descending));
+        assert!(parse_sort("password_hash").is_err());
+        assert!(parse_sort("id:up").is_err());
+
+        let query = PageUserQuery {
+            page_size: Some(500),
+            username: Some("al".into()),
+            email: Some(String::new()),
+            ..PageUserQuery::default()
+        };
+        let params = query.params().unwrap();
+        assert_eq!((params.pagination.page, params.pagination.page_size), (1, 50));
+        assert_eq!(params.sort.sort_column, "created_at");
+        assert_eq!(params.filters.get("username"), Some(&Some("al".to_string())));
+        assert!(!params.filters.contains_key("email"));
+
+        let query = PageUserQuery {
+            created_after: "2025-02-01T00:00:00Z".parse().ok(),
+            created_before: "2025-01-01T00:00:00Z".parse().ok(),
+            ..PageUserQuery::default()
+        };
+        assert!(query.params().is_err());
+        assert!(PageUserQuery { page: Some(0), ..PageUserQuery::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::error::AppError;
+use crate::init::app_state::AppState;
+use crate::model::cursor::{CursorPage, CursorQuery, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::model::vote::Vote;
+use crate::leaderboard::{Leaderboard, LeaderboardWindow, DEFAULT_TOP_LIMIT};
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TopVotedQuery {
+    /// 默认 `all`，滚动窗口的精度为一小时
+    window: Option<LeaderboardWindow>,
+    /// 默认 10，最大 100
+    limit: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/votes/top",
+    tag = "votes",
+    responses(
+        (status = 200, description = "URLs with the highest score", body = [crate::leaderboard::LeaderboardEntry]),
+        (status = 400, description = "Invalid window or limit")
+    ),
+    params(TopVotedQuery)
+)]
+pub(crate) async fn top_voted(
+    State(leaderboard): State<Arc<Leaderboard>>,
+    Query(query): Query<TopVotedQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let window = query.window.unwrap_or_default();
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_LIMIT);
+    Ok(Json(leaderboard.top(window, limit).await?))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListVotesQuery {
+    url: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/votes",
+    tag = "votes",
+    responses(
+        (status = 200, description = "Votes for the URL newest first", body = CursorPage<Vote>),
+        (status = 400, description = "Invalid cursor")
+    ),
+    params(ListVotesQuery, CursorQuery)
+)]
+pub(crate) async fn list_votes(
+    State(context): State<AppState>,
+    Query(filter): Query<ListVotesQuery>,
+    Query(query): Query<CursorQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = query.limit(DEFAULT_LIMIT, MAX_LIMIT);
+    Ok(Json(Vote::list(&context.pool, &context.cursor, &filter.url, &query, limit).await?))
+}
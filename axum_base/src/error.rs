@@ -1,7 +1,10 @@
 use axum::{
     http::{StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde::Serialize;
+use crate::request_id::RequestId;
 use validator::ValidationErrors;
 
 #[derive(Debug, thiserror::Error)]
@@ -10,25 +13,68 @@ pub enum AppError {
     Database(#[from] sqlx::Error),
     #[error("Redis error: {0}")]
     Redis(#[from] redis::RedisError),
+    #[error("Kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+    #[error("Event bus error: {0}")]
+    EventBus(#[from] crate::kafka::bus::BusError),
+    #[error("ClickHouse error: {0}")]
+    ClickHouse(#[from] clickhouse::error::Error),
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
     #[error("JWT error: {0}")]
     Jwt(#[from] jsonwebtoken::errors::Error),
     #[error("Validation error: {0}")]
     Validation(#[from] ValidationErrors),
+    #[error("Bad request: {0}")]
+    BadRequest(String),
     #[error("Invalid credentials")]
     InvalidCredentials,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Payload too large")]
+    PayloadTooLarge,
+    #[error("Unprocessable: {0}")]
+    Unprocessable(String),
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+}
+
+/// 错误响应体，带上请求标识便于对照日志
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        match self {
-            Self::Database(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-            Self::Redis(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-            Self::Json(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
-            Self::Jwt(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-            Self::Validation(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
-            Self::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response(),
-        }
+        let (status, error) = match self {
+            Self::Database(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            Self::Redis(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            Self::Kafka(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            Self::EventBus(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            Self::ClickHouse(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            Self::Json(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Self::Jwt(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            Self::Validation(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Self::BadRequest(e) => (StatusCode::BAD_REQUEST, e),
+            Self::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Invalid credentials".into()),
+            Self::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".into()),
+            Self::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".into()),
+            Self::NotFound(e) => (StatusCode::NOT_FOUND, e),
+            Self::Conflict(e) => (StatusCode::CONFLICT, e),
+            Self::PayloadTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large".into()),
+            Self::Unprocessable(e) => (StatusCode::UNPROCESSABLE_ENTITY, e),
+            Self::Unavailable(e) => (StatusCode::SERVICE_UNAVAILABLE, e),
+        };
+        let request_id = RequestId::current().map(|id| id.to_string());
+        (status, Json(ErrorBody { error, request_id })).into_response()
     }
 }
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use prost::Message;
+use tracing::warn;
+use crate::config::UserEventTopics;
+use crate::constant::USER_EVENT_SCHEMA_VERSION;
+use crate::kafka::bus::EventBus;
+use crate::kafka::producer::{DeliveryReport, ProduceError, ProducerRecord};
+use crate::protos::user_events::{user_event::Event, UserEvent};
+use crate::protos::voting::VoteCast;
+
+impl UserEvent {
+    /// 以当前时间和当前结构版本包装事件
+    pub fn new(event: Event) -> Self {
+        Self {
+            schema_version: USER_EVENT_SCHEMA_VERSION,
+            occurred_at: chrono::Utc::now().timestamp_millis(),
+            event: Some(event),
+        }
+    }
+}
+
+pub(crate) fn event_type(event: &Event) -> &'static str {
+    match event {
+        Event::Registered(_) => "UserRegistered",
+        Event::LoggedIn(_) => "UserLoggedIn",
+        Event::LoginFailed(_) => "LoginFailed",
+        Event::Deleted(_) => "UserDeleted",
+    }
+}
+
+/// 分区 key 为用户 id，保证同一用户的事件有序；登录失败且用户不存在时退化为用户名
+fn partition_key(event: &Event) -> String {
+    match event {
+        Event::Registered(e) => e.user_id.to_string(),
+        Event::LoggedIn(e) => e.user_id.to_string(),
+        Event::LoginFailed(e) => e
+            .user_id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| e.username.clone()),
+        Event::Deleted(e) => e.user_id.to_string(),
+    }
+}
+
+/// 把用户领域事件编码为 protobuf 并发布到对应 topic
+#[derive(Clone)]
+pub struct UserEventPublisher {
+    bus: Arc<dyn EventBus>,
+    topics: UserEventTopics,
+}
+
+impl UserEventPublisher {
+    pub fn new(bus: Arc<dyn EventBus>, topics: UserEventTopics) -> Self {
+        Self { bus, topics }
+    }
+
+    fn topic(&self, event: &Event) -> &str {
+        match event {
+            Event::Registered(_) => &self.topics.registered,
+            Event::LoggedIn(_) => &self.topics.logged_in,
+            Event::LoginFailed(_) => &self.topics.login_failed,
+            Event::Deleted(_) => &self.topics.deleted,
+        }
+    }
+
+    /// 构造待发送的消息，事件类型和结构版本同时写入消息头，消费方无需解码即可路由
+    pub fn record(&self, event: Event) -> ProducerRecord {
+        let topic = self.topic(&event).to_string();
+        let key = partition_key(&event);
+        let event_type = event_type(&event);
+        let payload = UserEvent::new(event).encode_to_vec();
+        ProducerRecord::new(topic, payload)
+            .with_key(key)
+            .with_header("event-type", event_type)
+            .with_header("schema-version", USER_EVENT_SCHEMA_VERSION.to_string())
+            .with_header("content-type", "application/x-protobuf")
+    }
+
+    /// 入队后立即返回，不等待确认，失败只记录日志，不影响业务请求。
+    ///
+    /// 发送队列已满时直接丢弃事件，避免请求堆积等待。
+    pub fn publish(&self, event: Event) {
+        let record = self.record(event);
+        let topic = record.topic.clone();
+        let delivery_topic = topic.clone();
+        let enqueued = self.bus.publish_with_callback(
+            record,
+            Box::new(move |result: Result<DeliveryReport, ProduceError>| {
+                if let Err(e) = result {
+                    warn!(topic = delivery_topic, "failed to deliver user event: {}", e);
+                }
+            }),
+        );
+        if let Err(e) = enqueued {
+            warn!(topic, "failed to publish user event: {}", e);
+        }
+    }
+}
+
+/// 构造投票事件的消息，key 为 URL，保证同一 URL 的投票有序
+pub fn vote_record(topic: &str, event: &VoteCast) -> ProducerRecord {
+    ProducerRecord::new(topic, event.encode_to_vec())
+        .with_key(event.url.as_str())
+        .with_header("event-type", "VoteCast")
+        .with_header("content-type", "application/x-protobuf")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::kafka::memory::MemoryBus;
+    use crate::protos::user_events::{LoginFailed, UserLoggedIn, UserRegistered};
+
+    pub(crate) fn memory_publisher(bus: &MemoryBus) -> UserEventPublisher {
+        UserEventPublisher::new(Arc::new(bus.clone()), UserEventTopics::default())
+    }
+
+    fn header<'a>(record: &'a ProducerRecord, key: &str) -> &'a [u8] {
+        &record.headers.iter().find(|(k, _)| k == key).unwrap().1
+    }
+
+    #[tokio::test]
+    async fn test_user_event_record() {
+        let publisher = memory_publisher(&MemoryBus::new(1));
+
+        let record = publisher.record(Event::Registered(UserRegistered {
+            user_id: 7,
+            username: "alice".into(),
+            email: "alice@example.com".into(),
+        }));
+        assert_eq!(record.topic, "user.registered");
+        assert_eq!(record.key.as_deref(), Some(b"7".as_slice()));
+        assert_eq!(header(&record, "event-type"), b"UserRegistered");
+        assert_eq!(header(&record, "schema-version"), b"1");
+        let decoded = UserEvent::decode(record.payload.as_slice()).unwrap();
+        assert_eq!(decoded.schema_version, USER_EVENT_SCHEMA_VERSION);
+        assert!(matches!(decoded.event, Some(Event::Registered(e)) if e.username == "alice"));
+
+        let record = publisher.record(Event::LoginFailed(LoginFailed {
+            username: "nobody".into(),
+            reason: "unknown_user".into(),
+            user_id: None,
+        }));
+        assert_eq!(record.topic, "user.login_failed");
+        assert_eq!(record.key.as_deref(), Some(b"nobody".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_publish_in_background() {
+        let bus = MemoryBus::new(1);
+        memory_publisher(&bus).publish(Event::LoggedIn(UserLoggedIn {
+            user_id: 7,
+            username: "alice".into(),
+        }));
+        tokio::task::yield_now().await;
+
+        let messages = bus.messages("user.logged_in");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].header("event-type"), Some(b"UserLoggedIn".as_slice()));
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tonic::{Request, Response, Status};
+use crate::analytics::query::{
+    AnalyticsQueries, Window, DEFAULT_BUCKET, DEFAULT_DAILY_SPAN, DEFAULT_TOP_LIMIT, DEFAULT_VOTE_SPAN,
+};
+use crate::error::AppError;
+use crate::protos::analytics::analytics_server::Analytics;
+use crate::protos::analytics::{
+    DailyCount, DailyCountsResponse, DailyRequest, TopUrlsRequest, TopUrlsResponse, UrlVotes,
+    VoteTrendPoint, VoteTrendRequest, VoteTrendResponse,
+};
+
+/// 与 REST 接口共用 [`AnalyticsQueries`] 及其缓存
+pub struct AnalyticsService {
+    queries: Arc<AnalyticsQueries>,
+}
+
+impl AnalyticsService {
+    pub fn new(queries: Arc<AnalyticsQueries>) -> Self {
+        Self { queries }
+    }
+
+    fn window(&self, from: Option<i64>, to: Option<i64>, span: Duration) -> Result<Window, AppError> {
+        let from = from.map(millis).transpose()?;
+        let to = to.map(millis).transpose()?;
+        self.queries.window(from, to, span)
+    }
+}
+
+fn millis(ms: i64) -> Result<DateTime<Utc>, AppError> {
+    DateTime::from_timestamp_millis(ms).ok_or_else(|| AppError::BadRequest("timestamp out of range".into()))
+}
+
+fn daily_counts(days: Vec<crate::analytics::query::DailyCount>) -> DailyCountsResponse {
+    DailyCountsResponse {
+        days: days
+            .into_iter()
+            .map(|d| DailyCount { day: d.day.to_string(), count: d.count })
+            .collect(),
+    }
+}
+
+#[tonic::async_trait]
+impl Analytics for AnalyticsService {
+    async fn vote_trend(&self, request: Request<VoteTrendRequest>) -> Result<Response<VoteTrendResponse>, Status> {
+        let req = request.into_inner();
+        let window = self.window(req.from, req.to, DEFAULT_VOTE_SPAN)?;
+        let bucket = req.bucket_secs.map_or(DEFAULT_BUCKET, |secs| Duration::from_secs(secs.into()));
+        let points = self.queries.vote_trend(&req.url, window, bucket).await?;
+        Ok(Response::new(VoteTrendResponse {
+            points: points
+                .into_iter()
+                .map(|p| VoteTrendPoint {
+                    bucket_start: p.bucket_start.timestamp_millis(),
+                    up_votes: p.up_votes,
+                    down_votes: p.down_votes,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn top_urls(&self, request: Request<TopUrlsRequest>) -> Result<Response<TopUrlsResponse>, Status> {
+        let req = request.into_inner();
+        let window = self.window(req.from, req.to, DEFAULT_VOTE_SPAN)?;
+        let urls = self.queries.top_urls(window, req.limit.unwrap_or(DEFAULT_TOP_LIMIT)).await?;
+        Ok(Response::new(TopUrlsResponse {
+            urls: urls
+                .into_iter()
+                .map(|u| UrlVotes {
+                    url: u.url,
+                    up_votes: u.up_votes,
+                    down_votes: u.down_votes,
+                    score: u.score,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn daily_active_users(&self, request: Request<DailyRequest>) -> Result<Response<DailyCountsResponse>, Status> {
+        let req = request.into_inner();
+        let window = self.window(req.from, req.to, DEFAULT_DAILY_SPAN)?;
+        Ok(Response::new(daily_counts(self.queries.daily_active_users(window).await?)))
+    }
+
+    async fn daily_signups(&self, request: Request<DailyRequest>) -> Result<Response<DailyCountsResponse>, Status> {
+        let req = request.into_inner();
+        let window = self.window(req.from, req.to, DEFAULT_DAILY_SPAN)?;
+        Ok(Response::new(daily_counts(self.queries.daily_signups(window).await?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clickhouse::test::{handlers, Mock};
+    use crate::config::AnalyticsConfig;
+
+    #[derive(serde::Serialize)]
+    struct DailyRow {
+        day: String,
+        count: u64,
+    }
+
+    #[tokio::test]
+    async fn test_daily_active_users() {
+        let mock = Mock::new();
+        let service = AnalyticsService::new(Arc::new(AnalyticsQueries::new(AnalyticsConfig {
+            clickhouse_url: Some(mock.url().to_string()),
+            ..AnalyticsConfig::default()
+        })));
+
+        mock.add(handlers::provide(vec![
+            DailyRow { day: "2025-06-01".into(), count: 12 },
+            DailyRow { day: "2025-06-02".into(), count: 9 },
+        ]));
+        let request = Request::new(DailyRequest { from: None, to: None });
+        let days = service.daily_active_users(request).await.unwrap().into_inner().days;
+        assert_eq!(days[1], DailyCount { day: "2025-06-02".into(), count: 9 });
+
+        let request = Request::new(DailyRequest { from: Some(2_000), to: Some(1_000) });
+        let status = service.daily_signups(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -1,10 +1,92 @@
+pub mod analytics;
+pub mod users;
+pub mod voting;
+
+use std::net::SocketAddr;
+use tonic::service::Interceptor;
+use tonic::transport::Server;
+use tonic::Status;
+use tracing::{error, info};
+use crate::controller::auth::{authenticate_admin, AdminIds, JwtSecret};
+use crate::error::AppError;
+use crate::metrics::GrpcMetricsLayer;
+use crate::request_id::{grpc_interceptor, GrpcRequestIdLayer};
 //引用 proto对象
 use crate::protos::voting::voting_client::VotingClient;
+use crate::protos::analytics::analytics_server::AnalyticsServer;
+use crate::protos::users::users_server::UsersServer;
+use crate::protos::voting::voting_server::VotingServer;
 use crate::protos::voting::{VotingRequest, GetVotesRequest};
+use analytics::AnalyticsService;
+use users::UserService;
+use voting::VotingService;
+
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        match error {
+            AppError::NotFound(e) => Status::not_found(e),
+            AppError::Validation(e) => Status::invalid_argument(e.to_string()),
+            AppError::BadRequest(e) => Status::invalid_argument(e),
+            AppError::Unavailable(e) => Status::unavailable(e),
+            AppError::Unauthorized => Status::unauthenticated("missing or invalid token"),
+            AppError::Forbidden => Status::permission_denied("admin only"),
+            other => Status::internal(other.to_string()),
+        }
+    }
+}
+
+/// 只允许管理员调用，token 取自 `authorization: Bearer <token>` metadata
+#[derive(Clone)]
+pub(crate) struct AdminInterceptor {
+    secret: JwtSecret,
+    admins: AdminIds,
+}
+
+impl AdminInterceptor {
+    pub(crate) fn new(secret: JwtSecret, admins: AdminIds) -> Self {
+        Self { secret, admins }
+    }
+}
+
+impl Interceptor for AdminInterceptor {
+    fn call(&mut self, request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or(AppError::Unauthorized)?;
+        authenticate_admin(token.trim(), &self.secret, &self.admins)?;
+        Ok(request)
+    }
+}
+
+/// 在 `addr` 上提供 gRPC 服务，直到进程退出
+pub async fn serve(
+    addr: SocketAddr,
+    voting: VotingService,
+    analytics: AnalyticsService,
+    users: UserService,
+    admin: AdminInterceptor,
+) {
+    let result = Server::builder()
+        .layer(GrpcRequestIdLayer)
+        .layer(GrpcMetricsLayer)
+        .add_service(VotingServer::new(voting))
+        .add_service(AnalyticsServer::new(analytics))
+        // 用户搜索会返回邮箱，只给管理员使用
+        .add_service(UsersServer::with_interceptor(users, admin))
+        .serve(addr)
+        .await;
+    if let Err(e) = result {
+        error!("gRPC server on {} stopped: {}", addr, e);
+    }
+}
 
 pub async fn _example() -> Result<(), Box<dyn std::error::Error>> {
-    // 创建客户端
-    let mut client = VotingClient::connect("http://[::1]:50051").await?;
+    // 创建客户端，在请求处理中调用时会转发当前的请求标识
+    let channel = tonic::transport::Channel::from_static("http://[::1]:50051").connect().await?;
+    let mut client = VotingClient::with_interceptor(channel, grpc_interceptor);
 
     // 发送投票
     let request = tonic::Request::new(VotingRequest {
@@ -13,7 +95,7 @@ pub async fn _example() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     let response = client.vote(request).await?;
-    println!("Vote Response: {:?}", response);
+    info!(?response, "vote response");
 
     // 获取投票结果
     let request = tonic::Request::new(GetVotesRequest {
@@ -21,7 +103,7 @@ pub async fn _example() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     let response = client.get_votes(request).await?;
-    println!("Get Votes Response: {:?}", response);
+    info!(?response, "get votes response");
 
     Ok(())
 }
@@ -30,10 +112,12 @@ pub async fn _example() -> Result<(), Box<dyn std::error::Error>> {
 mod tests {
     use super::*;
     use crate::protos::voting::voting_server::{Voting, VotingServer};
-    use crate::protos::voting::{VotingResponse, GetVotesResponse};
+    use crate::protos::voting::{VotingResponse, GetVotesResponse, TopVotedRequest, TopVotedResponse};
     use std::collections::HashMap;
     use std::sync::Mutex;
     use tonic::{transport::Server, Request, Response, Status};
+    use crate::constant::REQUEST_ID_HEADER;
+    use crate::request_id::RequestId;
 
     #[derive(Debug, Default)]
     struct MockVotingService {
@@ -74,6 +158,13 @@ mod tests {
                 down_votes: *down_votes,
             }))
         }
+
+        async fn top_voted(
+            &self,
+            _request: Request<TopVotedRequest>,
+        ) -> Result<Response<TopVotedResponse>, Status> {
+            Err(Status::unimplemented("top_voted"))
+        }
     }
 
     #[tokio::test]
@@ -117,4 +208,77 @@ mod tests {
         server.abort();
         Ok(())
     }
+
+    /// 把服务端看到的请求标识作为确认信息返回
+    struct EchoRequestId;
+
+    #[tonic::async_trait]
+    impl Voting for EchoRequestId {
+        async fn vote(&self, _request: Request<VotingRequest>) -> Result<Response<VotingResponse>, Status> {
+            let confirmation = RequestId::current().map(|id| id.to_string()).unwrap_or_default();
+            Ok(Response::new(VotingResponse { confirmation }))
+        }
+
+        async fn get_votes(&self, _request: Request<GetVotesRequest>) -> Result<Response<GetVotesResponse>, Status> {
+            Err(Status::unimplemented("get_votes"))
+        }
+
+        async fn top_voted(&self, _request: Request<TopVotedRequest>) -> Result<Response<TopVotedResponse>, Status> {
+            Err(Status::unimplemented("top_voted"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id_propagation() -> Result<(), Box<dyn std::error::Error>> {
+        let addr = "127.0.0.1:50053".parse()?;
+        let server = tokio::spawn(async move {
+            Server::builder()
+                .layer(GrpcRequestIdLayer)
+                .add_service(VotingServer::new(EchoRequestId))
+                .serve(addr)
+                .await
+                .unwrap();
+        });
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        let channel = tonic::transport::Channel::from_static("http://127.0.0.1:50053").connect().await?;
+        let mut client = VotingClient::with_interceptor(channel, grpc_interceptor);
+        let request = || tonic::Request::new(VotingRequest { url: "https://test.com".into(), vote: 0 });
+
+        let id = RequestId::parse(b"abc-123").unwrap();
+        let response = id.scope(client.vote(request())).await?;
+        assert_eq!(response.metadata().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+        assert_eq!(response.into_inner().confirmation, "abc-123");
+        // 调用方没有标识时由服务端生成
+        let response = client.vote(request()).await?;
+        let generated = response.metadata().get(REQUEST_ID_HEADER).unwrap().to_str()?.to_string();
+        assert_eq!(response.into_inner().confirmation, generated);
+        assert_eq!(generated.len(), 32);
+
+        server.abort();
+        Ok(())
+    }
+
+    #[test]
+    fn test_admin_interceptor() {
+        use jsonwebtoken::{encode, EncodingKey, Header};
+
+        let mut interceptor = AdminInterceptor::new(JwtSecret("secret".into()), AdminIds([0].into()));
+        let mut call = |sub: Option<&str>| {
+            let mut request = tonic::Request::new(());
+            if let Some(sub) = sub {
+                let claims = serde_json::json!({
+                    "sub": sub,
+                    "exp": (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp(),
+                });
+                let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+                request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+            }
+            interceptor.call(request).map(|_| ()).map_err(|status| status.code())
+        };
+
+        assert_eq!(call(Some("0")), Ok(()));
+        assert_eq!(call(Some("42")), Err(tonic::Code::PermissionDenied));
+        assert_eq!(call(None), Err(tonic::Code::Unauthenticated));
+    }
 }
@@ -0,0 +1,41 @@
+use sqlx::PgPool;
+use tonic::{Request, Response, Status};
+use crate::model::search::UserSearchHit;
+use crate::protos::users::users_server::Users;
+use crate::protos::users::{SearchUsersRequest, SearchUsersResponse, UserHit};
+
+/// 与 REST 接口 `/user/search` 使用同一查询，由 [`AdminInterceptor`](super::AdminInterceptor) 限制为管理员
+pub struct UserService {
+    pool: PgPool,
+}
+
+impl UserService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl From<UserSearchHit> for UserHit {
+    fn from(hit: UserSearchHit) -> Self {
+        Self {
+            id: hit.id,
+            username: hit.username,
+            email: hit.email,
+            created_at: hit.created_at.map(|t| t.timestamp_millis()),
+            rank: hit.rank,
+            username_highlight: hit.username_highlight,
+            email_highlight: hit.email_highlight,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Users for UserService {
+    async fn search_users(&self, request: Request<SearchUsersRequest>) -> Result<Response<SearchUsersResponse>, Status> {
+        let req = request.into_inner();
+        let hits = UserSearchHit::search(&self.pool, &req.q, req.limit).await?;
+        Ok(Response::new(SearchUsersResponse {
+            users: hits.into_iter().map(UserHit::from).collect(),
+        }))
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+use sqlx::PgPool;
+use tonic::{Request, Response, Status};
+use tracing::Instrument;
+use crate::error::AppError;
+use crate::event::vote_record;
+use crate::kafka::outbox;
+use crate::leaderboard::{Leaderboard, LeaderboardWindow, DEFAULT_TOP_LIMIT};
+use crate::protos::voting::voting_request::Vote;
+use crate::protos::voting::voting_server::Voting;
+use crate::protos::voting::top_voted_request::Window;
+use crate::protos::voting::{
+    GetVotesRequest, GetVotesResponse, TopVotedRequest, TopVotedResponse, UrlScore, VoteCast, VotingRequest,
+    VotingResponse,
+};
+use crate::telemetry::db_span;
+
+/// 以 Postgres 为准的投票服务，每次投票在同一事务中写入发件箱，由转发任务发布投票事件；
+/// 提交后更新 Redis 排行榜
+pub struct VotingService {
+    pool: PgPool,
+    topic: String,
+    leaderboard: Arc<Leaderboard>,
+}
+
+impl VotingService {
+    pub fn new(pool: PgPool, topic: impl Into<String>, leaderboard: Arc<Leaderboard>) -> Self {
+        Self { pool, topic: topic.into(), leaderboard }
+    }
+
+    async fn record_vote(&self, url: String, vote: Vote) -> Result<i64, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let (vote_id, created_at) = sqlx::query_as::<_, (i64, chrono::DateTime<chrono::Utc>)>(
+            "INSERT INTO votes (url, vote) VALUES ($1, $2) RETURNING id, created_at",
+        )
+        .bind(&url)
+        .bind(vote as i16)
+        .fetch_one(&mut *tx)
+        .instrument(db_span("INSERT votes"))
+        .await?;
+        let event = VoteCast {
+            vote_id,
+            url: url.clone(),
+            vote: vote as i32,
+            occurred_at: created_at.timestamp_millis(),
+        };
+        outbox::enqueue(&mut tx, &vote_record(&self.topic, &event)).await?;
+        tx.commit().await?;
+        self.leaderboard.record(&url, vote, created_at).await;
+        Ok(vote_id)
+    }
+
+    async fn count_votes(&self, url: &str) -> Result<(i64, i64), AppError> {
+        let counts = sqlx::query_as::<_, (i64, i64)>(
+            r"
+            SELECT COUNT(*) FILTER (WHERE vote = 0), COUNT(*) FILTER (WHERE vote = 1)
+            FROM votes
+            WHERE url = $1
+            ",
+        )
+        .bind(url)
+        .fetch_one(&self.pool)
+        .instrument(db_span("SELECT votes"))
+        .await?;
+        Ok(counts)
+    }
+}
+
+#[tonic::async_trait]
+impl Voting for VotingService {
+    async fn vote(&self, request: Request<VotingRequest>) -> Result<Response<VotingResponse>, Status> {
+        let req = request.into_inner();
+        if req.url.trim().is_empty() {
+            return Err(Status::invalid_argument("url must not be empty"));
+        }
+        let vote = Vote::try_from(req.vote).map_err(|_| Status::invalid_argument("Invalid vote type"))?;
+        let url = req.url;
+        self.record_vote(url.clone(), vote).await?;
+        Ok(Response::new(VotingResponse {
+            confirmation: format!("Vote recorded for {}", url),
+        }))
+    }
+
+    async fn get_votes(&self, request: Request<GetVotesRequest>) -> Result<Response<GetVotesResponse>, Status> {
+        let (up_votes, down_votes) = self.count_votes(&request.into_inner().url).await?;
+        Ok(Response::new(GetVotesResponse {
+            up_votes: up_votes.try_into().unwrap_or(i32::MAX),
+            down_votes: down_votes.try_into().unwrap_or(i32::MAX),
+        }))
+    }
+
+    async fn top_voted(&self, request: Request<TopVotedRequest>) -> Result<Response<TopVotedResponse>, Status> {
+        let req = request.into_inner();
+        let window = Window::try_from(req.window).map_err(|_| Status::invalid_argument("Invalid window"))?;
+        let entries = self
+            .leaderboard
+            .top(LeaderboardWindow::from(window), req.limit.unwrap_or(DEFAULT_TOP_LIMIT))
+            .await?;
+        Ok(Response::new(TopVotedResponse {
+            urls: entries
+                .into_iter()
+                .map(|e| UrlScore { url: e.url, score: e.score })
+                .collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use crate::config::LeaderboardConfig;
+    use crate::init::test_utils::TestDatabase;
+
+    #[tokio::test]
+    async fn test_vote_enqueues_event() {
+        let test_db = TestDatabase::new().await;
+        // 排行榜不可用时投票照常成功
+        let client = redis::Client::open("redis://127.0.0.1:1/").unwrap();
+        let leaderboard = Arc::new(Leaderboard::new(client, LeaderboardConfig::default()));
+        let service = VotingService::new(test_db.pool.clone(), "votes", leaderboard);
+
+        for vote in [0, 0, 1] {
+            let request = Request::new(VotingRequest { url: "https://test.com".into(), vote });
+            service.vote(request).await.unwrap();
+        }
+        let invalid = Request::new(VotingRequest { url: "https://test.com".into(), vote: 7 });
+        assert_eq!(service.vote(invalid).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        let request = Request::new(GetVotesRequest { url: "https://test.com".into() });
+        let votes = service.get_votes(request).await.unwrap().into_inner();
+        assert_eq!((votes.up_votes, votes.down_votes), (2, 1));
+
+        let rows: Vec<(String, Vec<u8>)> =
+            sqlx::query_as("SELECT topic, payload FROM outbox ORDER BY id")
+                .fetch_all(&test_db.pool)
+                .await
+                .unwrap();
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|(topic, _)| topic == "votes"));
+        let last = VoteCast::decode(rows[2].1.as_slice()).unwrap();
+        assert_eq!((last.url.as_str(), last.vote), ("https://test.com", Vote::Down as i32));
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use futures_util::future::BoxFuture;
+use tokio::time::Instant;
+use crate::error::AppError;
+use super::store::{IdempotencyStore, Record};
+
+// 每隔这么多次写入清理一次过期的记录
+const SWEEP_EVERY: u64 = 256;
+
+/// 进程内的幂等记录，多个实例之间不共享
+pub struct MemoryStore {
+    records: Mutex<HashMap<String, (Instant, Record)>>,
+    inserts: AtomicU64,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            records: Mutex::new(HashMap::new()),
+            inserts: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdempotencyStore for MemoryStore {
+    fn insert<'a>(&'a self, key: &'a str, record: &'a Record, ttl: Duration)
+        -> BoxFuture<'a, Result<Option<Record>, AppError>> {
+        let now = Instant::now();
+        let mut records = self.records.lock().unwrap();
+        if self.inserts.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == SWEEP_EVERY - 1 {
+            records.retain(|_, (expires_at, _)| *expires_at > now);
+        }
+        let existing = match records.get(key) {
+            Some((expires_at, existing)) if *expires_at > now => Some(existing.clone()),
+            _ => {
+                records.insert(key.to_string(), (now + ttl, record.clone()));
+                None
+            }
+        };
+        Box::pin(std::future::ready(Ok(existing)))
+    }
+
+    fn put<'a>(&'a self, key: &'a str, record: &'a Record, ttl: Duration) -> BoxFuture<'a, Result<(), AppError>> {
+        let expires_at = Instant::now() + ttl;
+        self.records.lock().unwrap().insert(key.to_string(), (expires_at, record.clone()));
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), AppError>> {
+        self.records.lock().unwrap().remove(key);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
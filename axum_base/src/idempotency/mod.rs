@@ -0,0 +1,293 @@
+pub mod memory;
+pub mod store;
+
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use axum::body::{to_bytes, Body};
+use axum::extract::{MatchedPath, Request};
+use axum::http::header::{HeaderName, HeaderValue};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_util::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+use tracing::warn;
+use crate::config::IdempotencyConfig;
+use crate::constant::{IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY_KEY_PREFIX, IDEMPOTENT_REPLAYED_HEADER};
+use crate::controller::auth::{request_user, JwtSecret};
+use crate::error::AppError;
+use crate::ratelimit::client_ip;
+use store::{IdempotencyStore, Record};
+
+const MAX_KEY_LEN: usize = 255;
+
+/// 带 `Idempotency-Key` 的请求只执行一次，同一路由、同一用户用同一 key 重试时重放首次的响应。
+/// 未登录的请求按客户端 IP 区分，无法确定 IP 时忽略该请求头。
+///
+/// 首次请求处理中再次收到时返回 409，同一 key 用于不同的请求体时返回 422。
+/// 5xx 响应不保存，客户端可以用同一 key 重试。存储不可用时直接执行请求并记录日志。
+#[derive(Clone)]
+pub(crate) struct IdempotencyLayer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    store: Arc<dyn IdempotencyStore>,
+    secret: JwtSecret,
+    config: IdempotencyConfig,
+    /// 是否使用 `X-Forwarded-For` 中的客户端 IP
+    trust_forwarded: bool,
+}
+
+impl IdempotencyLayer {
+    pub(crate) fn new(
+        store: Arc<dyn IdempotencyStore>,
+        secret: JwtSecret,
+        config: IdempotencyConfig,
+        trust_forwarded: bool,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner { store, secret, config, trust_forwarded }),
+        }
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = Idempotency<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Idempotency { inner, layer: self.inner.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Idempotency<S> {
+    inner: S,
+    layer: Arc<Inner>,
+}
+
+impl<S> Service<Request> for Idempotency<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // 取走已就绪的服务，克隆出的服务留给下一次调用
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(self.layer.clone().handle(inner, request))
+    }
+}
+
+impl Inner {
+    /// 既没有登录用户也没有客户端 IP 时返回 `None`，不同的客户端不能共用同一命名空间
+    fn store_key(&self, parts: &Parts, key: &str) -> Option<String> {
+        let path = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map_or_else(|| parts.uri.path(), MatchedPath::as_str);
+        let client = match request_user(parts, &self.secret) {
+            Some(user) => format!("user:{}", user.id),
+            None => format!("ip:{}", client_ip(parts, self.trust_forwarded)?),
+        };
+        Some(format!("{}{}:{}:{}:{}", IDEMPOTENCY_KEY_PREFIX, parts.method, path, client, key))
+    }
+
+    async fn handle<S>(self: Arc<Self>, mut inner: S, request: Request) -> Result<Response, S::Error>
+    where
+        S: Service<Request, Response = Response>,
+    {
+        let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+            return inner.call(request).await;
+        };
+        let Some(key) = key.to_str().ok().map(str::trim).filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LEN) else {
+            return Ok(AppError::BadRequest("invalid Idempotency-Key".into()).into_response());
+        };
+        let key = key.to_string();
+        let (parts, body) = request.into_parts();
+        let Some(store_key) = self.store_key(&parts, &key) else {
+            warn!(key, "cannot identify the client, ignoring Idempotency-Key");
+            return inner.call(Request::from_parts(parts, body)).await;
+        };
+        let Ok(body) = to_bytes(body, self.config.max_body_bytes).await else {
+            return Ok(AppError::PayloadTooLarge.into_response());
+        };
+        let fingerprint = format!("{:x}", Sha256::digest(&body));
+        let request = Request::from_parts(parts, Body::from(body));
+
+        let in_flight = Record::InFlight { fingerprint: fingerprint.clone() };
+        match self.store.insert(&store_key, &in_flight, self.config.lock_ttl).await {
+            Ok(None) => {}
+            Ok(Some(existing)) => return Ok(replay(existing, &fingerprint)),
+            Err(e) => {
+                warn!(key, "idempotency store unavailable, executing request: {}", e);
+                return inner.call(request).await;
+            }
+        }
+
+        let response = inner.call(request).await?;
+        if response.status().is_server_error() {
+            self.release(&store_key).await;
+            return Ok(response);
+        }
+        let (parts, body) = response.into_parts();
+        let body = match to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                self.release(&store_key).await;
+                warn!(key, "failed to read response body: {}", e);
+                return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        };
+        let completed = Record::Completed {
+            fingerprint,
+            status: parts.status.as_u16(),
+            headers: parts
+                .headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                .collect(),
+            body: STANDARD.encode(&body),
+        };
+        if let Err(e) = self.store.put(&store_key, &completed, self.config.ttl).await {
+            warn!(key, "failed to save idempotent response: {}", e);
+        }
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    async fn release(&self, store_key: &str) {
+        if let Err(e) = self.store.remove(store_key).await {
+            warn!(store_key, "failed to release idempotency key: {}", e);
+        }
+    }
+}
+
+fn replay(existing: Record, fingerprint: &str) -> Response {
+    if existing.fingerprint() != fingerprint {
+        return AppError::Unprocessable("Idempotency-Key was already used with a different request".into())
+            .into_response();
+    }
+    let Record::Completed { status, headers, body, .. } = existing else {
+        return AppError::Conflict("a request with this Idempotency-Key is still in progress".into()).into_response();
+    };
+    let Ok(body) = STANDARD.decode(body) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use tower::ServiceExt;
+    use memory::MemoryStore;
+
+    fn router(calls: Arc<AtomicUsize>) -> Router {
+        let layer = IdempotencyLayer::new(
+            Arc::new(MemoryStore::new()),
+            JwtSecret("secret".into()),
+            IdempotencyConfig::default(),
+            true,
+        );
+        let handler = move |body: String| async move {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            match body.as_str() {
+                "slow" => tokio::time::sleep(Duration::from_secs(10)).await,
+                "fail" => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+                _ => {}
+            }
+            Ok((StatusCode::CREATED, Json(serde_json::json!({ "call": call }))))
+        };
+        Router::new().route("/", post(handler).layer(layer))
+    }
+
+    async fn send(router: &Router, key: Option<&str>, body: &'static str) -> Response {
+        send_from(router, "1.1.1.1", key, body).await
+    }
+
+    async fn send_from(router: &Router, ip: &str, key: Option<&str>, body: &'static str) -> Response {
+        let mut request = Request::builder().method("POST").uri("/").header("x-forwarded-for", ip);
+        if let Some(key) = key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        router.clone().oneshot(request.body(Body::from(body)).unwrap()).await.unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_mismatch() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = router(calls.clone());
+
+        let first = send(&router, Some("a"), "x").await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        assert_eq!(body(first).await, r#"{"call":1}"#);
+
+        let replayed = send(&router, Some("a"), "x").await;
+        assert_eq!(replayed.status(), StatusCode::CREATED);
+        assert_eq!(replayed.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+        assert_eq!(replayed.headers()["content-type"], "application/json");
+        assert_eq!(body(replayed).await, r#"{"call":1}"#);
+
+        assert_eq!(send(&router, Some("a"), "y").await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(send(&router, Some("b"), "x").await.status(), StatusCode::CREATED);
+        assert_eq!(send(&router, None, "x").await.status(), StatusCode::CREATED);
+        assert_eq!(send(&router, Some(" "), "x").await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // 未登录的其他客户端使用同一 key 时不会拿到别人的响应
+        let other = send_from(&router, "2.2.2.2", Some("a"), "y").await;
+        assert!(other.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        assert_eq!(body(other).await, r#"{"call":4}"#);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_in_flight_and_server_error() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = router(calls.clone());
+
+        let first = tokio::spawn({
+            let router = router.clone();
+            async move { send(&router, Some("slow"), "slow").await }
+        });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(send(&router, Some("slow"), "slow").await.status(), StatusCode::CONFLICT);
+        assert_eq!(first.await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(send(&router, Some("slow"), "slow").await.status(), StatusCode::CREATED);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // 5xx 不保存，同一 key 可以重试
+        assert_eq!(send(&router, Some("fail"), "fail").await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(send(&router, Some("fail"), "fail").await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use futures_util::future::BoxFuture;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{AsyncCommands, RedisResult, Script};
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use crate::config::StoreBackend;
+use crate::error::AppError;
+use crate::telemetry::redis::TracedConnection;
+use super::memory::MemoryStore;
+
+const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+
+// 不存在时写入并返回 nil，否则返回已有的记录
+// KEYS: 幂等 key
+// ARGV: 记录, 过期毫秒
+static INSERT_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local existing = redis.call('GET', KEYS[1])
+        if existing then
+            return existing
+        end
+        redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+        return false
+        ",
+    )
+});
+
+/// 一个幂等 key 的状态，`fingerprint` 为请求体的摘要
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Record {
+    InFlight {
+        fingerprint: String,
+    },
+    Completed {
+        fingerprint: String,
+        status: u16,
+        headers: Vec<(String, String)>,
+        /// base64 编码的响应体
+        body: String,
+    },
+}
+
+impl Record {
+    pub fn fingerprint(&self) -> &str {
+        match self {
+            Record::InFlight { fingerprint } | Record::Completed { fingerprint, .. } => fingerprint,
+        }
+    }
+}
+
+/// 幂等记录的存储，多实例部署使用 Redis，单节点和测试可以换成进程内实现
+pub trait IdempotencyStore: Send + Sync {
+    /// key 不存在时写入 `record` 并返回 `None`，否则返回已有的记录
+    fn insert<'a>(&'a self, key: &'a str, record: &'a Record, ttl: Duration)
+        -> BoxFuture<'a, Result<Option<Record>, AppError>>;
+
+    /// 覆盖写入
+    fn put<'a>(&'a self, key: &'a str, record: &'a Record, ttl: Duration) -> BoxFuture<'a, Result<(), AppError>>;
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), AppError>>;
+}
+
+/// 按配置创建幂等记录的存储
+pub fn from_config(backend: StoreBackend, client: redis::Client) -> Arc<dyn IdempotencyStore> {
+    match backend {
+        StoreBackend::Redis => Arc::new(RedisStore::new(client)),
+        StoreBackend::Memory => Arc::new(MemoryStore::new()),
+    }
+}
+
+pub struct RedisStore {
+    client: redis::Client,
+    conn: OnceCell<ConnectionManager>,
+}
+
+impl RedisStore {
+    pub fn new(client: redis::Client) -> Self {
+        Self { client, conn: OnceCell::new() }
+    }
+
+    async fn conn(&self) -> RedisResult<TracedConnection> {
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(0)
+            .set_connection_timeout(REDIS_TIMEOUT)
+            .set_response_timeout(REDIS_TIMEOUT);
+        self.conn
+            .get_or_try_init(|| self.client.get_connection_manager_with_config(config))
+            .await
+            .cloned()
+            .map(TracedConnection::new)
+    }
+}
+
+impl IdempotencyStore for RedisStore {
+    fn insert<'a>(&'a self, key: &'a str, record: &'a Record, ttl: Duration)
+        -> BoxFuture<'a, Result<Option<Record>, AppError>> {
+        Box::pin(async move {
+            let mut conn = self.conn().await?;
+            let existing: Option<String> = INSERT_SCRIPT
+                .key(key)
+                .arg(serde_json::to_string(record)?)
+                .arg(ttl.as_millis() as u64)
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(existing.map(|json| serde_json::from_str(&json)).transpose()?)
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, record: &'a Record, ttl: Duration) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let mut conn = self.conn().await?;
+            let _: () = conn.pset_ex(key, serde_json::to_string(record)?, ttl.as_millis() as u64).await?;
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let mut conn = self.conn().await?;
+            let _: () = conn.del(key).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::test_utils::TestRedis;
+
+    #[tokio::test]
+    async fn test_redis_store() {
+        let test_redis = TestRedis::new().await;
+        let store = RedisStore::new(test_redis.client.clone());
+        let ttl = Duration::from_secs(60);
+        let in_flight = Record::InFlight { fingerprint: "a".into() };
+
+        assert_eq!(store.insert("k", &in_flight, ttl).await.unwrap(), None);
+        assert_eq!(store.insert("k", &in_flight, ttl).await.unwrap(), Some(in_flight.clone()));
+        let completed = Record::Completed {
+            fingerprint: "a".into(),
+            status: 200,
+            headers: vec![("content-type".into(), "application/json".into())],
+            body: "e30=".into(),
+        };
+        store.put("k", &completed, ttl).await.unwrap();
+        assert_eq!(store.insert("k", &in_flight, ttl).await.unwrap(), Some(completed));
+        store.remove("k").await.unwrap();
+        assert_eq!(store.insert("k", &in_flight, ttl).await.unwrap(), None);
+    }
+}
@@ -1,8 +1,27 @@
 use std::fmt;
 use std::ops::Deref;
 use std::sync::Arc;
+use crate::analytics::query::AnalyticsQueries;
+use crate::audit::AuditLog;
+use crate::cache::RedisCache;
+use crate::config::{
+    AdminConfig, AnalyticsConfig, BridgeConfig, CacheConfig, ChatConfig, EventBusConfig, IdempotencyConfig, LeaderboardConfig,
+    RateLimitConfig, UserEventTopics, WsConfig,
+};
+use crate::controller::auth::{AdminIds, JwtSecret, MetricsToken};
 use crate::error::AppError;
+use crate::event::UserEventPublisher;
+use crate::idempotency::store::{self as idempotency_store, IdempotencyStore};
+use crate::kafka::bus::{self, EventBus};
+use crate::leaderboard::Leaderboard;
+use crate::model::cursor::CursorCodec;
+use crate::ratelimit::store::{self as rate_limit_store, RateLimitStore};
+use crate::websocket::bridge::WsBridge;
+use crate::websocket::presence::Presence;
+use crate::websocket::WsManager;
+use axum::extract::FromRef;
 use dotenv::dotenv;
+use sqlx::postgres::PgPoolOptions;
 use std::env;
 
 #[derive(Debug, Clone)]
@@ -14,7 +33,20 @@ pub(crate) struct AppStateInner {
     pub(crate) pool: sqlx::PgPool,
     pub(crate) _redis_client: redis::Client,
     pub(crate) pem:String,
-    pub(crate) _kafka_url: String,
+    pub(crate) event_bus: Arc<dyn EventBus>,
+    pub(crate) user_events: UserEventPublisher,
+    pub(crate) ws: Arc<WsManager>,
+    pub(crate) ws_bridge: Arc<WsBridge>,
+    pub(crate) chat_config: ChatConfig,
+    pub(crate) analytics: Arc<AnalyticsQueries>,
+    pub(crate) cache: Arc<RedisCache>,
+    pub(crate) leaderboard: Arc<Leaderboard>,
+    pub(crate) rate_limit: Arc<dyn RateLimitStore>,
+    pub(crate) idempotency: Arc<dyn IdempotencyStore>,
+    pub(crate) cursor: CursorCodec,
+    pub(crate) admins: AdminIds,
+    pub(crate) metrics_token: MetricsToken,
+    pub(crate) audit: AuditLog,
 }
 
 
@@ -25,6 +57,54 @@ impl Deref for AppState {
     }
 }
 
+impl FromRef<AppState> for Arc<WsManager> {
+    fn from_ref(state: &AppState) -> Self {
+        state.ws.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<WsBridge> {
+    fn from_ref(state: &AppState) -> Self {
+        state.ws_bridge.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RedisCache> {
+    fn from_ref(state: &AppState) -> Self {
+        state.cache.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Leaderboard> {
+    fn from_ref(state: &AppState) -> Self {
+        state.leaderboard.clone()
+    }
+}
+
+impl FromRef<AppState> for JwtSecret {
+    fn from_ref(state: &AppState) -> Self {
+        JwtSecret(state.pem.as_str().into())
+    }
+}
+
+impl FromRef<AppState> for AuditLog {
+    fn from_ref(state: &AppState) -> Self {
+        state.audit.clone()
+    }
+}
+
+impl FromRef<AppState> for AdminIds {
+    fn from_ref(state: &AppState) -> Self {
+        state.admins.clone()
+    }
+}
+
+impl FromRef<AppState> for MetricsToken {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics_token.clone()
+    }
+}
+
 impl fmt::Debug for AppStateInner {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AppState")
@@ -37,24 +117,54 @@ impl AppState {
     pub async fn new() -> Result<Self, AppError> {
         dotenv().ok();
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let kafka_url = env::var("KAFKA_URL").expect("KAFKA_URL must be set");
         let pem = env::var("PEM").expect("PEM must be set");
-        let pool = sqlx::PgPool::connect(&database_url)
+        // 未单独配置时用 JWT 密钥签名分页游标
+        let cursor_secret = env::var("CURSOR_SECRET").unwrap_or_else(|_| pem.clone());
+        // 每次获取连接都发出耗时事件，由 `PoolAcquireLayer` 记为指标
+        let pool = PgPoolOptions::new()
+            .acquire_time_level(log::LevelFilter::Trace)
+            .connect(&database_url)
             .await
             .map_err(|_| {
                 AppError::Database(sqlx::Error::Configuration(
                     "Failed to connect to database".into(),
                 ))
             })?;
+        let rate_limit = RateLimitConfig::from_env();
         let redis_url = env::var("REDIS_URL").expect("REDIS_URL must be set");
         let redis_client = redis::Client::open(redis_url)?;
+        let event_bus = bus::from_config(&EventBusConfig::from_env())?;
+        let user_events = UserEventPublisher::new(event_bus.clone(), UserEventTopics::from_env());
+        let ws_config = WsConfig::from_env();
+        let presence = Presence::new(
+            redis_client.clone(),
+            ws_config.presence_ttl,
+            ws_config.presence_timeout,
+        );
+        let ws = Arc::new(WsManager::new(ws_config).with_presence(presence));
+
+        let audit = AuditLog::new(pool.clone(), rate_limit.trust_forwarded);
+        let admin = AdminConfig::from_env();
 
         Ok(Self {
             inner: Arc::new(AppStateInner {
                 pool,
+                cache: Arc::new(RedisCache::new(redis_client.clone(), CacheConfig::from_env())),
+                leaderboard: Arc::new(Leaderboard::new(redis_client.clone(), LeaderboardConfig::from_env())),
+                rate_limit: rate_limit_store::from_config(rate_limit.backend, redis_client.clone()),
+                idempotency: idempotency_store::from_config(IdempotencyConfig::from_env().backend, redis_client.clone()),
+                cursor: CursorCodec::new(cursor_secret),
+                admins: AdminIds(admin.user_ids.into()),
+                metrics_token: MetricsToken(admin.metrics_token.map(Into::into)),
+                audit,
                 _redis_client: redis_client,
                 pem,
-                _kafka_url: kafka_url
+                event_bus,
+                user_events,
+                ws,
+                ws_bridge: Arc::new(WsBridge::new(BridgeConfig::from_env())),
+                chat_config: ChatConfig::from_env(),
+                analytics: Arc::new(AnalyticsQueries::new(AnalyticsConfig::from_env())),
             }),
         })
     }
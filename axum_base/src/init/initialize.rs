@@ -1,16 +1,29 @@
-use tracing::{level_filters::LevelFilter as Level};
-use tracing_subscriber::fmt::Layer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+use dotenv::dotenv;
+use tracing::warn;
+use tracing_appender::non_blocking::WorkerGuard;
 use testcontainers::{clients};
 use std::sync::OnceLock;
+use crate::config::{LogConfig, TelemetryConfig};
+use crate::init::logging;
+use crate::metrics;
+use crate::telemetry;
 
-pub fn init() {
-    init_log();
-}
-
-pub fn init_log() {
-    let layer = Layer::new().with_filter(Level::DEBUG);
-    tracing_subscriber::registry().with(layer).init();
+/// 初始化日志、链路和指标，返回的 guard 需要持有到进程退出
+pub fn init() -> Option<WorkerGuard> {
+    // 日志配置在 AppState 创建前读取，先加载 .env
+    dotenv().ok();
+    let config = TelemetryConfig::from_env();
+    // 日志初始化前无法输出错误，先记下来
+    let (tracer, error) = match telemetry::init_tracer(&config) {
+        Ok(tracer) => (tracer, None),
+        Err(e) => (None, Some(e)),
+    };
+    let guard = logging::init_log(&LogConfig::from_env(), tracer);
+    if let Some(e) = error {
+        warn!("failed to start {:?} trace exporter, spans are not exported: {}", config.exporter, e);
+    }
+    metrics::install();
+    guard
 }
 
 static _CLI: OnceLock<clients::Cli> = OnceLock::new();
@@ -34,7 +47,7 @@ pub mod test_utils {
 
     impl TestDatabase {
         pub async fn new() -> Self {
-            let cli = _CLI.get_or_init(|| clients::Cli::default());
+            let cli = _CLI.get_or_init(clients::Cli::default);
             let image = RunnableImage::from(
                 GenericImage::new("postgres", "latest")
                     .with_env_var("POSTGRES_USER", "postgres")
@@ -59,7 +72,7 @@ pub mod test_utils {
             std::fs::create_dir_all(&migrations_dir).expect("Failed to create migrations directory");
 
             let output = Command::new("sqlx")
-                .args(&["database", "create"])
+                .args(["database", "create"])
                 .env("DATABASE_URL", &db_url)
                 .current_dir(project_root)
                 .output()
@@ -70,7 +83,7 @@ pub mod test_utils {
             }
 
             let output = Command::new("sqlx")
-                .args(&["migrate", "run"])
+                .args(["migrate", "run"])
                 .env("DATABASE_URL", &db_url)
                 .current_dir(project_root)
                 .output()
@@ -88,4 +101,28 @@ pub mod test_utils {
             }
         }
     }
+
+    pub struct TestRedis {
+        pub _container: Container<'static, GenericImage>,
+        pub client: redis::Client,
+    }
+
+    impl TestRedis {
+        pub async fn new() -> Self {
+            let cli = _CLI.get_or_init(clients::Cli::default);
+            let image = RunnableImage::from(
+                GenericImage::new("redis", "7")
+                    .with_exposed_port(6379)
+                    .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+            );
+            let container = cli.run(image);
+            let port = container.get_host_port_ipv4(6379);
+            let client = redis::Client::open(format!("redis://127.0.0.1:{}/", port)).unwrap();
+
+            Self {
+                _container: container,
+                client,
+            }
+        }
+    }
 }
@@ -0,0 +1,165 @@
+use std::io;
+use std::sync::OnceLock;
+use opentelemetry_sdk::trace::Tracer;
+use tracing::level_filters::LevelFilter as Level;
+use tracing::warn;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+use crate::config::{LogConfig, LogFormat, LogRotation};
+use crate::error::AppError;
+use crate::metrics::PoolAcquireLayer;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// 日志输出的过滤规则，初始化后可以在运行时替换
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+fn format_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        // 字段平铺到顶层，当前 span 中的请求标识等字段一并输出
+        LogFormat::Json => layer.json().flatten_event(true).with_current_span(true).with_span_list(false).boxed(),
+    }
+}
+
+fn file_appender(config: &LogConfig) -> io::Result<Option<RollingFileAppender>> {
+    let Some(dir) = &config.dir else {
+        return Ok(None);
+    };
+    // 清理旧文件时会读取目录，目录需要先存在
+    std::fs::create_dir_all(dir)?;
+    let rotation = match config.rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(&config.file_prefix)
+        .filename_suffix("log");
+    if config.max_files > 0 {
+        builder = builder.max_log_files(config.max_files);
+    }
+    builder.build(dir).map(Some).map_err(io::Error::other)
+}
+
+/// 按配置初始化日志，`tracer` 不为空时同时把 span 导出到 OpenTelemetry。
+///
+/// 写文件在后台线程进行，返回的 guard 在退出前不能丢弃，否则缓冲中的日志会丢失。
+pub fn init_log(config: &LogConfig, tracer: Option<Tracer>) -> Option<WorkerGuard> {
+    // 日志初始化前无法输出错误，先记下来
+    let (filter, filter_error) = match parse_filter(&config.filter) {
+        Ok(filter) => (filter, None),
+        Err(e) => (EnvFilter::new("info"), Some(e)),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    let mut output = format_layer(config.format, io::stdout, config.format == LogFormat::Text);
+    let (guard, file_error) = match file_appender(config) {
+        Ok(Some(appender)) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            output = output.and_then(format_layer(config.format, writer, false)).boxed();
+            (Some(guard), None)
+        }
+        Ok(None) => (None, None),
+        Err(e) => (None, Some(e)),
+    };
+    // 链路导出不受日志过滤规则影响
+    let otel = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer).with_filter(Level::INFO));
+    tracing_subscriber::registry()
+        .with(output.with_filter(filter))
+        .with(otel)
+        .with(PoolAcquireLayer.with_filter(PoolAcquireLayer::filter()))
+        .init();
+    let _ = FILTER.set(handle);
+    if let Some(e) = filter_error {
+        warn!("{}, falling back to \"info\"", e);
+    }
+    if let Some(e) = file_error {
+        warn!("failed to open log directory {:?}, logging to stdout only: {}", config.dir, e);
+    }
+    guard
+}
+
+fn parse_filter(filter: &str) -> Result<EnvFilter, AppError> {
+    EnvFilter::builder()
+        .parse(filter)
+        .map_err(|e| AppError::BadRequest(format!("invalid log filter: {}", e)))
+}
+
+/// 当前生效的过滤规则
+pub fn current_filter() -> Option<String> {
+    FILTER.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+/// 替换过滤规则，例如 `info,sqlx=debug`
+pub fn set_filter(filter: &str) -> Result<String, AppError> {
+    let filter = parse_filter(filter)?;
+    let handle = FILTER.get().ok_or_else(|| AppError::Unavailable("logging is not initialized".into()))?;
+    let current = filter.to_string();
+    handle
+        .reload(filter)
+        .map_err(|e| AppError::Unavailable(format!("failed to reload log filter: {}", e)))?;
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::{debug, info, info_span};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'w> MakeWriter<'w> for Buffer {
+        type Writer = Buffer;
+
+        fn make_writer(&'w self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_output_and_reload() {
+        let buffer = Buffer::default();
+        let (filter, handle) = reload::Layer::new(parse_filter("info").unwrap());
+        let subscriber = tracing_subscriber::registry().with(format_layer(LogFormat::Json, buffer.clone(), false).with_filter(filter));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = info_span!("request", request_id = "abc").entered();
+            debug!("hidden");
+            info!(user_id = 1, "visible");
+            handle.reload(parse_filter("debug").unwrap()).unwrap();
+            debug!("shown after reload");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["message"], "visible");
+        assert_eq!(lines[0]["user_id"], 1);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["span"]["request_id"], "abc");
+        assert_eq!(lines[1]["message"], "shown after reload");
+        assert!(parse_filter("info,sqlx=nonsense").is_err());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod initialize;
 pub mod app_state;
+pub mod logging;
 
 #[cfg(test)]
 pub use initialize::test_utils;
@@ -0,0 +1,231 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::future::BoxFuture;
+use metrics::gauge;
+use rdkafka::client::ClientContext;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, ConsumerContext, Rebalance, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{Headers, Message};
+use rdkafka::statistics::Statistics;
+use rdkafka::topic_partition_list::TopicPartitionList;
+use crate::config::{EventBusConfig, EventBusKind, KafkaConfig, OffsetReset};
+use super::memory::MemoryBus;
+use super::producer::{DeliveryReport, KafkaProducer, ProduceError, ProducerRecord};
+
+/// 从事件总线收到的消息，持有自己的数据以便在任务间传递
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RawMessage {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+    pub headers: Vec<(String, Vec<u8>)>,
+}
+
+impl RawMessage {
+    fn from_kafka<M: Message>(message: &M) -> Self {
+        let headers = message
+            .headers()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(|h| (h.key.to_string(), h.value.map(<[u8]>::to_vec).unwrap_or_default()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+            key: message.key().map(<[u8]>::to_vec),
+            payload: message.payload().map(<[u8]>::to_vec).unwrap_or_default(),
+            headers,
+        }
+    }
+
+    pub fn header(&self, key: &str) -> Option<&[u8]> {
+        self.headers.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_slice())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BusError {
+    #[error("Kafka error: {0}")]
+    Kafka(#[from] KafkaError),
+}
+
+/// 投递成功或失败后调用的回调
+pub type DeliveryCallback = Box<dyn FnOnce(Result<DeliveryReport, ProduceError>) + Send>;
+
+/// 发布订阅的抽象，生产环境使用 Kafka，测试和本地开发可以换成进程内实现
+pub trait EventBus: Send + Sync {
+    /// 消息被确认后返回
+    fn publish(&self, record: ProducerRecord) -> BoxFuture<'_, Result<DeliveryReport, ProduceError>>;
+
+    /// 立即入队，不等待确认；队满时返回 [`ProduceError::QueueFull`]，不会调用 `callback`
+    fn publish_with_callback(&self, record: ProducerRecord, callback: DeliveryCallback) -> Result<(), ProduceError>;
+
+    /// 以消费组身份订阅，从该组已提交的位置开始读取，没有已提交的位置时按 `reset` 决定
+    fn subscribe(
+        &self,
+        group: &str,
+        topics: &[String],
+        reset: OffsetReset,
+    ) -> Result<Arc<dyn Subscription>, BusError>;
+
+    /// 等待已发布的消息全部确认，用于停机前
+    fn flush(&self, timeout: Duration) -> BoxFuture<'_, Result<(), ProduceError>>;
+}
+
+/// 一个消费组成员的读取位置
+pub trait Subscription: Send + Sync {
+    fn recv(&self) -> BoxFuture<'_, Result<RawMessage, BusError>>;
+
+    /// 标记消息已处理，重新订阅时从它的下一条开始
+    fn commit(&self, message: &RawMessage) -> Result<(), BusError>;
+
+    /// 停止返回分区的消息，恢复后从尚未返回的下一条继续
+    fn pause(&self, topic: &str, partition: i32) -> Result<(), BusError>;
+
+    fn resume(&self, topic: &str, partition: i32) -> Result<(), BusError>;
+
+    /// 取出上次调用之后因重新均衡被收回的分区
+    fn take_revoked(&self) -> Vec<(String, i32)>;
+}
+
+/// 按配置创建事件总线
+pub fn from_config(config: &EventBusConfig) -> Result<Arc<dyn EventBus>, KafkaError> {
+    Ok(match config.kind {
+        EventBusKind::Kafka => Arc::new(KafkaBus::new(&KafkaConfig::from_env())?),
+        EventBusKind::Memory => Arc::new(MemoryBus::new(config.memory_partitions)),
+    })
+}
+
+/// 基于 [`KafkaProducer`] 和 rdkafka 消费者的实现
+pub struct KafkaBus {
+    producer: KafkaProducer,
+    brokers: String,
+    stats_interval: Duration,
+}
+
+impl KafkaBus {
+    pub fn new(config: &KafkaConfig) -> Result<Self, KafkaError> {
+        Ok(Self {
+            producer: KafkaProducer::new(config)?,
+            brokers: config.brokers.clone(),
+            stats_interval: config.stats_interval,
+        })
+    }
+}
+
+/// 把 librdkafka 定期上报的各分区消费延迟记为指标，并记下重新均衡时被收回的分区
+struct GroupContext {
+    group: String,
+    revoked: Mutex<Vec<(String, i32)>>,
+}
+
+impl ClientContext for GroupContext {
+    fn stats(&self, statistics: Statistics) {
+        for topic in statistics.topics.values() {
+            for partition in topic.partitions.values() {
+                // -1 为 librdkafka 内部的未分配分区，延迟未知时也是 -1
+                if partition.partition < 0 || partition.consumer_lag < 0 {
+                    continue;
+                }
+                gauge!(
+                    "kafka_consumer_lag",
+                    "group" => self.group.clone(),
+                    "topic" => topic.topic.clone(),
+                    "partition" => partition.partition.to_string(),
+                )
+                .set(partition.consumer_lag as f64);
+            }
+        }
+    }
+}
+
+impl ConsumerContext for GroupContext {
+    fn pre_rebalance(&self, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Revoke(partitions) = rebalance {
+            let mut revoked = self.revoked.lock().unwrap();
+            revoked.extend(partitions.elements().iter().map(|e| (e.topic().to_string(), e.partition())));
+        }
+    }
+}
+
+impl EventBus for KafkaBus {
+    fn publish(&self, record: ProducerRecord) -> BoxFuture<'_, Result<DeliveryReport, ProduceError>> {
+        Box::pin(self.producer.send(record))
+    }
+
+    fn publish_with_callback(&self, record: ProducerRecord, callback: DeliveryCallback) -> Result<(), ProduceError> {
+        self.producer.send_with_callback(record, callback)
+    }
+
+    fn subscribe(
+        &self,
+        group: &str,
+        topics: &[String],
+        reset: OffsetReset,
+    ) -> Result<Arc<dyn Subscription>, BusError> {
+        let consumer: StreamConsumer<GroupContext> = ClientConfig::new()
+            .set("bootstrap.servers", &self.brokers)
+            .set("group.id", group)
+            .set("auto.offset.reset", reset.as_str())
+            // 只提交调用方确认处理过的 offset，由后台定时提交
+            .set("enable.auto.commit", "true")
+            .set("enable.auto.offset.store", "false")
+            .set("statistics.interval.ms", self.stats_interval.as_millis().to_string())
+            .create_with_context(GroupContext { group: group.to_string(), revoked: Mutex::default() })?;
+        let topics: Vec<&str> = topics.iter().map(String::as_str).collect();
+        consumer.subscribe(&topics)?;
+        Ok(Arc::new(KafkaSubscription { consumer }))
+    }
+
+    fn flush(&self, timeout: Duration) -> BoxFuture<'_, Result<(), ProduceError>> {
+        Box::pin(async move { Ok(self.producer.flush(timeout).await?) })
+    }
+}
+
+struct KafkaSubscription {
+    consumer: StreamConsumer<GroupContext>,
+}
+
+impl KafkaSubscription {
+    fn partition_list(topic: &str, partition: i32) -> TopicPartitionList {
+        let mut list = TopicPartitionList::new();
+        list.add_partition(topic, partition);
+        list
+    }
+}
+
+impl Subscription for KafkaSubscription {
+    fn recv(&self) -> BoxFuture<'_, Result<RawMessage, BusError>> {
+        Box::pin(async move {
+            let message = self.consumer.recv().await?;
+            Ok(RawMessage::from_kafka(&message))
+        })
+    }
+
+    fn commit(&self, message: &RawMessage) -> Result<(), BusError> {
+        self.consumer.store_offset(&message.topic, message.partition, message.offset)?;
+        Ok(())
+    }
+
+    // librdkafka 暂停时丢弃已预取的消息，恢复后从最后返回给调用方的位置重新拉取
+    fn pause(&self, topic: &str, partition: i32) -> Result<(), BusError> {
+        self.consumer.pause(&Self::partition_list(topic, partition))?;
+        Ok(())
+    }
+
+    fn resume(&self, topic: &str, partition: i32) -> Result<(), BusError> {
+        self.consumer.resume(&Self::partition_list(topic, partition))?;
+        Ok(())
+    }
+
+    fn take_revoked(&self) -> Vec<(String, i32)> {
+        std::mem::take(&mut *self.consumer.context().revoked.lock().unwrap())
+    }
+}
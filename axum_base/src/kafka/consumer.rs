@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use metrics::{counter, histogram};
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::config::ConsumerConfig;
+use crate::constant::{
+    DLQ_ATTEMPTS_HEADER, DLQ_CONSUMER_GROUP_HEADER, DLQ_ERROR_HEADER, DLQ_FAILED_AT_HEADER,
+    DLQ_ORIGINAL_OFFSET_HEADER, DLQ_ORIGINAL_PARTITION_HEADER, DLQ_ORIGINAL_TOPIC_HEADER,
+    DLQ_TOPIC_SUFFIX, REQUEST_ID_HEADER,
+};
+use crate::error::AppError;
+use crate::request_id::RequestId;
+use crate::telemetry;
+use super::bus::{BusError, EventBus, RawMessage, Subscription};
+use super::producer::ProducerRecord;
+
+/// 消息体的解码方式，解码失败的消息直接进入死信 topic
+pub trait Decode: Sized {
+    fn decode(payload: &[u8]) -> Result<Self, String>;
+}
+
+/// 按 JSON 解码消息体
+// 现有消费者使用 protobuf 或纯文本，目前只有测试使用
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> Decode for Json<T> {
+    fn decode(payload: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(payload).map(Json).map_err(|e| e.to_string())
+    }
+}
+
+/// 按 UTF-8 文本读取消息体
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text(pub String);
+
+impl Decode for Text {
+    fn decode(payload: &[u8]) -> Result<Self, String> {
+        String::from_utf8(payload.to_vec()).map(Text).map_err(|e| e.to_string())
+    }
+}
+
+/// 按 protobuf 解码消息体
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proto<T>(pub T);
+
+impl<T: prost::Message + Default> Decode for Proto<T> {
+    fn decode(payload: &[u8]) -> Result<Self, String> {
+        T::decode(payload).map(Proto).map_err(|e| e.to_string())
+    }
+}
+
+/// 交给处理函数的消息，`value` 为解码后的消息体
+pub struct Delivery<D> {
+    pub value: D,
+    pub message: Arc<RawMessage>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandlerError {
+    /// 临时故障，按退避重试，次数用完后进入死信 topic
+    #[error("{0}")]
+    Retryable(String),
+    /// 消息本身无法处理，不重试直接进入死信 topic
+    #[error("{0}")]
+    Permanent(String),
+}
+
+impl From<AppError> for HandlerError {
+    fn from(error: AppError) -> Self {
+        HandlerError::Retryable(error.to_string())
+    }
+}
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+type BoxedHandler = Arc<dyn Fn(Arc<RawMessage>) -> HandlerFuture + Send + Sync>;
+
+fn boxed<D, F, Fut>(handler: F) -> BoxedHandler
+where
+    D: Decode + Send + 'static,
+    F: Fn(Delivery<D>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+{
+    Arc::new(move |message: Arc<RawMessage>| -> HandlerFuture {
+        match D::decode(&message.payload) {
+            Ok(value) => Box::pin(handler(Delivery { value, message })),
+            Err(e) => Box::pin(async move {
+                Err(HandlerError::Permanent(format!("failed to decode message: {}", e)))
+            }),
+        }
+    })
+}
+
+/// 按 topic 注册处理函数后创建 [`ConsumerRuntime`]
+pub struct ConsumerBuilder {
+    config: ConsumerConfig,
+    handlers: HashMap<String, BoxedHandler>,
+}
+
+impl ConsumerBuilder {
+    pub fn new(config: ConsumerConfig) -> Self {
+        Self { config, handlers: HashMap::new() }
+    }
+
+    /// 同一个 topic 重复注册时后者覆盖前者
+    pub fn handler<D, F, Fut>(mut self, topic: impl Into<String>, handler: F) -> Self
+    where
+        D: Decode + Send + 'static,
+        F: Fn(Delivery<D>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        self.handlers.insert(topic.into(), boxed(handler));
+        self
+    }
+
+    /// 以 `group_id` 订阅所有已注册的 topic，死信发布到同一个总线
+    pub fn build(self, bus: Arc<dyn EventBus>) -> Result<ConsumerRuntime, BusError> {
+        let topics: Vec<String> = self.handlers.keys().cloned().collect();
+        let subscription = bus.subscribe(&self.config.group_id, &topics, self.config.offset_reset)?;
+        Ok(ConsumerRuntime {
+            shared: Arc::new(Shared {
+                subscription,
+                bus,
+                semaphore: Semaphore::new(self.config.max_concurrency),
+                handlers: self.handlers,
+                config: self.config,
+            }),
+        })
+    }
+}
+
+struct Shared {
+    subscription: Arc<dyn Subscription>,
+    bus: Arc<dyn EventBus>,
+    handlers: HashMap<String, BoxedHandler>,
+    config: ConsumerConfig,
+    semaphore: Semaphore,
+}
+
+/// 长期运行的消费者。
+///
+/// 每个分区有一个按 offset 顺序处理消息的任务，不同分区并发执行，同时执行的
+/// 处理函数总数不超过 `max_concurrency`。某个分区的缓冲满时只暂停拉取该分区，
+/// 其他分区照常消费。offset 只在处理成功或转入死信后保存，进程退出或分区被收回后
+/// 从最后提交的位置重新消费，投递语义是至少一次。
+pub struct ConsumerRuntime {
+    shared: Arc<Shared>,
+}
+
+impl ConsumerRuntime {
+    pub async fn run(self) {
+        let mut partitions: HashMap<(String, i32), PartitionWorker> = HashMap::new();
+        // 被收回但还在处理当前消息的任务，分区重新分配回来时新任务等它结束
+        let mut draining: HashMap<(String, i32), JoinHandle<()>> = HashMap::new();
+        loop {
+            let received = self.shared.subscription.recv().await;
+            for key in self.shared.subscription.take_revoked() {
+                if let Some(worker) = partitions.remove(&key) {
+                    worker.revoked.store(true, Ordering::Relaxed);
+                    draining.insert(key, worker.task);
+                }
+            }
+            draining.retain(|_, task| !task.is_finished());
+
+            let message = match received {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("kafka consumer error: {}", e);
+                    tokio::time::sleep(self.shared.config.retry_backoff).await;
+                    continue;
+                }
+            };
+            let key = (message.topic.clone(), message.partition);
+            let worker = partitions
+                .entry(key)
+                .or_insert_with_key(|key| spawn_partition_worker(self.shared.clone(), draining.remove(key)));
+            // 暂停期间不应再收到该分区的消息，万一收到也要排在等待入队的消息之后
+            if let Some(overflow) = worker.overflow.take() {
+                let _ = overflow.await;
+            }
+            match worker.tx.try_send(message) {
+                Ok(()) => {}
+                Err(TrySendError::Full(message)) => {
+                    worker.overflow = Some(self.shared.clone().enqueue_paused(worker.tx.clone(), message));
+                }
+                Err(TrySendError::Closed(_)) => error!("partition worker stopped unexpectedly"),
+            }
+        }
+    }
+}
+
+/// 按 offset 顺序处理一个分区的任务
+struct PartitionWorker {
+    tx: mpsc::Sender<RawMessage>,
+    task: JoinHandle<()>,
+    /// 分区被收回后缓冲中剩余的消息不再处理，由新的消费者从已提交的位置重新消费
+    revoked: Arc<AtomicBool>,
+    /// 缓冲满时在后台等待入队的消息
+    overflow: Option<JoinHandle<()>>,
+}
+
+fn spawn_partition_worker(shared: Arc<Shared>, previous: Option<JoinHandle<()>>) -> PartitionWorker {
+    let (tx, mut rx) = mpsc::channel(shared.config.partition_buffer);
+    let revoked = Arc::new(AtomicBool::new(false));
+    let skip = revoked.clone();
+    let task = tokio::spawn(async move {
+        if let Some(previous) = previous {
+            let _ = previous.await;
+        }
+        while let Some(message) = rx.recv().await {
+            if !skip.load(Ordering::Relaxed) {
+                shared.process(message).await;
+            }
+        }
+    });
+    PartitionWorker { tx, task, revoked, overflow: None }
+}
+
+impl Shared {
+    /// 暂停拉取分区，在后台等缓冲有空位后入队再恢复
+    fn enqueue_paused(self: Arc<Self>, tx: mpsc::Sender<RawMessage>, message: RawMessage) -> JoinHandle<()> {
+        let (topic, partition) = (message.topic.clone(), message.partition);
+        if let Err(e) = self.subscription.pause(&topic, partition) {
+            warn!(topic, partition, "failed to pause partition: {}", e);
+        }
+        tokio::spawn(async move {
+            if tx.send(message).await.is_err() {
+                error!("partition worker stopped unexpectedly");
+                return;
+            }
+            if let Err(e) = self.subscription.resume(&topic, partition) {
+                // 分区已被收回，重新分配时从头开始拉取
+                debug!(topic, partition, "failed to resume partition: {}", e);
+            }
+        })
+    }
+
+    async fn process(&self, message: RawMessage) {
+        let message = Arc::new(message);
+        if let Some(handler) = self.handlers.get(&message.topic) {
+            // 恢复生产方的请求标识和链路，处理函数发出的消息和日志沿用同一标识
+            let id = RequestId::from_header(message.header(REQUEST_ID_HEADER));
+            let span = info_span!(
+                "kafka",
+                otel.kind = "consumer",
+                topic = message.topic,
+                partition = message.partition,
+                offset = message.offset,
+                request_id = %id
+            );
+            span.set_parent(telemetry::extract_kafka(&message.headers));
+            let handle = async {
+                let start = Instant::now();
+                let result = handle_with_retry(handler, &message, &self.config, &self.semaphore).await;
+                let labels = [
+                    ("topic", message.topic.clone()),
+                    ("group", self.config.group_id.clone()),
+                    ("result", if result.is_ok() { "ok" } else { "dead_letter" }.to_string()),
+                ];
+                counter!("kafka_messages_consumed_total", &labels).increment(1);
+                histogram!("kafka_consume_duration_seconds", &labels[..2]).record(start.elapsed().as_secs_f64());
+                if let Err((error, attempts)) = result {
+                    self.dead_letter(&message, &error, attempts).await;
+                }
+            };
+            id.scope(handle).instrument(span).await;
+        }
+        if let Err(e) = self.subscription.commit(&message) {
+            // 分区已被分配给其他消费者，对方会从上次提交的位置重新处理
+            debug!(topic = message.topic, partition = message.partition, "failed to store offset: {}", e);
+        }
+    }
+
+    /// 死信发送失败时持续重试，不能跳过未处理的消息
+    async fn dead_letter(&self, message: &RawMessage, error: &HandlerError, attempts: u32) {
+        let record = dead_letter_record(message, error, attempts, &self.config.group_id);
+        let mut failures = 0;
+        loop {
+            match self.bus.publish(record.clone()).await {
+                Ok(_) => {
+                    warn!(
+                        topic = message.topic,
+                        partition = message.partition,
+                        offset = message.offset,
+                        attempts,
+                        "message moved to dead letter topic: {}",
+                        error
+                    );
+                    return;
+                }
+                Err(e) => {
+                    failures += 1;
+                    error!(topic = record.topic, "failed to send dead letter: {}", e);
+                    tokio::time::sleep(self.config.backoff(failures)).await;
+                }
+            }
+        }
+    }
+}
+
+/// 执行处理函数，可重试的失败按退避重试，返回最终的失败原因和总尝试次数。
+/// 退避等待期间不占用并发名额。
+async fn handle_with_retry(
+    handler: &BoxedHandler,
+    message: &Arc<RawMessage>,
+    config: &ConsumerConfig,
+    semaphore: &Semaphore,
+) -> Result<(), (HandlerError, u32)> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = {
+            let _permit = semaphore.acquire().await.expect("semaphore is never closed");
+            handler(message.clone()).await
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e @ HandlerError::Retryable(_)) if attempts <= config.max_retries => {
+                debug!(topic = message.topic, offset = message.offset, attempts, "handler failed: {}", e);
+                tokio::time::sleep(config.backoff(attempts)).await;
+            }
+            Err(e) => return Err((e, attempts)),
+        }
+    }
+}
+
+/// 原消息的 key、消息体和消息头保持不变，附加失败信息
+fn dead_letter_record(message: &RawMessage, error: &HandlerError, attempts: u32, group: &str) -> ProducerRecord {
+    let mut record = ProducerRecord::new(
+        format!("{}{}", message.topic, DLQ_TOPIC_SUFFIX),
+        message.payload.clone(),
+    );
+    record.key = message.key.clone();
+    record.headers = message.headers.clone();
+    record
+        .with_header(DLQ_ORIGINAL_TOPIC_HEADER, message.topic.clone())
+        .with_header(DLQ_ORIGINAL_PARTITION_HEADER, message.partition.to_string())
+        .with_header(DLQ_ORIGINAL_OFFSET_HEADER, message.offset.to_string())
+        .with_header(DLQ_CONSUMER_GROUP_HEADER, group)
+        .with_header(DLQ_ERROR_HEADER, error.to_string())
+        .with_header(DLQ_ATTEMPTS_HEADER, attempts.to_string())
+        .with_header(DLQ_FAILED_AT_HEADER, chrono::Utc::now().to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use crate::protos::user_events::{user_event::Event, UserDeleted, UserEvent};
+
+    fn message(payload: &[u8]) -> Arc<RawMessage> {
+        Arc::new(RawMessage {
+            topic: "orders".into(),
+            partition: 2,
+            offset: 41,
+            key: Some(b"7".to_vec()),
+            payload: payload.to_vec(),
+            headers: vec![("source".into(), b"test".to_vec())],
+        })
+    }
+
+    /// 前 `failures` 次返回可重试错误
+    fn flaky_handler(failures: u32, calls: Arc<AtomicU32>) -> BoxedHandler {
+        boxed(move |delivery: Delivery<Json<serde_json::Value>>| {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                assert_eq!(delivery.value.0["id"], 1);
+                if call <= failures {
+                    Err(HandlerError::Retryable(format!("attempt {}", call)))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_decode() {
+        let event = UserEvent::new(Event::Deleted(UserDeleted { user_id: 3 }));
+        let Proto(decoded) = Proto::<UserEvent>::decode(&prost::Message::encode_to_vec(&event)).unwrap();
+        assert_eq!(decoded, event);
+
+        let Json(value) = Json::<serde_json::Value>::decode(br#"{"id":1}"#).unwrap();
+        assert_eq!(value["id"], 1);
+        assert!(Json::<serde_json::Value>::decode(b"not json").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_with_backoff() {
+        let config = ConsumerConfig { max_retries: 3, ..ConsumerConfig::default() };
+        let semaphore = Semaphore::new(1);
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let handler = flaky_handler(2, calls.clone());
+        assert!(handle_with_retry(&handler, &message(br#"{"id":1}"#), &config, &semaphore).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let handler = flaky_handler(u32::MAX, calls.clone());
+        let (error, attempts) = handle_with_retry(&handler, &message(br#"{"id":1}"#), &config, &semaphore)
+            .await
+            .unwrap_err();
+        assert_eq!(attempts, 4);
+        assert!(matches!(error, HandlerError::Retryable(_)));
+
+        // 解码失败不重试
+        let calls = Arc::new(AtomicU32::new(0));
+        let handler = flaky_handler(0, calls.clone());
+        let (error, attempts) = handle_with_retry(&handler, &message(b"garbage"), &config, &semaphore)
+            .await
+            .unwrap_err();
+        assert_eq!(attempts, 1);
+        assert!(matches!(error, HandlerError::Permanent(_)));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_dead_letter_record() {
+        let message = message(b"garbage");
+        let error = HandlerError::Permanent("bad payload".into());
+        let record = dead_letter_record(&message, &error, 1, "group-a");
+
+        assert_eq!(record.topic, "orders.dlq");
+        assert_eq!(record.key, message.key);
+        assert_eq!(record.payload, message.payload);
+        let header = |key: &str| {
+            record.headers.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_slice())
+        };
+        assert_eq!(header("source"), Some(b"test".as_slice()));
+        assert_eq!(header(DLQ_ORIGINAL_TOPIC_HEADER), Some(b"orders".as_slice()));
+        assert_eq!(header(DLQ_ORIGINAL_PARTITION_HEADER), Some(b"2".as_slice()));
+        assert_eq!(header(DLQ_ORIGINAL_OFFSET_HEADER), Some(b"41".as_slice()));
+        assert_eq!(header(DLQ_CONSUMER_GROUP_HEADER), Some(b"group-a".as_slice()));
+        assert_eq!(header(DLQ_ERROR_HEADER), Some(b"bad payload".as_slice()));
+        assert_eq!(header(DLQ_ATTEMPTS_HEADER), Some(b"1".as_slice()));
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::future::BoxFuture;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use tokio::sync::Notify;
+use crate::config::OffsetReset;
+use super::bus::{BusError, DeliveryCallback, EventBus, RawMessage, Subscription};
+use super::producer::{DeliveryReport, ProduceError, ProducerRecord};
+
+#[derive(Default)]
+struct MemoryState {
+    /// topic 下每个分区的消息，下标即 offset
+    topics: HashMap<String, Vec<Vec<RawMessage>>>,
+    /// (消费组, topic, 分区) 下一条要读取的 offset
+    committed: HashMap<(String, String, i32), i64>,
+    round_robin: usize,
+}
+
+struct Inner {
+    partitions: i32,
+    state: Mutex<MemoryState>,
+    notify: Notify,
+}
+
+/// 进程内的事件总线，保存所有消息和各消费组提交的位置，用于测试和本地开发。
+///
+/// 带 key 的消息按 key 哈希选择分区，没有 key 时轮询。不做分区分配，
+/// 同一消费组同时只应有一个订阅。
+#[derive(Clone)]
+pub struct MemoryBus {
+    inner: Arc<Inner>,
+}
+
+impl MemoryBus {
+    pub fn new(partitions: i32) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                partitions: partitions.max(1),
+                state: Mutex::new(MemoryState::default()),
+                notify: Notify::new(),
+            }),
+        }
+    }
+
+    /// topic 下的全部消息，先按分区再按 offset 排列
+    #[cfg(test)]
+    pub fn messages(&self, topic: &str) -> Vec<RawMessage> {
+        let state = self.inner.state.lock().unwrap();
+        state.topics.get(topic).map(|logs| logs.concat()).unwrap_or_default()
+    }
+
+    /// 消费组在分区上已提交的位置，即下一条要读取的 offset
+    #[cfg(test)]
+    pub fn committed(&self, group: &str, topic: &str, partition: i32) -> Option<i64> {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .committed
+            .get(&(group.to_string(), topic.to_string(), partition))
+            .copied()
+    }
+
+    fn append(&self, record: ProducerRecord) -> Result<DeliveryReport, ProduceError> {
+        let partitions = self.inner.partitions;
+        let mut state = self.inner.state.lock().unwrap();
+        let partition = match (record.partition, &record.key) {
+            (Some(partition), _) => partition,
+            (None, Some(key)) => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish() % partitions as u64) as i32
+            }
+            (None, None) => {
+                state.round_robin = state.round_robin.wrapping_add(1);
+                (state.round_robin % partitions as usize) as i32
+            }
+        };
+        if !(0..partitions).contains(&partition) {
+            return Err(ProduceError::Kafka(KafkaError::MessageProduction(
+                RDKafkaErrorCode::UnknownPartition,
+            )));
+        }
+
+        let logs = state
+            .topics
+            .entry(record.topic.clone())
+            .or_insert_with(|| vec![Vec::new(); partitions as usize]);
+        let log = &mut logs[partition as usize];
+        let offset = log.len() as i64;
+        log.push(RawMessage {
+            topic: record.topic.clone(),
+            partition,
+            offset,
+            key: record.key,
+            payload: record.payload,
+            headers: record.headers,
+        });
+        drop(state);
+        self.inner.notify.notify_waiters();
+
+        Ok(DeliveryReport { topic: record.topic, partition, offset })
+    }
+}
+
+impl EventBus for MemoryBus {
+    fn publish(&self, record: ProducerRecord) -> BoxFuture<'_, Result<DeliveryReport, ProduceError>> {
+        let result = self.append(record);
+        Box::pin(async move { result })
+    }
+
+    fn publish_with_callback(&self, record: ProducerRecord, callback: DeliveryCallback) -> Result<(), ProduceError> {
+        callback(self.append(record));
+        Ok(())
+    }
+
+    fn subscribe(
+        &self,
+        group: &str,
+        topics: &[String],
+        reset: OffsetReset,
+    ) -> Result<Arc<dyn Subscription>, BusError> {
+        let mut positions = HashMap::new();
+        if reset == OffsetReset::Latest {
+            // 没有已提交位置的分区从当前末尾开始，之后才创建的分区从 0 开始
+            let state = self.inner.state.lock().unwrap();
+            for topic in topics {
+                let Some(logs) = state.topics.get(topic) else {
+                    continue;
+                };
+                for (partition, log) in logs.iter().enumerate() {
+                    let key = (group.to_string(), topic.clone(), partition as i32);
+                    if !state.committed.contains_key(&key) {
+                        positions.insert((topic.clone(), partition as i32), log.len() as i64);
+                    }
+                }
+            }
+        }
+        Ok(Arc::new(MemorySubscription {
+            bus: self.clone(),
+            group: group.to_string(),
+            topics: topics.to_vec(),
+            positions: Mutex::new(positions),
+            paused: Mutex::new(HashSet::new()),
+        }))
+    }
+
+    fn flush(&self, _timeout: Duration) -> BoxFuture<'_, Result<(), ProduceError>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+struct MemorySubscription {
+    bus: MemoryBus,
+    group: String,
+    topics: Vec<String>,
+    /// 本订阅的读取位置，首次读取分区时从消费组已提交的位置开始
+    positions: Mutex<HashMap<(String, i32), i64>>,
+    paused: Mutex<HashSet<(String, i32)>>,
+}
+
+impl MemorySubscription {
+    fn try_next(&self) -> Option<RawMessage> {
+        let mut positions = self.positions.lock().unwrap();
+        let paused = self.paused.lock().unwrap();
+        let state = self.bus.inner.state.lock().unwrap();
+        for topic in &self.topics {
+            let Some(logs) = state.topics.get(topic) else {
+                continue;
+            };
+            for (partition, log) in logs.iter().enumerate() {
+                let partition = partition as i32;
+                if paused.contains(&(topic.clone(), partition)) {
+                    continue;
+                }
+                let position = positions.entry((topic.clone(), partition)).or_insert_with(|| {
+                    state
+                        .committed
+                        .get(&(self.group.clone(), topic.clone(), partition))
+                        .copied()
+                        .unwrap_or(0)
+                });
+                if let Some(message) = log.get(*position as usize) {
+                    *position += 1;
+                    return Some(message.clone());
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Subscription for MemorySubscription {
+    fn recv(&self) -> BoxFuture<'_, Result<RawMessage, BusError>> {
+        Box::pin(async move {
+            loop {
+                // 先登记唤醒再检查，避免错过两者之间发布的消息
+                let notified = self.bus.inner.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if let Some(message) = self.try_next() {
+                    return Ok(message);
+                }
+                notified.await;
+            }
+        })
+    }
+
+    fn commit(&self, message: &RawMessage) -> Result<(), BusError> {
+        let mut state = self.bus.inner.state.lock().unwrap();
+        let committed = state
+            .committed
+            .entry((self.group.clone(), message.topic.clone(), message.partition))
+            .or_insert(0);
+        *committed = (*committed).max(message.offset + 1);
+        Ok(())
+    }
+
+    fn pause(&self, topic: &str, partition: i32) -> Result<(), BusError> {
+        self.paused.lock().unwrap().insert((topic.to_string(), partition));
+        Ok(())
+    }
+
+    fn resume(&self, topic: &str, partition: i32) -> Result<(), BusError> {
+        self.paused.lock().unwrap().remove(&(topic.to_string(), partition));
+        self.bus.inner.notify.notify_waiters();
+        Ok(())
+    }
+
+    /// 不做分区分配，分区不会被收回
+    fn take_revoked(&self) -> Vec<(String, i32)> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_consumer_group_offsets() {
+        let bus = MemoryBus::new(2);
+        for i in 0..4 {
+            bus.publish(ProducerRecord::new("t", format!("m{}", i)).with_key("same"))
+                .await
+                .unwrap();
+        }
+        // 相同 key 落在同一分区，offset 连续
+        let messages = bus.messages("t");
+        assert!(messages.iter().all(|m| m.partition == messages[0].partition));
+        assert_eq!(messages.iter().map(|m| m.offset).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        let topics = vec!["t".to_string()];
+        let subscription = bus.subscribe("g", &topics, OffsetReset::Earliest).unwrap();
+        let first = subscription.recv().await.unwrap();
+        let second = subscription.recv().await.unwrap();
+        assert_eq!((first.payload.as_slice(), second.payload.as_slice()), (b"m0".as_slice(), b"m1".as_slice()));
+        subscription.commit(&first).unwrap();
+        assert_eq!(bus.committed("g", "t", first.partition), Some(1));
+
+        // 新订阅从已提交的位置开始，未提交的消息会重新投递
+        let subscription = bus.subscribe("g", &topics, OffsetReset::Earliest).unwrap();
+        assert_eq!(subscription.recv().await.unwrap(), second);
+        // 其他消费组从头开始
+        let other = bus.subscribe("other", &topics, OffsetReset::Earliest).unwrap();
+        assert_eq!(other.recv().await.unwrap(), first);
+        for _ in 1..4 {
+            other.recv().await.unwrap();
+        }
+
+        // 没有新消息时等待发布
+        let pending = tokio::spawn(async move { other.recv().await.unwrap() });
+        tokio::task::yield_now().await;
+        bus.publish(ProducerRecord::new("t", "late").with_key("same")).await.unwrap();
+        let late = pending.await.unwrap();
+        assert_eq!((late.offset, late.payload.as_slice()), (4, b"late".as_slice()));
+
+        // latest 的新消费组跳过已有消息，已提交过的消费组不受影响
+        let latest = bus.subscribe("latest", &topics, OffsetReset::Latest).unwrap();
+        let resumed = bus.subscribe("g", &topics, OffsetReset::Latest).unwrap();
+        assert_eq!(resumed.recv().await.unwrap(), second);
+        bus.publish(ProducerRecord::new("t", "fresh").with_key("same")).await.unwrap();
+        let fresh = latest.recv().await.unwrap();
+        assert_eq!((fresh.offset, fresh.payload.as_slice()), (5, b"fresh".as_slice()));
+    }
+}
@@ -1,105 +1,172 @@
-use std::time::Duration;
-use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
-use kafka::producer::{Producer, Record, RequiredAcks};
-use kafka::error::Error as KafkaError;
-
-
-fn _produce_message(
-    data: &[u8],
-    topic: &str,
-    brokers: Vec<String>,
-) -> Result<(), KafkaError> {
-    println!("About to publish a message at {:?} to: {}", brokers, topic);
-
-    // ~ create a producer. this is a relatively costly operation, so
-    // you'll do this typically once in your application and re-use
-    // the instance many times.
-    let mut producer = Producer::from_hosts(brokers)
-        // ~ give the brokers one second time to ack the message
-        .with_ack_timeout(Duration::from_secs(1))
-        // ~ require only one broker to ack the message
-        .with_required_acks(RequiredAcks::One)
-        // ~ build the producer with the above settings
-        .create()?;
-
-    // ~ now send a single message.  this is a synchronous/blocking
-    // operation.
-
-    // ~ we're sending 'data' as a 'value'. there will be no key
-    // associated with the sent message.
-
-    // ~ we leave the partition "unspecified" - this is a negative
-    // partition - which causes the producer to find out one on its
-    // own using its underlying partitioner.
-    producer.send(&Record {
-        topic,
-        partition: -1,
-        key: (),
-        value: data,
-    })?;
-
-    // ~ we can achieve exactly the same as above in a shorter way with
-    // the following call
-    producer.send(&Record::from_value(topic, data))?;
-
-    Ok(())
-}
-
-
-fn _consume_messages(group: String, topic: String, brokers: Vec<String>) -> Result<(), KafkaError> {
-    let mut con = Consumer::from_hosts(brokers)
-        .with_topic(topic)
-        .with_group(group)
-        .with_fallback_offset(FetchOffset::Earliest)
-        .with_offset_storage(Some(GroupOffsetStorage::Kafka))
-        .create()?;
-
-    loop {
-        let mss = con.poll()?;
-        if mss.is_empty() {
-            println!("No messages available right now.");
-            return Ok(());
-        }
-
-        for ms in mss.iter() {
-            for m in ms.messages() {
-                println!(
-                    "{}:{}@{}: {:?}",
-                    ms.topic(),
-                    ms.partition(),
-                    m.offset,
-                    m.value
-                );
-            }
-            let _ = con.consume_messageset(ms);
-        }
-        con.commit_consumed()?;
-    }
-}
-
-
+pub mod producer;
+pub mod bus;
+pub mod memory;
+pub mod outbox;
+pub mod consumer;
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
-    #[test]
-    fn test_produce_message() {
-        let brokers = vec!["localhost:9092".to_string()];
-        let topic = "test-topic";
-        let data = b"test message";
-
-        let result = _produce_message(data, topic, brokers);
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::{oneshot, Semaphore};
+    use crate::config::ConsumerConfig;
+    use super::bus::EventBus;
+    use super::consumer::{ConsumerBuilder, Delivery, HandlerError, Json, Text};
+    use super::memory::MemoryBus;
+    use super::producer::ProducerRecord;
+    use crate::constant::REQUEST_ID_HEADER;
+    use crate::request_id::RequestId;
+
+    #[tokio::test]
+    async fn test_produce_message() {
+        let bus = MemoryBus::new(3);
+        let record = ProducerRecord::new("test-topic", b"test message".to_vec())
+            .with_key("test-key")
+            .with_header("source", "test");
+
+        let result = bus.publish(record).await;
         assert!(result.is_ok(), "Failed to produce message: {:?}", result.err());
+        let messages = bus.messages("test-topic");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].key.as_deref(), Some(b"test-key".as_slice()));
+        assert_eq!(messages[0].header("source"), Some(b"test".as_slice()));
     }
     
-    #[test]
-    fn test_consume_messages() {
-        let brokers = vec!["localhost:9092".to_string()];
-        let topic = "test-topic".to_string();
-        let group = "test-group".to_string();
+    #[tokio::test]
+    async fn test_consume_messages() {
+        let bus = MemoryBus::new(3);
+        bus.publish(ProducerRecord::new("test-topic", br#"{"text":"hello"}"#.to_vec()))
+            .await
+            .unwrap();
+        bus.publish(ProducerRecord::new("test-topic", b"not json".to_vec()))
+            .await
+            .unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let tx = Mutex::new(Some(tx));
+        let config = ConsumerConfig {
+            group_id: "test-group".to_string(),
+            ..ConsumerConfig::default()
+        };
+        let runtime = ConsumerBuilder::new(config)
+            .handler("test-topic", move |delivery: Delivery<Json<serde_json::Value>>| {
+                if let Some(tx) = tx.lock().unwrap().take() {
+                    let _ = tx.send(delivery.message.clone());
+                }
+                async { Ok::<_, HandlerError>(()) }
+            })
+            .build(Arc::new(bus.clone()))
+            .unwrap();
+        let consumer = tokio::spawn(runtime.run());
+
+        let message = tokio::time::timeout(Duration::from_secs(5), rx).await.unwrap().unwrap();
+        assert_eq!(message.payload, br#"{"text":"hello"}"#);
+
+        // 无法解码的消息进入死信 topic，两条消息的 offset 都已提交
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while bus.messages("test-topic.dlq").is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let dead = &bus.messages("test-topic.dlq")[0];
+        assert_eq!(dead.payload, b"not json");
+        let committed: i64 = (0..3)
+            .filter_map(|p| bus.committed("test-group", "test-topic", p))
+            .sum();
+        assert_eq!(committed, 2);
+        consumer.abort();
+    }
 
-        let result = _consume_messages(group, topic, brokers);
-        assert!(result.is_ok(), "Failed to consume messages: {:?}", result.err());
+    #[tokio::test]
+    async fn test_request_id_follows_messages() {
+        let bus = MemoryBus::new(1);
+        let id = RequestId::parse(b"abc-123").unwrap();
+        let record = id.scope(async { ProducerRecord::new("in", b"{}".to_vec()) }).await;
+        bus.publish(record).await.unwrap();
+
+        // 处理函数中发出的消息沿用收到的消息的标识
+        let runtime = ConsumerBuilder::new(ConsumerConfig::default())
+            .handler("in", {
+                let bus = bus.clone();
+                move |_: Delivery<Json<serde_json::Value>>| {
+                    let bus = bus.clone();
+                    let record = ProducerRecord::new("out", b"{}".to_vec());
+                    async move {
+                        bus.publish(record).await.map_err(|e| HandlerError::Retryable(e.to_string()))?;
+                        Ok(())
+                    }
+                }
+            })
+            .build(Arc::new(bus.clone()))
+            .unwrap();
+        let consumer = tokio::spawn(runtime.run());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while bus.messages("out").is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(bus.messages("out")[0].header(REQUEST_ID_HEADER), Some(b"abc-123".as_slice()));
+        assert!(ProducerRecord::new("t", b"".to_vec()).headers.is_empty());
+        consumer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_full_partition_does_not_block_others() {
+        let bus = MemoryBus::new(2);
+        for i in 0..5 {
+            bus.publish(ProducerRecord::new("t", format!("slow{}", i)).with_partition(0)).await.unwrap();
+        }
+        bus.publish(ProducerRecord::new("t", "fast").with_partition(1)).await.unwrap();
+
+        // 分区 0 的处理函数在放行前一直挂起，缓冲很快被填满
+        let gate = Arc::new(Semaphore::new(0));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let config = ConsumerConfig {
+            group_id: "test-group".to_string(),
+            partition_buffer: 1,
+            ..ConsumerConfig::default()
+        };
+        let runtime = ConsumerBuilder::new(config)
+            .handler("t", {
+                let (gate, handled) = (gate.clone(), handled.clone());
+                move |delivery: Delivery<Text>| {
+                    let (gate, handled) = (gate.clone(), handled.clone());
+                    async move {
+                        if delivery.message.partition == 0 {
+                            gate.acquire().await.unwrap().forget();
+                        }
+                        handled.lock().unwrap().push(delivery.value.0);
+                        Ok::<_, HandlerError>(())
+                    }
+                }
+            })
+            .build(Arc::new(bus.clone()))
+            .unwrap();
+        let consumer = tokio::spawn(runtime.run());
+
+        let wait_handled = |n: usize| {
+            let handled = handled.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while handled.lock().unwrap().len() < n {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+                .await
+                .expect("messages were not handled");
+            }
+        };
+        wait_handled(1).await;
+        assert_eq!(*handled.lock().unwrap(), ["fast"]);
+
+        gate.add_permits(5);
+        wait_handled(6).await;
+        assert_eq!(handled.lock().unwrap()[1..], ["slow0", "slow1", "slow2", "slow3", "slow4"]);
+        assert_eq!(bus.committed("test-group", "t", 0), Some(5));
+        consumer.abort();
     }
 }
@@ -0,0 +1,435 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sqlx::{PgConnection, PgPool};
+use tracing::{debug, warn, Instrument};
+use crate::config::OutboxConfig;
+use crate::constant::OUTBOX_ID_HEADER;
+use crate::error::AppError;
+use crate::telemetry::db_span;
+use super::bus::EventBus;
+use super::producer::ProducerRecord;
+
+/// 在调用方的事务中写入待发送消息，事务提交后才会被转发任务看到
+pub(crate) async fn enqueue(conn: &mut PgConnection, record: &ProducerRecord) -> Result<i64, AppError> {
+    let (header_keys, header_values): (Vec<&str>, Vec<&[u8]>) = record
+        .headers
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_slice()))
+        .unzip();
+    let id = sqlx::query_scalar::<_, i64>(
+        r"
+        INSERT INTO outbox (topic, key, payload, header_keys, header_values)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        ",
+    )
+    .bind(&record.topic)
+    .bind(record.key.as_deref())
+    .bind(&record.payload)
+    .bind(header_keys)
+    .bind(header_values)
+    .fetch_one(conn)
+    .instrument(db_span("INSERT outbox"))
+    .await?;
+    Ok(id)
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct OutboxRow {
+    id: i64,
+    topic: String,
+    key: Option<Vec<u8>>,
+    payload: Vec<u8>,
+    header_keys: Vec<String>,
+    header_values: Vec<Vec<u8>>,
+    /// 认领时仍在其他实例的租约中
+    leased: bool,
+}
+
+impl OutboxRow {
+    fn into_record(self) -> ProducerRecord {
+        let mut record = ProducerRecord::new(self.topic, self.payload);
+        record.key = self.key;
+        for (key, value) in self.header_keys.into_iter().zip(self.header_values) {
+            record = record.with_header(key, value);
+        }
+        record.with_header(OUTBOX_ID_HEADER, self.id.to_string())
+    }
+}
+
+enum BatchOutcome {
+    Idle,
+    Sent,
+    Failed,
+}
+
+/// 把发件箱中的待发送行按 id 顺序发布到事件总线的后台任务。
+///
+/// 投递语义是至少一次：进程在批次中途退出时整批会被重新发布，消费方可以用
+/// `outbox-id` 消息头去重。某一行发布失败时其后的行不会越过它发送，
+/// 转发按 [`OutboxConfig::backoff`] 退避后从该行重试。
+pub struct OutboxRelay {
+    pool: PgPool,
+    bus: Arc<dyn EventBus>,
+    config: OutboxConfig,
+}
+
+impl OutboxRelay {
+    pub fn new(pool: PgPool, bus: Arc<dyn EventBus>, config: OutboxConfig) -> Self {
+        Self { pool, bus, config }
+    }
+
+    pub async fn run(self) {
+        let mut failures = 0u32;
+        let mut last_prune: Option<Instant> = None;
+        loop {
+            let delay = match self.relay_batch().await {
+                // 可能还有积压，立即读取下一批
+                Ok(BatchOutcome::Sent) => {
+                    failures = 0;
+                    Duration::ZERO
+                }
+                Ok(BatchOutcome::Idle) => {
+                    failures = 0;
+                    self.config.poll_interval
+                }
+                Ok(BatchOutcome::Failed) => {
+                    failures += 1;
+                    self.config.backoff(failures)
+                }
+                Err(e) => {
+                    warn!("outbox relay failed: {}", e);
+                    failures += 1;
+                    self.config.backoff(failures)
+                }
+            };
+
+            if last_prune.is_none_or(|at| at.elapsed() >= self.config.prune_interval) {
+                match self.prune().await {
+                    Ok(pruned) => debug!(pruned, "pruned sent outbox rows"),
+                    Err(e) => warn!("failed to prune outbox: {}", e),
+                }
+                last_prune = Some(Instant::now());
+            }
+
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    /// 用短事务认领最早的一批待发送行，在事务外逐条发布，再把确认的行标记为已发送。
+    ///
+    /// 最早的行在其他实例的租约中时不认领，多个实例的转发因此串行执行，不会乱序。
+    /// 发布一批超过 [`OutboxConfig::lease`] 时其他实例可能重复发布同一批。
+    async fn relay_batch(&self) -> Result<BatchOutcome, AppError> {
+        let claim = format!("{:032x}", rand::random::<u128>());
+        let rows = self.claim(&claim).await?;
+        if rows.is_empty() {
+            return Ok(BatchOutcome::Idle);
+        }
+
+        let mut sent = Vec::with_capacity(rows.len());
+        let mut failure = None;
+        for row in rows {
+            let id = row.id;
+            match self.bus.publish(row.into_record()).await {
+                Ok(_) => sent.push(id),
+                Err(e) => {
+                    failure = Some((id, e));
+                    break;
+                }
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r"
+            UPDATE outbox
+            SET sent_at = CURRENT_TIMESTAMP, attempts = attempts + 1, locked_until = NULL, claimed_by = NULL
+            WHERE id = ANY($1)
+            ",
+        )
+        .bind(&sent)
+        .execute(&mut *tx)
+        .instrument(db_span("UPDATE outbox"))
+        .await?;
+        if let Some((id, e)) = &failure {
+            warn!(id, "failed to publish outbox message: {}", e);
+            sqlx::query("UPDATE outbox SET attempts = attempts + 1, last_error = $2 WHERE id = $1")
+                .bind(id)
+                .bind(e.to_string())
+                .execute(&mut *tx)
+                .instrument(db_span("UPDATE outbox"))
+                .await?;
+            // 失败的行和其后未发布的行交还，退避后从失败的行重新认领
+            sqlx::query("UPDATE outbox SET locked_until = NULL, claimed_by = NULL WHERE claimed_by = $1 AND sent_at IS NULL")
+                .bind(&claim)
+                .execute(&mut *tx)
+                .instrument(db_span("UPDATE outbox"))
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(if failure.is_some() { BatchOutcome::Failed } else { BatchOutcome::Sent })
+    }
+
+    /// 锁住最早的一批待发送行并写入租约后立即提交，其中有行在其他实例的租约中时返回空
+    async fn claim(&self, claim: &str) -> Result<Vec<OutboxRow>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        // 并发认领的实例在行锁上等待，之后读到的是前一个实例写入的租约
+        let rows = sqlx::query_as::<_, OutboxRow>(
+            r"
+            SELECT id, topic, key, payload, header_keys, header_values, COALESCE(locked_until > CURRENT_TIMESTAMP, FALSE) AS leased
+            FROM outbox
+            WHERE sent_at IS NULL
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE
+            ",
+        )
+        .bind(self.config.batch_size)
+        .fetch_all(&mut *tx)
+        .instrument(db_span("SELECT outbox"))
+        .await?;
+        if rows.is_empty() || rows.iter().any(|row| row.leased) {
+            return Ok(Vec::new());
+        }
+        let ids: Vec<i64> = rows.iter().map(|row| row.id).collect();
+        sqlx::query(
+            r"
+            UPDATE outbox
+            SET locked_until = CURRENT_TIMESTAMP + make_interval(secs => $2), claimed_by = $3
+            WHERE id = ANY($1)
+            ",
+        )
+        .bind(&ids)
+        .bind(self.config.lease.as_secs_f64())
+        .bind(claim)
+        .execute(&mut *tx)
+        .instrument(db_span("UPDATE outbox"))
+        .await?;
+        tx.commit().await?;
+        Ok(rows)
+    }
+
+    /// 删除超过保留期的已发送行
+    async fn prune(&self) -> Result<u64, AppError> {
+        let result = sqlx::query(
+            "DELETE FROM outbox WHERE sent_at < CURRENT_TIMESTAMP - make_interval(secs => $1)",
+        )
+        .bind(self.config.retention.as_secs_f64())
+        .execute(&self.pool)
+        .instrument(db_span("DELETE outbox"))
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use futures_util::future::BoxFuture;
+    use crate::config::OffsetReset;
+    use crate::init::test_utils::TestDatabase;
+    use crate::kafka::bus::{BusError, DeliveryCallback, Subscription};
+    use crate::kafka::memory::MemoryBus;
+    use crate::kafka::producer::{DeliveryReport, ProduceError};
+
+    /// 在进程内总线外注入故障：`hang_after` 条之后永远挂起，模拟进程在批次中途退出
+    #[derive(Clone)]
+    struct FaultyBus {
+        inner: MemoryBus,
+        hang_after: Option<usize>,
+        fail_once: Arc<Mutex<HashSet<i64>>>,
+    }
+
+    impl FaultyBus {
+        fn new() -> Self {
+            Self {
+                inner: MemoryBus::new(1),
+                hang_after: None,
+                fail_once: Arc::default(),
+            }
+        }
+
+        fn delivered(&self) -> Vec<i64> {
+            self.inner
+                .messages("t")
+                .iter()
+                .map(|m| std::str::from_utf8(m.header(OUTBOX_ID_HEADER).unwrap()).unwrap().parse().unwrap())
+                .collect()
+        }
+    }
+
+    impl EventBus for FaultyBus {
+        fn publish(&self, record: ProducerRecord) -> BoxFuture<'_, Result<DeliveryReport, ProduceError>> {
+            Box::pin(async move {
+                let (_, id) = record.headers.iter().find(|(k, _)| k == OUTBOX_ID_HEADER).unwrap();
+                let id: i64 = std::str::from_utf8(id).unwrap().parse().unwrap();
+                if self.fail_once.lock().unwrap().remove(&id) {
+                    return Err(ProduceError::QueueFull);
+                }
+                if self.hang_after.is_some_and(|n| self.delivered().len() >= n) {
+                    std::future::pending::<()>().await;
+                }
+                self.inner.publish(record).await
+            })
+        }
+
+        fn publish_with_callback(&self, record: ProducerRecord, callback: DeliveryCallback) -> Result<(), ProduceError> {
+            self.inner.publish_with_callback(record, callback)
+        }
+
+        fn subscribe(
+            &self,
+            group: &str,
+            topics: &[String],
+            reset: OffsetReset,
+        ) -> Result<Arc<dyn Subscription>, BusError> {
+            self.inner.subscribe(group, topics, reset)
+        }
+
+        fn flush(&self, timeout: Duration) -> BoxFuture<'_, Result<(), ProduceError>> {
+            self.inner.flush(timeout)
+        }
+    }
+
+    fn test_config() -> OutboxConfig {
+        OutboxConfig {
+            poll_interval: Duration::from_millis(20),
+            retry_backoff: Duration::from_millis(20),
+            lease: Duration::from_millis(500),
+            ..OutboxConfig::default()
+        }
+    }
+
+    async fn enqueue_n(pool: &PgPool, n: usize) -> Vec<i64> {
+        let mut tx = pool.begin().await.unwrap();
+        let mut ids = Vec::new();
+        for i in 0..n {
+            let record = ProducerRecord::new("t", format!("m{}", i)).with_key("k");
+            ids.push(enqueue(&mut tx, &record).await.unwrap());
+        }
+        tx.commit().await.unwrap();
+        ids
+    }
+
+    async fn pending(pool: &PgPool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM outbox WHERE sent_at IS NULL")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn wait_until<F: Fn() -> bool>(condition: F) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for condition");
+    }
+
+    async fn wait_drained(pool: &PgPool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pending(pool).await > 0 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("outbox was not drained");
+    }
+
+    #[tokio::test]
+    async fn test_relay_resumes_after_crash_mid_batch() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+        let ids = enqueue_n(&pool, 5).await;
+
+        let crashing = FaultyBus { hang_after: Some(3), ..FaultyBus::new() };
+        let relay = tokio::spawn(OutboxRelay::new(pool.clone(), Arc::new(crashing.clone()), test_config()).run());
+        wait_until(|| crashing.delivered().len() == 3).await;
+        relay.abort();
+        let _ = relay.await;
+        // 批次没有标记，已发出的行也仍是待发送状态
+        assert_eq!(pending(&pool).await, 5);
+        let leased: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM outbox WHERE locked_until > CURRENT_TIMESTAMP")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(leased, 5);
+
+        // 租约过期后由其他实例从头重新发布
+        let bus = FaultyBus::new();
+        let relay = tokio::spawn(OutboxRelay::new(pool.clone(), Arc::new(bus.clone()), test_config()).run());
+        wait_drained(&pool).await;
+        relay.abort();
+        assert_eq!(bus.delivered(), ids);
+    }
+
+    #[tokio::test]
+    async fn test_failed_row_blocks_later_rows() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+        let ids = enqueue_n(&pool, 4).await;
+
+        let bus = FaultyBus::new();
+        bus.fail_once.lock().unwrap().insert(ids[2]);
+        let relay = tokio::spawn(OutboxRelay::new(pool.clone(), Arc::new(bus.clone()), test_config()).run());
+        wait_drained(&pool).await;
+        relay.abort();
+
+        assert_eq!(bus.delivered(), ids);
+        let (attempts, last_error): (i32, Option<String>) =
+            sqlx::query_as("SELECT attempts, last_error FROM outbox WHERE id = $1")
+                .bind(ids[2])
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(attempts, 2);
+        assert!(last_error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_prune_sent_rows() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+        let ids = enqueue_n(&pool, 3).await;
+        sqlx::query("UPDATE outbox SET sent_at = CURRENT_TIMESTAMP - INTERVAL '8 days' WHERE id = $1")
+            .bind(ids[0])
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE outbox SET sent_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(ids[1])
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let relay = OutboxRelay::new(pool.clone(), Arc::new(MemoryBus::new(1)), test_config());
+        assert_eq!(relay.prune().await.unwrap(), 1);
+        assert_eq!(pending(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_binary_header_roundtrip() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+        let mut tx = pool.begin().await.unwrap();
+        let record = ProducerRecord::new("t", "m").with_header("sig", vec![0xff, 0x00, 0xfe]);
+        enqueue(&mut tx, &record).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let bus = FaultyBus::new();
+        let relay = tokio::spawn(OutboxRelay::new(pool.clone(), Arc::new(bus.clone()), test_config()).run());
+        wait_drained(&pool).await;
+        relay.abort();
+
+        let messages = bus.inner.messages("t");
+        assert_eq!(messages[0].header("sig"), Some(&[0xff, 0x00, 0xfe][..]));
+    }
+}
@@ -0,0 +1,244 @@
+use std::future::Future;
+use std::time::Duration;
+use metrics::counter;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::util::Timeout;
+use crate::config::KafkaConfig;
+use crate::constant::REQUEST_ID_HEADER;
+use crate::request_id::RequestId;
+use crate::telemetry;
+
+/// 待发送的消息，持有自己的数据以便跨 await 和任务传递
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProducerRecord {
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// 为空时按 key 的 murmur2 哈希选择分区，没有 key 时随机
+    pub partition: Option<i32>,
+}
+
+impl ProducerRecord {
+    /// 在处理请求时创建的消息附带该请求的标识和链路
+    pub fn new(topic: impl Into<String>, payload: impl Into<Vec<u8>>) -> Self {
+        let mut headers = RequestId::current()
+            .map(|id| vec![(REQUEST_ID_HEADER.to_string(), id.as_str().as_bytes().to_vec())])
+            .unwrap_or_default();
+        telemetry::inject_kafka(&mut headers);
+        Self {
+            topic: topic.into(),
+            payload: payload.into(),
+            headers,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn with_partition(mut self, partition: i32) -> Self {
+        self.partition = Some(partition);
+        self
+    }
+
+    fn as_future_record(&self) -> FutureRecord<'_, [u8], [u8]> {
+        let headers = self.headers.iter().fold(OwnedHeaders::new(), |headers, (key, value)| {
+            headers.insert(Header { key, value: Some(value) })
+        });
+        let mut record = FutureRecord::to(&self.topic)
+            .payload(self.payload.as_slice())
+            .headers(headers);
+        if let Some(key) = &self.key {
+            record = record.key(key.as_slice());
+        }
+        if let Some(partition) = self.partition {
+            record = record.partition(partition);
+        }
+        record
+    }
+}
+
+/// broker 确认后的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryReport {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProduceError {
+    #[error("producer queue is full")]
+    QueueFull,
+    #[error("producer was dropped before delivery")]
+    Canceled,
+    #[error("Kafka error: {0}")]
+    Kafka(#[from] KafkaError),
+}
+
+impl ProduceError {
+    fn from_kafka(error: KafkaError) -> Self {
+        match error {
+            KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull) => ProduceError::QueueFull,
+            e => ProduceError::Kafka(e),
+        }
+    }
+}
+
+fn record_delivery(topic: &str, delivered: bool) {
+    let result = if delivered { "ok" } else { "error" };
+    counter!("kafka_messages_produced_total", "topic" => topic.to_string(), "result" => result).increment(1);
+}
+
+/// 长期持有的异步生产者，克隆后共享同一个底层连接和发送队列。
+///
+/// 消息先进入 librdkafka 的有界队列，由其后台线程按 `linger`/`batch_size` 凑批发送。
+/// 队满时 [`send`](Self::send) 最多等待 `enqueue_timeout`，
+/// [`try_send`](Self::try_send) 和 [`send_with_callback`](Self::send_with_callback)
+/// 立即返回 [`ProduceError::QueueFull`]，调用方据此限流。
+#[derive(Clone)]
+pub struct KafkaProducer {
+    producer: FutureProducer,
+    enqueue_timeout: Duration,
+}
+
+impl KafkaProducer {
+    /// 只创建客户端，不等待连接 broker
+    pub fn new(config: &KafkaConfig) -> Result<Self, KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("client.id", &config.client_id)
+            .set("linger.ms", config.linger.as_millis().to_string())
+            .set("batch.num.messages", config.batch_size.to_string())
+            .set("queue.buffering.max.messages", config.queue_capacity.to_string())
+            .set("message.timeout.ms", config.delivery_timeout.as_millis().to_string())
+            .set("acks", &config.acks)
+            // 与 Java 客户端相同的 key 分区算法，保证同一 key 落到同一分区
+            .set("partitioner", "murmur2_random")
+            .create()?;
+        Ok(Self {
+            producer,
+            enqueue_timeout: config.enqueue_timeout,
+        })
+    }
+
+    /// 入队并等待 broker 确认
+    pub async fn send(&self, record: ProducerRecord) -> Result<DeliveryReport, ProduceError> {
+        let result = self
+            .producer
+            .send(record.as_future_record(), Timeout::After(self.enqueue_timeout))
+            .await
+            .map_err(|(e, _)| ProduceError::from_kafka(e));
+        record_delivery(&record.topic, result.is_ok());
+        let (partition, offset) = result?;
+        Ok(DeliveryReport { topic: record.topic, partition, offset })
+    }
+
+    /// 立即入队，返回等待确认的 future；队满时不等待
+    pub fn try_send(
+        &self,
+        record: ProducerRecord,
+    ) -> Result<impl Future<Output = Result<DeliveryReport, ProduceError>>, ProduceError> {
+        let delivery = self
+            .producer
+            .send_result(record.as_future_record())
+            .map_err(|(e, _)| ProduceError::from_kafka(e))
+            .inspect_err(|_| record_delivery(&record.topic, false))?;
+        Ok(async move {
+            let result = delivery
+                .await
+                .map_err(|_| ProduceError::Canceled)
+                .and_then(|delivery| delivery.map_err(|(e, _)| ProduceError::from_kafka(e)));
+            record_delivery(&record.topic, result.is_ok());
+            let (partition, offset) = result?;
+            Ok(DeliveryReport { topic: record.topic, partition, offset })
+        })
+    }
+
+    /// 立即入队，投递成功或失败后在后台任务中调用 `callback`
+    pub fn send_with_callback<F>(&self, record: ProducerRecord, callback: F) -> Result<(), ProduceError>
+    where
+        F: FnOnce(Result<DeliveryReport, ProduceError>) + Send + 'static,
+    {
+        let delivery = self.try_send(record)?;
+        tokio::spawn(async move { callback(delivery.await) });
+        Ok(())
+    }
+
+    /// 等待队列中的消息全部发送完毕，用于停机前
+    pub async fn flush(&self, timeout: Duration) -> Result<(), KafkaError> {
+        let producer = self.producer.clone();
+        tokio::task::spawn_blocking(move || producer.flush(timeout))
+            .await
+            .unwrap_or(Err(KafkaError::Canceled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    /// 指向不可达的 broker，消息只会停留在本地队列直到投递超时
+    fn unreachable_config(queue_capacity: usize) -> KafkaConfig {
+        KafkaConfig {
+            brokers: "127.0.0.1:1".to_string(),
+            queue_capacity,
+            enqueue_timeout: Duration::from_millis(100),
+            delivery_timeout: Duration::from_millis(300),
+            ..KafkaConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_record_builder() {
+        let record = ProducerRecord::new("topic", b"payload".to_vec())
+            .with_key("42")
+            .with_header("x-request-id", "abc")
+            .with_partition(3);
+        let future_record = record.as_future_record();
+        assert_eq!(future_record.key, Some(b"42".as_slice()));
+        assert_eq!(future_record.partition, Some(3));
+        assert_eq!(future_record.payload, Some(b"payload".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_backpressure_when_queue_full() {
+        let producer = KafkaProducer::new(&unreachable_config(1)).unwrap();
+
+        let _first = producer.try_send(ProducerRecord::new("t", "a")).unwrap();
+        assert!(matches!(
+            producer.try_send(ProducerRecord::new("t", "b")),
+            Err(ProduceError::QueueFull)
+        ));
+        assert!(matches!(
+            producer.send(ProducerRecord::new("t", "c")).await,
+            Err(ProduceError::QueueFull)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_callback_reports_failure() {
+        let producer = KafkaProducer::new(&unreachable_config(10)).unwrap();
+        let (tx, rx) = oneshot::channel();
+
+        producer
+            .send_with_callback(ProducerRecord::new("t", "a").with_key("1"), move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), rx).await.unwrap().unwrap();
+        assert!(matches!(result, Err(ProduceError::Kafka(_))));
+    }
+}
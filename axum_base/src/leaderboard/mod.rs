@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use chrono::{DateTime, Utc};
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{AsyncCommands, RedisResult, Script};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::OnceCell;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn, Instrument};
+use utoipa::ToSchema;
+use crate::config::LeaderboardConfig;
+use crate::constant::{
+    LEADERBOARD_ALL_KEY, LEADERBOARD_HOUR_KEY_PREFIX, LEADERBOARD_RECONCILE_LOCK_KEY, LEADERBOARD_UNION_KEY,
+};
+use crate::error::AppError;
+use crate::protos::voting::top_voted_request;
+use crate::protos::voting::voting_request::Vote;
+use crate::telemetry::db_span;
+use crate::telemetry::redis::TracedConnection;
+
+pub const DEFAULT_TOP_LIMIT: u32 = 10;
+pub const MAX_TOP_LIMIT: u32 = 100;
+
+const HOUR_SECS: i64 = 3600;
+// 最长的滚动窗口，分桶在这之后过期
+const MAX_WINDOW_HOURS: i64 = 7 * 24;
+// 对账时每条 ZADD 携带的成员数
+const ZADD_CHUNK: usize = 1000;
+
+// 合并窗口内的分桶后取前 N 名，临时 key 在脚本结束前删除
+// KEYS: 临时 key, 各小时分桶
+// ARGV: 名次上限
+static TOP_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        redis.call('ZUNIONSTORE', KEYS[1], #KEYS - 1, unpack(KEYS, 2))
+        local top = redis.call('ZREVRANGE', KEYS[1], 0, tonumber(ARGV[1]) - 1, 'WITHSCORES')
+        redis.call('DEL', KEYS[1])
+        return top
+        ",
+    )
+});
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaderboardWindow {
+    #[default]
+    All,
+    Hour,
+    Day,
+    Week,
+}
+
+impl LeaderboardWindow {
+    /// 滚动窗口包含的小时分桶数，总榜为 `None`
+    fn hours(self) -> Option<i64> {
+        match self {
+            LeaderboardWindow::All => None,
+            LeaderboardWindow::Hour => Some(1),
+            LeaderboardWindow::Day => Some(24),
+            LeaderboardWindow::Week => Some(MAX_WINDOW_HOURS),
+        }
+    }
+}
+
+impl From<top_voted_request::Window> for LeaderboardWindow {
+    fn from(window: top_voted_request::Window) -> Self {
+        match window {
+            top_voted_request::Window::All => LeaderboardWindow::All,
+            top_voted_request::Window::Hour => LeaderboardWindow::Hour,
+            top_voted_request::Window::Day => LeaderboardWindow::Day,
+            top_voted_request::Window::Week => LeaderboardWindow::Week,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LeaderboardEntry {
+    pub url: String,
+    /// 赞成票数减反对票数
+    pub score: i64,
+}
+
+fn score_delta(vote: Vote) -> i64 {
+    match vote {
+        Vote::Up => 1,
+        Vote::Down => -1,
+    }
+}
+
+fn hour_of(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(HOUR_SECS)
+}
+
+fn bucket_key(hour: i64) -> String {
+    format!("{}{}", LEADERBOARD_HOUR_KEY_PREFIX, hour)
+}
+
+/// 分桶在最长窗口移出它之后过期
+fn bucket_expire_at(hour: i64) -> i64 {
+    (hour + MAX_WINDOW_HOURS + 1) * HOUR_SECS
+}
+
+/// 截至 `now` 的最近 `hours` 个小时分桶，当前小时计入窗口，因此窗口精度为一小时
+fn window_keys(now: DateTime<Utc>, hours: i64) -> Vec<String> {
+    let current = hour_of(now);
+    (current - hours + 1..=current).map(bucket_key).collect()
+}
+
+/// 基于 Redis 有序集合的投票排行榜。
+///
+/// 总榜和每个小时分桶各是一个 zset，成员为 URL，分数为赞成减反对；
+/// 滚动窗口查询时合并窗口内的分桶。投票提交后增量更新，失败只记录日志，
+/// 由定期的 [`Leaderboard::reconcile`] 以 Postgres 为准重建。
+pub struct Leaderboard {
+    client: redis::Client,
+    conn: OnceCell<ConnectionManager>,
+    config: LeaderboardConfig,
+}
+
+impl Leaderboard {
+    pub fn new(client: redis::Client, config: LeaderboardConfig) -> Self {
+        Self {
+            client,
+            conn: OnceCell::new(),
+            config,
+        }
+    }
+
+    async fn conn(&self) -> RedisResult<TracedConnection> {
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(0)
+            .set_connection_timeout(self.config.timeout);
+        self.conn
+            .get_or_try_init(|| self.client.get_connection_manager_with_config(config))
+            .await
+            .cloned()
+            .map(TracedConnection::new)
+    }
+
+    /// 计入一张已提交的投票，`at` 为投票的提交时间
+    pub async fn record(&self, url: &str, vote: Vote, at: DateTime<Utc>) {
+        let delta = score_delta(vote);
+        let hour = hour_of(at);
+        let bucket = bucket_key(hour);
+        let update = async {
+            let mut conn = self.conn().await?;
+            redis::pipe()
+                .atomic()
+                .zincr(LEADERBOARD_ALL_KEY, url, delta)
+                .ignore()
+                .zincr(&bucket, url, delta)
+                .ignore()
+                .expire_at(&bucket, bucket_expire_at(hour))
+                .ignore()
+                .query_async::<()>(&mut conn)
+                .await
+        };
+        match tokio::time::timeout(self.config.timeout, update).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!(url, "failed to update leaderboard: {}", e),
+            Err(_) => warn!(url, "timed out updating leaderboard"),
+        }
+    }
+
+    /// 按得分从高到低返回前 `limit` 个 URL
+    pub async fn top(&self, window: LeaderboardWindow, limit: u32) -> Result<Vec<LeaderboardEntry>, AppError> {
+        if limit == 0 || limit > MAX_TOP_LIMIT {
+            return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_TOP_LIMIT)));
+        }
+        let mut conn = self.conn().await?;
+        let entries: Vec<(String, f64)> = match window.hours() {
+            None => conn.zrevrange_withscores(LEADERBOARD_ALL_KEY, 0, limit as isize - 1).await?,
+            Some(hours) => {
+                let mut invocation = TOP_SCRIPT.prepare_invoke();
+                invocation.key(LEADERBOARD_UNION_KEY);
+                for key in window_keys(Utc::now(), hours) {
+                    invocation.key(key);
+                }
+                invocation.arg(limit).invoke_async(&mut conn).await?
+            }
+        };
+        Ok(entries
+            .into_iter()
+            .map(|(url, score)| LeaderboardEntry { url, score: score as i64 })
+            .collect())
+    }
+
+    /// 用 Postgres 中的投票重建总榜和最长窗口内的分桶，返回总榜的 URL 数。
+    ///
+    /// 重建在一个 MULTI 中完成，读取方不会看到中间状态。查询 Postgres 与写入 Redis
+    /// 之间提交的投票可能被多计或漏计，误差保留到下一次对账。
+    pub async fn reconcile(&self, pool: &PgPool) -> Result<usize, AppError> {
+        let current = hour_of(Utc::now());
+        let first = current - MAX_WINDOW_HOURS + 1;
+        let totals = sqlx::query_as::<_, (String, i64)>(
+            r"
+            SELECT url, SUM(CASE WHEN vote = 0 THEN 1 ELSE -1 END)::BIGINT
+            FROM votes
+            GROUP BY url
+            ",
+        )
+        .fetch_all(pool)
+        .instrument(db_span("SELECT votes"))
+        .await?;
+        let hourly = sqlx::query_as::<_, (String, i64, i64)>(
+            r"
+            SELECT url, FLOOR(EXTRACT(EPOCH FROM created_at) / 3600)::BIGINT AS hour,
+                   SUM(CASE WHEN vote = 0 THEN 1 ELSE -1 END)::BIGINT
+            FROM votes
+            WHERE created_at >= $1
+            GROUP BY url, hour
+            ",
+        )
+        .bind(DateTime::from_timestamp(first * HOUR_SECS, 0))
+        .fetch_all(pool)
+        .instrument(db_span("SELECT votes"))
+        .await?;
+
+        let mut buckets: HashMap<i64, Vec<(i64, String)>> = HashMap::new();
+        for (url, hour, score) in hourly {
+            buckets.entry(hour).or_default().push((score, url));
+        }
+        let totals: Vec<(i64, String)> = totals.into_iter().map(|(url, score)| (score, url)).collect();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.del(LEADERBOARD_ALL_KEY).ignore();
+        for chunk in totals.chunks(ZADD_CHUNK) {
+            pipe.zadd_multiple(LEADERBOARD_ALL_KEY, chunk).ignore();
+        }
+        for hour in first..=current {
+            pipe.del(bucket_key(hour)).ignore();
+        }
+        for (hour, scores) in &buckets {
+            let key = bucket_key(*hour);
+            for chunk in scores.chunks(ZADD_CHUNK) {
+                pipe.zadd_multiple(&key, chunk).ignore();
+            }
+            pipe.expire_at(&key, bucket_expire_at(*hour)).ignore();
+        }
+        let mut conn = self.conn().await?;
+        pipe.query_async::<()>(&mut conn).await?;
+        Ok(totals.len())
+    }
+
+    /// 每个对账周期只有一个实例能拿到锁
+    async fn try_lock(&self) -> RedisResult<bool> {
+        let mut conn = self.conn().await?;
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(LEADERBOARD_RECONCILE_LOCK_KEY)
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(self.config.reconcile_interval.as_millis() as u64)
+            .query_async(&mut conn)
+            .await?;
+        Ok(acquired.is_some())
+    }
+
+    /// 启动时和之后每个 `reconcile_interval` 对账一次，直到进程退出
+    pub async fn run_reconciler(self: Arc<Self>, pool: PgPool) {
+        let mut interval = tokio::time::interval(self.config.reconcile_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match self.try_lock().await {
+                Ok(true) => match self.reconcile(&pool).await {
+                    Ok(urls) => info!(urls, "leaderboard reconciled"),
+                    Err(e) => warn!("failed to reconcile leaderboard: {}", e),
+                },
+                Ok(false) => {}
+                Err(e) => warn!("failed to acquire leaderboard reconcile lock: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::test_utils::{TestDatabase, TestRedis};
+
+    #[test]
+    fn test_window_keys() {
+        let now = DateTime::from_timestamp(10 * HOUR_SECS + 59, 0).unwrap();
+        assert_eq!(window_keys(now, 1), vec![bucket_key(10)]);
+        let keys = window_keys(now, 24);
+        assert_eq!(keys.len(), 24);
+        assert_eq!((keys[0].as_str(), keys[23].as_str()), ("leaderboard:hour:-13", "leaderboard:hour:10"));
+        assert_eq!(bucket_expire_at(10), (10 + MAX_WINDOW_HOURS + 1) * HOUR_SECS);
+    }
+
+    #[tokio::test]
+    async fn test_record_top_and_reconcile() {
+        let test_db = TestDatabase::new().await;
+        let test_redis = TestRedis::new().await;
+        let leaderboard = Leaderboard::new(test_redis.client.clone(), LeaderboardConfig::default());
+        let now = Utc::now();
+
+        for (url, vote, at) in [
+            ("a", Vote::Up, now),
+            ("a", Vote::Up, now),
+            ("b", Vote::Up, now),
+            ("b", Vote::Down, now),
+            ("c", Vote::Up, now - chrono::Duration::hours(3)),
+            ("c", Vote::Up, now - chrono::Duration::hours(3)),
+            ("c", Vote::Up, now - chrono::Duration::hours(3)),
+        ] {
+            sqlx::query("INSERT INTO votes (url, vote, created_at) VALUES ($1, $2, $3)")
+                .bind(url)
+                .bind(vote as i16)
+                .bind(at)
+                .execute(&test_db.pool)
+                .await
+                .unwrap();
+            leaderboard.record(url, vote, at).await;
+        }
+
+        let entry = |url: &str, score| LeaderboardEntry { url: url.into(), score };
+        let all = leaderboard.top(LeaderboardWindow::All, 2).await.unwrap();
+        assert_eq!(all, vec![entry("c", 3), entry("a", 2)]);
+        let hour = leaderboard.top(LeaderboardWindow::Hour, 10).await.unwrap();
+        assert_eq!(hour, vec![entry("a", 2), entry("b", 0)]);
+        assert!(leaderboard.top(LeaderboardWindow::All, 0).await.is_err());
+
+        // Redis 中漏计和多计的票在对账后以 Postgres 为准
+        let mut conn = test_redis.client.get_multiplexed_async_connection().await.unwrap();
+        let _: () = conn.zincr(LEADERBOARD_ALL_KEY, "d", 5).await.unwrap();
+        let _: () = conn.del(bucket_key(hour_of(now))).await.unwrap();
+        assert_eq!(leaderboard.reconcile(&test_db.pool).await.unwrap(), 3);
+        let all = leaderboard.top(LeaderboardWindow::All, 10).await.unwrap();
+        assert_eq!(all, vec![entry("c", 3), entry("a", 2), entry("b", 0)]);
+        let day = leaderboard.top(LeaderboardWindow::Day, 10).await.unwrap();
+        assert_eq!(day, all);
+        let hour = leaderboard.top(LeaderboardWindow::Hour, 10).await.unwrap();
+        assert_eq!(hour, vec![entry("a", 2), entry("b", 0)]);
+    }
+}
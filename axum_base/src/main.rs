@@ -1,3 +1,6 @@
+mod analytics;
+mod audit;
+mod cache;
 mod config;
 mod constant;
 mod init;
@@ -6,33 +9,72 @@ mod model;
 mod error;
 mod controller;
 mod kafka;
+mod event;
 mod grpc;
+mod idempotency;
+mod leaderboard;
+mod metrics;
 mod protos;
+mod ratelimit;
+mod request_id;
+mod telemetry;
 
 mod websocket;
 
 use anyhow::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
 
 use tracing::{info};
+use axum::extract::FromRef;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
+use crate::config::VotingConfig;
+use crate::controller::auth::{AdminIds, JwtSecret};
 use crate::controller::user_controller::ApiDoc;
+use crate::grpc::analytics::AnalyticsService;
+use crate::grpc::users::UserService;
+use crate::grpc::AdminInterceptor;
+use crate::grpc::voting::VotingService;
+use crate::init::app_state::AppState;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init::initialize::init();
+    let _log_guard = init::initialize::init();
 
     let api_doc = ApiDoc::openapi();
 
-    let app = route::api::api_router().await?
+    let state = AppState::new().await?;
+    let app = route::api::api_router(state.clone()).await?
         .merge(SwaggerUi::new("/swagger-ui")
             .url("/api-docs/openapi.json", api_doc));
 
+    let voting = VotingConfig::from_env();
+    info!("gRPC listening on {}", voting.addr);
+    tokio::spawn(grpc::serve(
+        voting.addr,
+        VotingService::new(state.pool.clone(), voting.topic, state.leaderboard.clone()),
+        AnalyticsService::new(state.analytics.clone()),
+        UserService::new(state.pool.clone()),
+        AdminInterceptor::new(JwtSecret::from_ref(&state), AdminIds::from_ref(&state)),
+    ));
+
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await?;
     info!("listening on {}", listener.local_addr()?);
     info!("swagger-ui: http://127.0.0.1:3000/swagger-ui");
 
-    axum::serve(listener, app.into_make_service()).await?;
-    
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    // 停机前把队列中的事件发送完
+    info!("flushing event bus");
+    state.event_bus.flush(Duration::from_secs(5)).await?;
+    telemetry::shutdown().await;
+
     Ok(())
 }
+
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use ::metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit};
+use axum::extract::{MatchedPath, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{Request, Response};
+use axum::response::IntoResponse;
+use futures_util::future::BoxFuture;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+use tonic::codegen::http as grpc_http;
+use tower::{Layer, Service};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::Context as LayerContext;
+use crate::controller::auth::MetricsScraper;
+use crate::init::app_state::AppState;
+use crate::websocket::WsMetricsSnapshot;
+
+/// 与 Prometheus 客户端默认的直方图分桶相同，单位为秒
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+/// 把直方图的样本合并到分桶中的间隔
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+/// sqlx 每次从连接池获取到连接时发出的事件
+const POOL_ACQUIRE_TARGET: &str = "sqlx::pool::acquire";
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// 安装全局的 Prometheus recorder，安装前记录的指标会被丢弃
+pub fn install() -> &'static PrometheusHandle {
+    HANDLE.get_or_init(|| {
+        let handle = PrometheusBuilder::new()
+            .set_buckets(LATENCY_BUCKETS)
+            .expect("buckets are not empty")
+            .install_recorder()
+            .expect("no other metrics recorder is installed");
+        describe();
+        handle
+    })
+}
+
+fn describe() {
+    describe_counter!("http_requests_total", "HTTP requests by matched route and status");
+    describe_histogram!("http_request_duration_seconds", Unit::Seconds, "HTTP request latency until response headers");
+    describe_counter!("grpc_requests_total", "gRPC calls by method and status code");
+    describe_histogram!("grpc_request_duration_seconds", Unit::Seconds, "gRPC call latency");
+    describe_gauge!("ws_connections_active", "Open WebSocket connections");
+    describe_gauge!("sse_streams_active", "Open SSE streams");
+    describe_counter!("ws_broadcast_lag_events_total", "Times a subscriber fell behind the room broadcast");
+    describe_counter!("ws_broadcast_missed_messages_total", "Broadcast messages skipped by lagging subscribers");
+    describe_counter!("ws_connections_reaped_total", "WebSocket connections closed by the server");
+    describe_gauge!("db_pool_connections", "Open database connections");
+    describe_gauge!("db_pool_idle_connections", "Idle database connections");
+    describe_gauge!("db_pool_max_connections", "Maximum database connections");
+    describe_histogram!("db_pool_acquire_duration_seconds", Unit::Seconds, "Time spent waiting for a pooled database connection");
+    describe_histogram!("redis_command_duration_seconds", Unit::Seconds, "Redis command and pipeline latency");
+    describe_counter!("redis_command_errors_total", "Failed Redis commands and pipelines");
+    describe_counter!("kafka_messages_produced_total", "Kafka messages acknowledged or rejected by the broker");
+    describe_counter!("kafka_messages_consumed_total", "Kafka messages handled by consumers");
+    describe_histogram!("kafka_consume_duration_seconds", Unit::Seconds, "Time to handle a consumed message including retries");
+    describe_gauge!("kafka_consumer_lag", "Messages between the consumer position and the partition high watermark");
+}
+
+/// 定期合并直方图样本，避免长时间没有抓取时样本堆积
+pub async fn run_upkeep() {
+    let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        install().run_upkeep();
+    }
+}
+
+/// Prometheus 文本格式的指标，需要 `METRICS_TOKEN` 或管理员的 token
+pub(crate) async fn metrics_handler(_scraper: MetricsScraper, State(state): State<AppState>) -> impl IntoResponse {
+    record_ws(&state.ws.metrics().snapshot());
+    record_pool(&state.pool);
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], install().render())
+}
+
+/// WebSocket 的计数由 [`WsMetrics`](crate::websocket::WsMetrics) 维护，抓取时同步过来
+fn record_ws(snapshot: &WsMetricsSnapshot) {
+    gauge!("ws_connections_active").set(snapshot.active_connections as f64);
+    gauge!("sse_streams_active").set(snapshot.active_sse_streams as f64);
+    counter!("ws_broadcast_lag_events_total").absolute(snapshot.lag_events);
+    counter!("ws_broadcast_missed_messages_total").absolute(snapshot.missed_messages);
+    for (reason, count) in [
+        ("pong_timeout", snapshot.reaped_pong_timeout),
+        ("idle", snapshot.reaped_idle),
+        ("lagged", snapshot.reaped_lagged),
+    ] {
+        counter!("ws_connections_reaped_total", "reason" => reason).absolute(count);
+    }
+}
+
+fn record_pool(pool: &PgPool) {
+    gauge!("db_pool_connections").set(pool.size() as f64);
+    gauge!("db_pool_idle_connections").set(pool.num_idle() as f64);
+    gauge!("db_pool_max_connections").set(pool.options().get_max_connections() as f64);
+}
+
+/// 把 sqlx 获取连接的事件记为 `db_pool_acquire_duration_seconds` 直方图。
+///
+/// 连接池需要用 `acquire_time_level` 打开这些事件；经由 [`filter`](Self::filter) 接收，不受日志过滤规则影响。
+pub struct PoolAcquireLayer;
+
+impl PoolAcquireLayer {
+    pub fn filter() -> Targets {
+        Targets::new().with_target(POOL_ACQUIRE_TARGET, tracing::Level::TRACE)
+    }
+}
+
+/// 取出事件中的等待时间，sqlx 的字段名拼写为 `aquired_after_secs`
+struct AcquiredAfter(Option<f64>);
+
+impl Visit for AcquiredAfter {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "aquired_after_secs" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+impl<S: Subscriber> tracing_subscriber::Layer<S> for PoolAcquireLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        if event.metadata().target() != POOL_ACQUIRE_TARGET {
+            return;
+        }
+        let mut acquired_after = AcquiredAfter(None);
+        event.record(&mut acquired_after);
+        if let Some(secs) = acquired_after.0 {
+            histogram!("db_pool_acquire_duration_seconds").record(secs);
+        }
+    }
+}
+
+/// 按匹配到的路由模板统计请求数和延迟，没有匹配的路由记为 `unmatched`，避免路径参数产生大量时间序列。
+///
+/// 需要通过 `Router::layer` 添加，路由匹配后才能取到 [`MatchedPath`]。
+#[derive(Clone, Default)]
+pub struct HttpMetricsLayer;
+
+impl<S> Layer<S> for HttpMetricsLayer {
+    type Service = HttpMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpMetricsService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct HttpMetricsService<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<Request<B>> for HttpMetricsService<S>
+where
+    S: Service<Request<B>, Response = Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let method = request.method().to_string();
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or_else(|| "unmatched".to_string(), |path| path.as_str().to_string());
+        let start = Instant::now();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+            let labels = [
+                ("method", method),
+                ("route", route),
+                ("status", response.status().as_u16().to_string()),
+            ];
+            counter!("http_requests_total", &labels).increment(1);
+            histogram!("http_request_duration_seconds", &labels).record(start.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
+
+/// 按方法统计 gRPC 调用数、状态码和延迟
+#[derive(Clone, Default)]
+pub struct GrpcMetricsLayer;
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+    type Service = GrpcMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcMetricsService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct GrpcMetricsService<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<grpc_http::Request<B>> for GrpcMetricsService<S>
+where
+    S: Service<grpc_http::Request<B>, Response = grpc_http::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = grpc_http::Response<R>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: grpc_http::Request<B>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let start = Instant::now();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+            // 失败的调用只有响应头，状态码在头中；成功的调用状态码在 trailer 中，一定是 0
+            let code = response
+                .headers()
+                .get("grpc-status")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("0")
+                .to_string();
+            counter!("grpc_requests_total", "method" => method.clone(), "code" => code).increment(1);
+            histogram!("grpc_request_duration_seconds", "method" => method).record(start.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_http_metrics() {
+        let handle = install();
+        let router = Router::new()
+            .route("/metrics-test/{id}", get(|| async { "ok" }))
+            .layer(HttpMetricsLayer);
+        for uri in ["/metrics-test/1", "/metrics-test/2", "/metrics-test-missing"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let output = handle.render();
+        assert!(output.contains(r#"http_requests_total{method="GET",route="/metrics-test/{id}",status="200"} 2"#));
+        assert!(output.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"}"#));
+        assert!(output.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/metrics-test/{id}",status="200",le="0.005"}"#));
+    }
+
+    #[test]
+    fn test_pool_acquire_layer() {
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::Layer as _;
+
+        let handle = install();
+        let subscriber = tracing_subscriber::registry().with(PoolAcquireLayer.with_filter(PoolAcquireLayer::filter()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::trace!(target: "sqlx::pool::acquire", aquired_after_secs = 0.02, "acquired connection");
+            tracing::trace!(target: "other", aquired_after_secs = 0.02, "ignored");
+        });
+        let output = handle.render();
+        assert!(output.contains("db_pool_acquire_duration_seconds_count 1"));
+        assert!(output.contains(r#"db_pool_acquire_duration_seconds_bucket{le="0.025"} 1"#));
+    }
+
+    #[test]
+    fn test_record_ws() {
+        let handle = install();
+        record_ws(&WsMetricsSnapshot {
+            active_connections: 3,
+            active_sse_streams: 1,
+            reaped_pong_timeout: 0,
+            reaped_idle: 2,
+            reaped_lagged: 0,
+            lag_events: 4,
+            missed_messages: 40,
+        });
+        let output = handle.render();
+        assert!(output.contains("ws_connections_active 3"));
+        assert!(output.contains("ws_broadcast_missed_messages_total 40"));
+        assert!(output.contains(r#"ws_connections_reaped_total{reason="idle"} 2"#));
+    }
+}
@@ -0,0 +1,237 @@
+use std::borrow::Cow;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use tracing::Instrument;
+use utoipa::ToSchema;
+use validator::{ValidationError, ValidationErrors};
+use crate::error::AppError;
+use crate::model::cursor::{fetch_page, CursorCodec, CursorPage, CursorQuery, Keyset, Order};
+use crate::telemetry::db_span;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, sqlx::FromRow, PartialEq)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub room: String,
+    pub sender_id: i32,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SendChatMessage {
+    pub content: String,
+}
+
+/// 推送给房间内连接的聊天事件
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum ChatEvent {
+    #[serde(rename = "chat.message")]
+    Created { message: ChatMessage },
+    #[serde(rename = "chat.edited")]
+    Edited { message: ChatMessage },
+    #[serde(rename = "chat.deleted")]
+    Deleted { id: i64, room: String },
+}
+
+impl ChatEvent {
+    pub fn room(&self) -> &str {
+        match self {
+            ChatEvent::Created { message } | ChatEvent::Edited { message } => &message.room,
+            ChatEvent::Deleted { room, .. } => room,
+        }
+    }
+}
+
+fn validation_error(field: &'static str, code: &'static str, message: String) -> AppError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Owned(message));
+    let mut errors = ValidationErrors::new();
+    errors.add(field, error);
+    AppError::Validation(errors)
+}
+
+/// 内容不能为空白，且不超过 `max_len` 个字符
+pub(crate) fn check_content(content: &str, max_len: usize) -> Result<(), AppError> {
+    if content.trim().is_empty() {
+        return Err(validation_error("content", "required", "content must not be empty".into()));
+    }
+    if content.chars().count() > max_len {
+        return Err(validation_error(
+            "content",
+            "length",
+            format!("content must be at most {} characters", max_len),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_room(room: &str) -> Result<(), AppError> {
+    if room.is_empty() || room.len() > 100 {
+        return Err(validation_error(
+            "room",
+            "length",
+            "room length must be between 1 and 100".into(),
+        ));
+    }
+    Ok(())
+}
+
+impl Keyset for ChatMessage {
+    type Key = (i64,);
+    const KEY_COLUMNS: &'static [&'static str] = &["id"];
+    const ORDER: Order = Order::Desc;
+
+    fn key(&self) -> Self::Key {
+        (self.id,)
+    }
+}
+
+impl ChatMessage {
+    pub(crate) async fn insert(
+        pool: &PgPool,
+        room: &str,
+        sender_id: i32,
+        content: &str,
+    ) -> Result<ChatMessage, AppError> {
+        let message = sqlx::query_as::<_, ChatMessage>(
+            r"
+            INSERT INTO chat_messages (room, sender_id, content)
+            VALUES ($1, $2, $3)
+            RETURNING id, room, sender_id, content, created_at, edited_at
+            ",
+        )
+        .bind(room)
+        .bind(sender_id)
+        .bind(content)
+        .fetch_one(pool)
+        .instrument(db_span("INSERT chat_messages"))
+        .await?;
+        Ok(message)
+    }
+
+    /// 按 id 倒序翻页，游标只在同一房间内有效
+    pub(crate) async fn history(
+        pool: &PgPool,
+        codec: &CursorCodec,
+        room: &str,
+        request: &CursorQuery,
+        limit: i64,
+    ) -> Result<CursorPage<ChatMessage>, AppError> {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "SELECT id, room, sender_id, content, created_at, edited_at FROM chat_messages WHERE room = ",
+        );
+        query.push_bind(room.to_string());
+        fetch_page(pool, query, codec, &format!("chat:{}", room), request, limit).await
+    }
+
+    async fn sender_of(pool: &PgPool, id: i64) -> Result<i32, AppError> {
+        sqlx::query_scalar::<_, i32>("SELECT sender_id FROM chat_messages WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .instrument(db_span("SELECT chat_messages"))
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat message {} not found", id)))
+    }
+
+    /// 只能修改自己发送的消息
+    pub(crate) async fn update_content(
+        pool: &PgPool,
+        id: i64,
+        sender_id: i32,
+        content: &str,
+    ) -> Result<ChatMessage, AppError> {
+        if Self::sender_of(pool, id).await? != sender_id {
+            return Err(AppError::Forbidden);
+        }
+        let message = sqlx::query_as::<_, ChatMessage>(
+            r"
+            UPDATE chat_messages
+            SET content = $3, edited_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND sender_id = $2
+            RETURNING id, room, sender_id, content, created_at, edited_at
+            ",
+        )
+        .bind(id)
+        .bind(sender_id)
+        .bind(content)
+        .fetch_optional(pool)
+        .instrument(db_span("UPDATE chat_messages"))
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("chat message {} not found", id)))?;
+        Ok(message)
+    }
+
+    /// 只能删除自己发送的消息，返回消息所在房间
+    pub(crate) async fn delete(pool: &PgPool, id: i64, sender_id: i32) -> Result<String, AppError> {
+        if Self::sender_of(pool, id).await? != sender_id {
+            return Err(AppError::Forbidden);
+        }
+        let room = sqlx::query_scalar::<_, String>(
+            "DELETE FROM chat_messages WHERE id = $1 AND sender_id = $2 RETURNING room",
+        )
+        .bind(id)
+        .bind(sender_id)
+        .fetch_optional(pool)
+        .instrument(db_span("DELETE chat_messages"))
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("chat message {} not found", id)))?;
+        Ok(room)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::test_utils::TestDatabase;
+
+    #[test]
+    fn test_check_content() {
+        assert!(check_content("hello", 5).is_ok());
+        assert!(check_content("你好世界", 4).is_ok());
+        assert!(check_content("hello!", 5).is_err());
+        assert!(check_content("   ", 5).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_edit_and_delete_own_message() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+
+        // 初始化脚本中已有 id 为 0 的 admin 用户
+        let message = ChatMessage::insert(&pool, "lobby", 0, "hello").await.unwrap();
+        ChatMessage::insert(&pool, "lobby", 0, "world").await.unwrap();
+
+        let codec = CursorCodec::new("secret");
+        let page = ChatMessage::history(&pool, &codec, "lobby", &CursorQuery::default(), 1).await.unwrap();
+        assert_eq!(page.records[0].content, "world");
+        assert!(page.prev_cursor.is_none());
+        let request = CursorQuery { after: page.next_cursor, ..CursorQuery::default() };
+        let older = ChatMessage::history(&pool, &codec, "lobby", &request, 1).await.unwrap();
+        assert_eq!(older.records, vec![message.clone()]);
+        assert!(older.next_cursor.is_none());
+        let request = CursorQuery { before: older.prev_cursor, ..CursorQuery::default() };
+        let newer = ChatMessage::history(&pool, &codec, "lobby", &request, 10).await.unwrap();
+        assert_eq!(newer.records[0].content, "world");
+        assert!(newer.prev_cursor.is_none() && newer.next_cursor.is_some());
+        // 游标不能用于其他房间
+        let request = CursorQuery { before: newer.next_cursor, ..CursorQuery::default() };
+        assert!(ChatMessage::history(&pool, &codec, "other", &request, 10).await.is_err());
+
+        assert!(matches!(
+            ChatMessage::update_content(&pool, message.id, 1, "hijack").await,
+            Err(AppError::Forbidden)
+        ));
+        let edited = ChatMessage::update_content(&pool, message.id, 0, "hello!").await.unwrap();
+        assert_eq!(edited.content, "hello!");
+        assert!(edited.edited_at.is_some());
+
+        assert_eq!(ChatMessage::delete(&pool, message.id, 0).await.unwrap(), "lobby");
+        assert!(matches!(
+            ChatMessage::delete(&pool, message.id, 0).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
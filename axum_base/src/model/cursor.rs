@@ -0,0 +1,209 @@
+use std::sync::Arc;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::postgres::PgRow;
+use sqlx::{Encode, FromRow, PgPool, Postgres, QueryBuilder, Type};
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+use crate::error::AppError;
+use crate::telemetry::db_span;
+
+/// 未指定 `limit` 时每页的条数
+pub const DEFAULT_LIMIT: i64 = 20;
+/// 每页的最大条数
+pub const MAX_LIMIT: i64 = 100;
+
+/// 列表的排列方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Order {
+    Asc,
+    Desc,
+}
+
+/// 可以按排序键翻页的行，所有排序列按同一方向排列
+pub(crate) trait Keyset: for<'r> FromRow<'r, PgRow> + Send + Unpin {
+    /// 各分量依次对应 `KEY_COLUMNS`，组合起来必须唯一且不为 NULL
+    type Key: BindKey + Serialize + DeserializeOwned;
+    const KEY_COLUMNS: &'static [&'static str];
+    const ORDER: Order;
+
+    fn key(&self) -> Self::Key;
+}
+
+/// 把排序键的各分量依次绑定为查询参数
+pub(crate) trait BindKey {
+    fn push_binds(self, query: &mut QueryBuilder<'static, Postgres>);
+}
+
+macro_rules! impl_bind_key {
+    ($($name:ident),+) => {
+        impl<$($name),+> BindKey for ($($name,)+)
+        where
+            $($name: for<'q> Encode<'q, Postgres> + Type<Postgres> + 'static),+
+        {
+            #[allow(non_snake_case)]
+            fn push_binds(self, query: &mut QueryBuilder<'static, Postgres>) {
+                let ($($name,)+) = self;
+                let mut values = query.separated(", ");
+                $(values.push_bind($name);)+
+            }
+        }
+    };
+}
+
+impl_bind_key!(A);
+impl_bind_key!(A, B);
+impl_bind_key!(A, B, C);
+
+/// 对游标签名，客户端只能原样传回服务端生成的游标。
+///
+/// 游标只在生成它的列表（`scope`）中有效，例如另一个聊天室的游标会被拒绝。
+#[derive(Clone)]
+pub(crate) struct CursorCodec {
+    secret: Arc<[u8]>,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+impl CursorCodec {
+    pub(crate) fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self { secret: secret.as_ref().into() }
+    }
+
+    fn mac(&self, scope: &str, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        // 与同一密钥的其他用途区分开
+        mac.update(b"cursor\0");
+        mac.update(scope.as_bytes());
+        mac.update(b"\0");
+        mac.update(payload);
+        mac
+    }
+
+    pub(crate) fn encode<K: Serialize>(&self, scope: &str, key: &K) -> String {
+        let payload = serde_json::to_vec(key).expect("cursor keys serialize to JSON");
+        let tag = self.mac(scope, &payload).finalize().into_bytes();
+        format!("{}.{}", URL_SAFE_NO_PAD.encode(&payload), URL_SAFE_NO_PAD.encode(tag))
+    }
+
+    pub(crate) fn decode<K: DeserializeOwned>(&self, scope: &str, cursor: &str) -> Result<K, AppError> {
+        let invalid = || AppError::BadRequest("invalid cursor".into());
+        let (payload, tag) = cursor.split_once('.').ok_or_else(invalid)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).map_err(|_| invalid())?;
+        self.mac(scope, &payload).verify_slice(&tag).map_err(|_| invalid())?;
+        serde_json::from_slice(&payload).map_err(|_| invalid())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CursorQuery {
+    /// 上一页的 `next_cursor`，返回排在它之后的记录
+    pub after: Option<String>,
+    /// 上一页的 `prev_cursor`，返回排在它之前的记录，不能与 `after` 同时使用
+    pub before: Option<String>,
+    /// 每页条数
+    pub limit: Option<i64>,
+}
+
+impl CursorQuery {
+    pub(crate) fn limit(&self, default: i64, max: i64) -> i64 {
+        // `clamp` 在 max < 1 时会 panic
+        self.limit.unwrap_or(default).clamp(1, max.max(1))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CursorPage<T> {
+    pub records: Vec<T>,
+    /// 后面还有记录时返回，作为下一页的 `after`
+    pub next_cursor: Option<String>,
+    /// 前面还有记录时返回，作为上一页的 `before`
+    pub prev_cursor: Option<String>,
+}
+
+/// 按排序键取一页记录。
+///
+/// `query` 是以 WHERE 条件结尾的 SELECT 语句，这里追加排序键条件、ORDER BY 和 LIMIT。
+/// 多取一条来判断是否还有更多记录。
+pub(crate) async fn fetch_page<T: Keyset>(
+    pool: &PgPool,
+    mut query: QueryBuilder<'static, Postgres>,
+    codec: &CursorCodec,
+    scope: &str,
+    request: &CursorQuery,
+    limit: i64,
+) -> Result<CursorPage<T>, AppError> {
+    let (backward, cursor) = match (&request.after, &request.before) {
+        (Some(_), Some(_)) => return Err(AppError::BadRequest("after and before cannot be used together".into())),
+        (None, Some(cursor)) => (true, Some(cursor)),
+        (after, None) => (false, after.as_ref()),
+    };
+    // 向前翻页时反向查询，取到后再翻转回列表顺序
+    let ascending = (T::ORDER == Order::Asc) != backward;
+    if let Some(cursor) = cursor {
+        let key: T::Key = codec.decode(scope, cursor)?;
+        let op = if ascending { ">" } else { "<" };
+        query.push(format!(" AND ({}) {} (", T::KEY_COLUMNS.join(", "), op));
+        key.push_binds(&mut query);
+        query.push(")");
+    }
+    let direction = if ascending { "ASC" } else { "DESC" };
+    let order_by: Vec<_> = T::KEY_COLUMNS.iter().map(|c| format!("{} {}", c, direction)).collect();
+    query.push(" ORDER BY ").push(order_by.join(", "));
+    query.push(" LIMIT ").push_bind(limit + 1);
+
+    let mut records: Vec<T> = query.build_query_as().fetch_all(pool).instrument(db_span("SELECT")).await?;
+    let has_more = records.len() as i64 > limit;
+    records.truncate(limit as usize);
+    if backward {
+        records.reverse();
+    }
+    // 从游标处翻页时，游标所在的一侧一定还有记录
+    let (has_next, has_prev) = if backward {
+        (cursor.is_some(), has_more)
+    } else {
+        (has_more, cursor.is_some())
+    };
+    let encode = |record: Option<&T>| record.map(|r| codec.encode(scope, &r.key()));
+    Ok(CursorPage {
+        next_cursor: encode(records.last().filter(|_| has_next)),
+        prev_cursor: encode(records.first().filter(|_| has_prev)),
+        records,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_codec() {
+        let codec = CursorCodec::new("secret");
+        let cursor = codec.encode("users", &(42i64, "bob".to_string()));
+        let key: (i64, String) = codec.decode("users", &cursor).unwrap();
+        assert_eq!(key, (42, "bob".to_string()));
+
+        assert!(codec.decode::<(i64, String)>("chat:lobby", &cursor).is_err());
+        assert!(CursorCodec::new("other").decode::<(i64, String)>("users", &cursor).is_err());
+        let (_, tag) = cursor.split_once('.').unwrap();
+        let forged = format!("{}.{}", URL_SAFE_NO_PAD.encode(br#"[41,"bob"]"#), tag);
+        assert!(codec.decode::<(i64, String)>("users", &forged).is_err());
+        assert!(codec.decode::<(i64, String)>("users", "garbage").is_err());
+    }
+
+    #[test]
+    fn test_cursor_query_limit() {
+        let query = |limit| CursorQuery { limit, ..CursorQuery::default() };
+        assert_eq!(query(None).limit(50, 100), 50);
+        assert_eq!(query(Some(500)).limit(50, 100), 100);
+        assert_eq!(query(Some(0)).limit(50, 100), 1);
+        // 配置错误的上限不会 panic
+        assert_eq!(query(Some(10)).limit(50, 0), 1);
+    }
+}
@@ -1 +1,5 @@
 pub mod user;
+pub mod chat;
+pub mod cursor;
+pub mod search;
+pub mod vote;
@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::Instrument;
+use utoipa::ToSchema;
+use crate::error::AppError;
+use crate::model::user::escape_like;
+use crate::telemetry::db_span;
+
+/// 未指定 `limit` 时返回的条数
+pub const DEFAULT_SEARCH_LIMIT: u32 = 20;
+/// 最多返回的条数
+pub const MAX_SEARCH_LIMIT: u32 = 50;
+const MAX_QUERY_LEN: usize = 100;
+
+/// 全文检索匹配词前缀，三元组匹配用户名或邮箱中的任意片段及相近的拼写
+const SEARCH_SQL: &str = r"
+    SELECT id, username, email, created_at AT TIME ZONE 'UTC' AS created_at,
+           (COALESCE(ts_rank(search, query), 0)
+               + GREATEST(similarity(username, $1), similarity(email, $1)))::REAL AS rank
+    FROM users, to_tsquery('simple', $2) AS query
+    WHERE search @@ query
+       OR username ILIKE $3 OR email ILIKE $3
+       OR username % $1 OR email % $1
+    ORDER BY rank DESC, id
+    LIMIT $4
+";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct UserSearchHit {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub created_at: Option<DateTime<Utc>>,
+    /// 相关度，越大越靠前
+    pub rank: f32,
+    /// 匹配的片段包在 `<mark>` 中，其余部分已按 HTML 转义
+    #[sqlx(skip)]
+    pub username_highlight: String,
+    #[sqlx(skip)]
+    pub email_highlight: String,
+}
+
+/// 每个词按前缀匹配；只保留字母和数字，避免用户输入中的 tsquery 运算符
+fn prefix_tsquery(terms: &[&str]) -> Option<String> {
+    let terms: Vec<String> = terms
+        .iter()
+        .map(|term| term.chars().filter(|c| c.is_alphanumeric()).collect::<String>())
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("{}:*", term))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" & "))
+}
+
+/// 把 `text` 中不区分大小写匹配任一 `terms` 的片段包在 `<mark>` 中，其余部分按 HTML 转义
+pub(crate) fn highlight(text: &str, terms: &[&str]) -> String {
+    let fold = |c: char| c.to_lowercase().next().unwrap_or(c);
+    let chars: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = chars.iter().copied().map(fold).collect();
+    let mut marked = vec![false; chars.len()];
+    for term in terms {
+        let term: Vec<char> = term.chars().map(fold).collect();
+        if term.is_empty() || term.len() > folded.len() {
+            continue;
+        }
+        for start in 0..=folded.len() - term.len() {
+            if folded[start..start + term.len()] == term[..] {
+                marked[start..start + term.len()].fill(true);
+            }
+        }
+    }
+
+    let mut highlighted = String::with_capacity(text.len());
+    for (i, &c) in chars.iter().enumerate() {
+        if marked[i] && (i == 0 || !marked[i - 1]) {
+            highlighted.push_str("<mark>");
+        }
+        match c {
+            '&' => highlighted.push_str("&amp;"),
+            '<' => highlighted.push_str("&lt;"),
+            '>' => highlighted.push_str("&gt;"),
+            '"' => highlighted.push_str("&quot;"),
+            '\'' => highlighted.push_str("&#39;"),
+            c => highlighted.push(c),
+        }
+        if marked[i] && (i + 1 == chars.len() || !marked[i + 1]) {
+            highlighted.push_str("</mark>");
+        }
+    }
+    highlighted
+}
+
+impl UserSearchHit {
+    /// 按用户名或邮箱搜索，`q` 中的多个词需要全部按前缀匹配，或者整体作为片段匹配
+    pub(crate) async fn search(pool: &PgPool, q: &str, limit: Option<u32>) -> Result<Vec<UserSearchHit>, AppError> {
+        let q = q.trim();
+        if q.is_empty() || q.chars().count() > MAX_QUERY_LEN {
+            return Err(AppError::BadRequest(format!(
+                "q must be between 1 and {} characters",
+                MAX_QUERY_LEN
+            )));
+        }
+        let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+        let terms: Vec<&str> = q.split_whitespace().collect();
+        let mut hits = sqlx::query_as::<_, UserSearchHit>(SEARCH_SQL)
+            .bind(q)
+            .bind(prefix_tsquery(&terms))
+            .bind(format!("%{}%", escape_like(q)))
+            .bind(i64::from(limit))
+            .fetch_all(pool)
+            .instrument(db_span("SELECT users"))
+            .await?;
+        for hit in &mut hits {
+            hit.username_highlight = highlight(&hit.username, &terms);
+            hit.email_highlight = highlight(&hit.email, &terms);
+        }
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::test_utils::TestDatabase;
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(highlight("Alice", &["LIC"]), "A<mark>lic</mark>e");
+        assert_eq!(highlight("alice@example.com", &["ali", "ice", "com"]), "<mark>alice</mark>@example.<mark>com</mark>");
+        assert_eq!(highlight("<b>&", &["b"]), "&lt;<mark>b</mark>&gt;&amp;");
+        assert_eq!(highlight("bob", &["x"]), "bob");
+        assert_eq!(prefix_tsquery(&["al'ice", "&", "ex:*"]), Some("alice:* & ex:*".to_string()));
+        assert_eq!(prefix_tsquery(&["!"]), None);
+    }
+
+    #[tokio::test]
+    async fn test_search_users() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+        for (username, email) in [
+            ("alice", "alice@example.com"),
+            ("malice", "m@corp.io"),
+            ("bob", "bob@example.com"),
+        ] {
+            sqlx::query("INSERT INTO users (username, email, password_hash) VALUES ($1, $2, '')")
+                .bind(username)
+                .bind(email)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let usernames = |hits: Vec<UserSearchHit>| hits.into_iter().map(|h| h.username).collect::<Vec<_>>();
+
+        // 前缀匹配的排在只有片段匹配的前面
+        let hits = UserSearchHit::search(&pool, "ALI", None).await.unwrap();
+        assert_eq!(hits[0].username_highlight, "<mark>ali</mark>ce");
+        assert_eq!(hits[0].email_highlight, "<mark>ali</mark>ce@example.com");
+        assert_eq!(usernames(hits), ["alice", "malice"]);
+        assert_eq!(usernames(UserSearchHit::search(&pool, "corp", None).await.unwrap()), ["malice"]);
+        let hits = UserSearchHit::search(&pool, "example bo", None).await.unwrap();
+        assert_eq!(usernames(hits)[0], "bob");
+        // 拼写相近
+        assert_eq!(usernames(UserSearchHit::search(&pool, "alise", None).await.unwrap())[0], "alice");
+        assert_eq!(UserSearchHit::search(&pool, "alice", Some(1)).await.unwrap().len(), 1);
+        assert!(UserSearchHit::search(&pool, "zzz", None).await.unwrap().is_empty());
+        assert!(UserSearchHit::search(&pool, "  ", None).await.is_err());
+    }
+}
@@ -1,11 +1,18 @@
+use crate::cache::{user_key, RedisCache};
 use crate::error::AppError;
+use crate::event::UserEventPublisher;
+use crate::kafka::outbox;
+use crate::model::cursor::{fetch_page, CursorCodec, CursorPage, CursorQuery, Keyset, Order};
+use crate::protos::user_events::{user_event::Event, LoginFailed, UserDeleted, UserLoggedIn, UserRegistered};
+use crate::telemetry::db_span;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header,  Validation};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use sqlx::PgPool;
-use sqlx_paginated::{
-    paginated_query_as, PaginatedResponse, QueryParamsBuilder,
-};
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgArguments;
+use sqlx::{PgPool, Postgres};
+use sqlx_paginated::{paginated_query_as, PaginatedResponse, QueryBuilder, QueryParams};
+use tracing::Instrument;
 use utoipa::ToSchema;
 use validator::{Validate, ValidationError};
 
@@ -54,6 +61,84 @@ pub struct BaseUserInfo {
     pub email: String,
 }
 
+/// 用户列表的一项
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::FromRow, Default)]
+pub struct UserSummary {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// 用户列表可以排序的列
+pub(crate) const USER_SORT_COLUMNS: [&str; 4] = ["id", "username", "email", "created_at"];
+
+/// 用户名和邮箱可以按前缀筛选
+pub(crate) const USER_PREFIX_FILTERS: [&str; 2] = ["username", "email"];
+
+const USER_PAGE_SQL: &str = "SELECT id, username, email, created_at AT TIME ZONE 'UTC' AS created_at FROM users";
+
+/// 转义 LIKE 的通配符，使输入按字面匹配
+pub(crate) fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// sqlx-paginated 自带的筛选是等值匹配，用户名和邮箱的筛选值在这里改为不区分大小写的前缀匹配
+fn user_page_conditions(params: &QueryParams<UserSummary>) -> (Vec<String>, PgArguments) {
+    let mut builder = QueryBuilder::<UserSummary, Postgres>::new()
+        .with_search(params)
+        .with_date_range(params);
+    for column in USER_PREFIX_FILTERS {
+        if let Some(Some(prefix)) = params.filters.get(column) {
+            builder = builder.with_condition(column, "ILIKE", format!("{}%", escape_like(prefix)));
+        }
+    }
+    builder.build()
+}
+
+impl Keyset for UserSummary {
+    type Key = (i32,);
+    const KEY_COLUMNS: &'static [&'static str] = &["id"];
+    const ORDER: Order = Order::Desc;
+
+    fn key(&self) -> Self::Key {
+        (self.id,)
+    }
+}
+
+impl UserSummary {
+    /// 按 id 倒序翻页，新注册的用户在前
+    pub(crate) async fn list(
+        pool: &PgPool,
+        codec: &CursorCodec,
+        request: &CursorQuery,
+        limit: i64,
+    ) -> Result<CursorPage<UserSummary>, AppError> {
+        let query = sqlx::QueryBuilder::<Postgres>::new(format!("{} WHERE TRUE", USER_PAGE_SQL));
+        fetch_page(pool, query, codec, "users", request, limit).await
+    }
+
+    pub(crate) async fn page_user(
+        pool: &PgPool,
+        params: QueryParams<'_, UserSummary>,
+    ) -> Result<PaginatedResponse<UserSummary>, AppError> {
+        let paginated_response = paginated_query_as::<UserSummary>(USER_PAGE_SQL)
+            .with_params(params)
+            .with_query_builder(user_page_conditions)
+            .fetch_paginated(pool)
+            .instrument(db_span("SELECT users"))
+            .await?;
+        Ok(paginated_response)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, Validate)]
 pub struct LoginUser {
     #[validate(length(
@@ -76,11 +161,17 @@ pub(crate) struct Claims {
     exp: usize,
 }
 
-pub(crate) fn _validate_token(token: &str) -> Result<Claims, AppError> {
+impl Claims {
+    pub(crate) fn user_id(&self) -> Option<i32> {
+        self.sub.parse().ok()
+    }
+}
+
+pub(crate) fn validate_token(token: &str, pem: &str) -> Result<Claims, AppError> {
     let validation = Validation::default();
     let token_data = decode::<Claims>(
         token,
-        &DecodingKey::from_secret(b"your_secret_key"),
+        &DecodingKey::from_secret(pem.as_bytes()),
         &validation,
     )?;
     Ok(token_data.claims)
@@ -98,35 +189,48 @@ impl BaseUserInfo {
             id
         )
         .fetch_one(pool)
+        .instrument(db_span("SELECT users"))
         .await?;
         Ok(user)
     }
 
-    pub(crate) async fn page_user(
-        pool: &PgPool,
-        page: i32,
-        page_size: i32,
-    ) -> Result<PaginatedResponse<BaseUserInfo>, AppError> {
-        let params = QueryParamsBuilder::<BaseUserInfo>::new()
-            .with_pagination(page as i64, page_size as i64)
-            .build();
-        let paginated_response = paginated_query_as!(BaseUserInfo, "SELECT * FROM users")
-            // Alternative function call example (if macros don't fit your use case):
-            // paginated_query_as::<User>("SELECT * FROM users")
-            .with_params(params)
-            .fetch_paginated(pool)
-            .await?;
+    /// 先读缓存，未命中时查询数据库
+    pub(crate) async fn find_user(pool: &PgPool, cache: &RedisCache, id: i32) -> Result<BaseUserInfo, AppError> {
+        cache
+            .get_or_load(&user_key(id), || Self::select_user(pool, id))
+            .await
+    }
 
-        Ok(paginated_response)
+    /// 返回被删除用户的信息，提交后清除该用户的缓存
+    pub(crate) async fn delete_user(
+        pool: &PgPool,
+        cache: &RedisCache,
+        id: i32,
+        events: &UserEventPublisher,
+    ) -> Result<BaseUserInfo, AppError> {
+        let mut tx = pool.begin().await?;
+        let deleted = sqlx::query_as::<_, BaseUserInfo>("DELETE FROM users WHERE id = $1 RETURNING username, email")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .instrument(db_span("DELETE users"))
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("user {} not found", id)))?;
+        let record = events.record(Event::Deleted(UserDeleted { user_id: id }));
+        outbox::enqueue(&mut tx, &record).await?;
+        tx.commit().await?;
+        cache.invalidate(&user_key(id)).await;
+        Ok(deleted)
     }
 }
 
 impl LoginUser {
+    /// 返回用户 id 和 token
     pub(crate) async fn verify_user(
         pg_pool: &PgPool,
         pem: &str,
         user: &LoginUser,
-    ) -> Result<String, AppError> {
+        events: &UserEventPublisher,
+    ) -> Result<(i32, String), AppError> {
         let mut hasher = Sha256::new();
         hasher.update(user.password.as_bytes());
         let password_hash = format!("{:x}", hasher.finalize());
@@ -140,9 +244,16 @@ impl LoginUser {
             password_hash
         )
         .fetch_optional(pg_pool)
-        .await?
-        .ok_or(AppError::InvalidCredentials)?
-        .id;
+        .instrument(db_span("SELECT users"))
+        .await?;
+        let Some(user_id) = user_id.map(|row| row.id) else {
+            Self::publish_login_failed(pg_pool, user, events).await?;
+            return Err(AppError::InvalidCredentials);
+        };
+        events.publish(Event::LoggedIn(UserLoggedIn {
+            user_id,
+            username: user.username.clone(),
+        }));
 
         let claims = Claims {
             sub: user_id.to_string(),
@@ -153,7 +264,27 @@ impl LoginUser {
             &claims,
             &EncodingKey::from_secret(pem.as_bytes()),
         )?;
-        Ok(token)
+        Ok((user_id, token))
+    }
+
+    /// 区分用户不存在和密码错误，只用于事件，响应中不暴露
+    async fn publish_login_failed(
+        pg_pool: &PgPool,
+        user: &LoginUser,
+        events: &UserEventPublisher,
+    ) -> Result<(), AppError> {
+        let user_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
+            .bind(&user.username)
+            .fetch_optional(pg_pool)
+            .instrument(db_span("SELECT users"))
+            .await?;
+        let reason = if user_id.is_some() { "wrong_password" } else { "unknown_user" };
+        events.publish(Event::LoginFailed(LoginFailed {
+            username: user.username.clone(),
+            reason: reason.to_string(),
+            user_id,
+        }));
+        Ok(())
     }
 }
 
@@ -162,11 +293,14 @@ impl CreateUser {
         pool: &PgPool,
         _pem: &str,
         user: &CreateUser,
+        events: &UserEventPublisher,
     ) -> Result<i32, AppError> {
         let mut hasher = Sha256::new();
         hasher.update(user.password.as_bytes());
         let password_hash = format!("{:x}", hasher.finalize());
 
+        // 注册事件与用户在同一事务中写入发件箱，由转发任务发布
+        let mut tx = pool.begin().await?;
         let user_id = sqlx::query!(
             r"
             INSERT INTO users (username, email, password_hash)
@@ -177,20 +311,30 @@ impl CreateUser {
             user.email,
             password_hash
         )
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
+        .instrument(db_span("INSERT users"))
         .await?
         .id;
+        let record = events.record(Event::Registered(UserRegistered {
+            user_id,
+            username: user.username.clone(),
+            email: user.email.clone(),
+        }));
+        outbox::enqueue(&mut tx, &record).await?;
+        tx.commit().await?;
         Ok(user_id)
     }
 
+    /// 返回新用户的 id 和 token
     pub(crate) async fn create_user(
         pool: &PgPool,
         pem: &str,
         user: &CreateUser,
-    ) -> Result<String, AppError> {
-        let user_id = Self::insert_user(pool, pem, user).await;
+        events: &UserEventPublisher,
+    ) -> Result<(i32, String), AppError> {
+        let user_id = Self::insert_user(pool, pem, user, events).await;
         match user_id {
-            Ok(user_id) => Self::generate_jwt(&pem, user_id)?,
+            Ok(user_id) => Self::generate_jwt(&pem, user_id)?.map(|token| (user_id, token)),
             Err(_) => Err(AppError::Database(sqlx::Error::RowNotFound)),
         }
     }
@@ -214,6 +358,8 @@ impl CreateUser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::event::tests::memory_publisher;
+    use crate::kafka::memory::MemoryBus;
     use crate::init::test_utils::TestDatabase;
 
     #[tokio::test]
@@ -227,7 +373,9 @@ mod tests {
             password: "password123@".to_string(),
         };
         let pem = "your_secret_key";
-        let token = CreateUser::create_user(&pool, &pem, &user).await.unwrap();
+        let (user_id, token) = CreateUser::create_user(&pool, pem, &user, &memory_publisher(&MemoryBus::new(1)))
+            .await
+            .unwrap();
         let claims: Claims = decode(
             &token,
             &DecodingKey::from_secret(pem.as_bytes()),
@@ -236,7 +384,105 @@ mod tests {
             .unwrap()
             .claims;
         //not null
-        assert!(!claims.sub.is_empty());
+        assert_eq!(claims.sub, user_id.to_string());
         assert!(claims.exp > 0);
+
+        let (topic, key): (String, Vec<u8>) =
+            sqlx::query_as("SELECT topic, key FROM outbox WHERE sent_at IS NULL")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(topic, "user.registered");
+        assert_eq!(key, claims.sub.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_list_users_by_cursor() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+        for name in ["u1", "u2", "u3", "u4", "u5"] {
+            sqlx::query("INSERT INTO users (username, email, password_hash) VALUES ($1, $1 || '@test.com', '')")
+                .bind(name)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let codec = CursorCodec::new("secret");
+        let list = |request: CursorQuery| {
+            let (pool, codec) = (pool.clone(), codec.clone());
+            async move { UserSummary::list(&pool, &codec, &request, 2).await.unwrap() }
+        };
+        let names = |page: &CursorPage<UserSummary>| page.records.iter().map(|u| u.username.clone()).collect::<Vec<_>>();
+
+        let first = list(CursorQuery::default()).await;
+        assert_eq!(names(&first), ["u5", "u4"]);
+        assert!(first.prev_cursor.is_none());
+        let second = list(CursorQuery { after: first.next_cursor.clone(), ..CursorQuery::default() }).await;
+        assert_eq!(names(&second), ["u3", "u2"]);
+        // 翻页期间插入的新用户不会让后面的页重复或跳过记录
+        sqlx::query("INSERT INTO users (username, email, password_hash) VALUES ('u6', 'u6@test.com', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let third = list(CursorQuery { after: second.next_cursor.clone(), ..CursorQuery::default() }).await;
+        // 初始化脚本中的 admin 用户 id 为 0
+        assert_eq!(names(&third), ["u1", "admin"]);
+        assert!(third.next_cursor.is_none());
+
+        let back = list(CursorQuery { before: third.prev_cursor.clone(), ..CursorQuery::default() }).await;
+        assert_eq!(names(&back), ["u3", "u2"]);
+        let back = list(CursorQuery { before: back.prev_cursor.clone(), ..CursorQuery::default() }).await;
+        assert_eq!(names(&back), ["u5", "u4"]);
+        assert!(back.prev_cursor.is_some());
+        let back = list(CursorQuery { before: back.prev_cursor.clone(), ..CursorQuery::default() }).await;
+        assert_eq!(names(&back), ["u6"]);
+        assert!(back.prev_cursor.is_none());
+
+        let both = CursorQuery { after: first.next_cursor, before: second.next_cursor, limit: None };
+        assert!(UserSummary::list(&pool, &codec, &both, 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_page_user() {
+        let test_db = TestDatabase::new().await;
+        let pool = test_db.pool;
+        for (username, email, created_at) in [
+            ("alice", "alice@example.com", "2025-01-01T00:00:00Z"),
+            ("alan", "al_an@example.com", "2025-02-01T00:00:00Z"),
+            ("bob", "bob@test.com", "2025-03-01T00:00:00Z"),
+        ] {
+            sqlx::query("INSERT INTO users (username, email, password_hash, created_at) VALUES ($1, $2, '', $3)")
+                .bind(username)
+                .bind(email)
+                .bind(created_at.parse::<DateTime<Utc>>().unwrap().naive_utc())
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let usernames = |page: PaginatedResponse<UserSummary>| {
+            page.records.into_iter().map(|u| u.username).collect::<Vec<_>>()
+        };
+        let params = || {
+            sqlx_paginated::QueryParamsBuilder::<UserSummary>::new()
+                .with_sort("username", sqlx_paginated::QuerySortDirection::Ascending)
+        };
+
+        let page = UserSummary::page_user(&pool, params().with_filter("username", Some("AL")).build()).await.unwrap();
+        assert_eq!(page.total, Some(2));
+        assert_eq!(usernames(page), ["alan", "alice"]);
+        // 通配符按字面匹配
+        let page = UserSummary::page_user(&pool, params().with_filter("email", Some("al_")).build()).await.unwrap();
+        assert_eq!(usernames(page), ["alan"]);
+        let page = UserSummary::page_user(&pool, params().with_search("EXAMPLE", vec!["username", "email"]).build())
+            .await
+            .unwrap();
+        assert_eq!(usernames(page), ["alan", "alice"]);
+
+        let after = "2025-01-15T00:00:00Z".parse().ok();
+        let before = "2025-12-31T00:00:00Z".parse().ok();
+        let params = params().with_date_range(after, before, Some("created_at")).build();
+        let page = UserSummary::page_user(&pool, params).await.unwrap();
+        assert_eq!(page.records[1].created_at, "2025-03-01T00:00:00Z".parse().ok());
+        assert_eq!(usernames(page), ["alan", "bob"]);
     }
 }
\ No newline at end of file
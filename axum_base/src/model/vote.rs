@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use utoipa::ToSchema;
+use crate::error::AppError;
+use crate::model::cursor::{fetch_page, CursorCodec, CursorPage, CursorQuery, Keyset, Order};
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, sqlx::FromRow, PartialEq)]
+pub struct Vote {
+    pub id: i64,
+    pub url: String,
+    /// 0 赞成，1 反对
+    pub vote: i16,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Keyset for Vote {
+    type Key = (i64,);
+    const KEY_COLUMNS: &'static [&'static str] = &["id"];
+    const ORDER: Order = Order::Desc;
+
+    fn key(&self) -> Self::Key {
+        (self.id,)
+    }
+}
+
+impl Vote {
+    /// 按 id 倒序翻页，游标只在同一 URL 下有效
+    pub(crate) async fn list(
+        pool: &PgPool,
+        codec: &CursorCodec,
+        url: &str,
+        request: &CursorQuery,
+        limit: i64,
+    ) -> Result<CursorPage<Vote>, AppError> {
+        let mut query = QueryBuilder::<Postgres>::new("SELECT id, url, vote, created_at FROM votes WHERE url = ");
+        query.push_bind(url.to_string());
+        fetch_page(pool, query, codec, &format!("votes:{}", url), request, limit).await
+    }
+}
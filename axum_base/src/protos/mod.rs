@@ -2,9 +2,24 @@ pub mod voting {
     tonic::include_proto!("voting");
 }
 
+pub mod user_events {
+    tonic::include_proto!("user_events");
+}
+
+pub mod analytics {
+    tonic::include_proto!("analytics");
+}
+
+pub mod users {
+    tonic::include_proto!("users");
+}
+
 #[test]
 fn compile_protos() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("../protos/voting.proto")?;
+    tonic_build::compile_protos("../protos/user_events.proto")?;
+    tonic_build::compile_protos("../protos/analytics.proto")?;
+    tonic_build::compile_protos("../protos/users.proto")?;
     Ok(())
 }
 
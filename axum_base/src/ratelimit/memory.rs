@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use futures_util::future::BoxFuture;
+use tokio::time::Instant;
+use crate::error::AppError;
+use super::store::{gcra, Decision, Quota, RateLimitStore};
+
+// 每隔这么多次检查清理一次已恢复满额的调用方
+const SWEEP_EVERY: u64 = 1024;
+
+/// 进程内的限流计数，多个实例之间不共享
+pub struct MemoryStore {
+    start: Instant,
+    tats: Mutex<HashMap<String, u64>>,
+    checks: AtomicU64,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            tats: Mutex::new(HashMap::new()),
+            checks: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn check<'a>(&'a self, key: &'a str, quota: Quota) -> BoxFuture<'a, Result<Decision, AppError>> {
+        let now = self.start.elapsed().as_millis() as u64;
+        let mut tats = self.tats.lock().unwrap();
+        if self.checks.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == SWEEP_EVERY - 1 {
+            tats.retain(|_, tat| *tat > now);
+        }
+        let (decision, tat) = gcra(now, tats.get(key).copied().unwrap_or(now), quota);
+        if let Some(tat) = tat {
+            tats.insert(key.to_string(), tat);
+        }
+        Box::pin(std::future::ready(Ok(decision)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_and_recovery() {
+        let store = MemoryStore::new();
+        let quota = Quota { emission: Duration::from_secs(10), burst: 3 };
+
+        for remaining in [2, 1, 0] {
+            let decision = store.check("k", quota).await.unwrap();
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let denied = store.check("k", quota).await.unwrap();
+        assert!(!denied.allowed);
+        assert_eq!((denied.retry_after, denied.reset), (Duration::from_secs(10), Duration::from_secs(30)));
+        assert!(store.check("other", quota).await.unwrap().allowed);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let decision = store.check("k", quota).await.unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(store.check("k", quota).await.unwrap().remaining, 2);
+    }
+}
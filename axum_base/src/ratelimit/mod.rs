@@ -0,0 +1,290 @@
+pub mod memory;
+pub mod store;
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+use tracing::warn;
+use crate::config::{RateLimitKey, RateLimitPolicy};
+use crate::constant::{API_KEY_HEADER, RATE_LIMIT_KEY_PREFIX};
+use crate::controller::auth::{request_user, JwtSecret};
+use store::{Decision, Quota, RateLimitStore};
+
+static RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+static RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+static RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+static RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+fn ceil_secs(duration: Duration) -> u64 {
+    (duration.as_millis() as u64).div_ceil(1000)
+}
+
+/// 为各个路由组创建限流层，同一路由组内的路由共享计数
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    secret: JwtSecret,
+    trust_forwarded: bool,
+    api_keys: Arc<HashSet<String>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(
+        store: Arc<dyn RateLimitStore>,
+        secret: JwtSecret,
+        trust_forwarded: bool,
+        api_keys: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self { store, secret, trust_forwarded, api_keys: Arc::new(api_keys.into_iter().collect()) }
+    }
+
+    /// 策略为 `None` 时不限流
+    pub(crate) fn layer(&self, group: &'static str, policy: Option<RateLimitPolicy>) -> RateLimitLayer {
+        RateLimitLayer {
+            limit: policy.map(|policy| {
+                Arc::new(GroupLimit {
+                    limiter: self.clone(),
+                    group,
+                    quota: Quota::from(&policy),
+                    policy,
+                })
+            }),
+        }
+    }
+
+    /// 测试等没有连接信息的场景统一计为 `unknown`
+    fn client_ip(&self, parts: &Parts) -> String {
+        client_ip(parts, self.trust_forwarded).unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// 未经可信代理时取连接的对端地址，没有连接信息时返回 `None`
+pub(crate) fn client_ip(parts: &Parts, trust_forwarded: bool) -> Option<String> {
+    if trust_forwarded {
+        let forwarded = parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty());
+        if let Some(ip) = forwarded {
+            return Some(ip.to_string());
+        }
+    }
+    parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip().to_string())
+}
+
+struct GroupLimit {
+    limiter: RateLimiter,
+    group: &'static str,
+    policy: RateLimitPolicy,
+    quota: Quota,
+}
+
+impl GroupLimit {
+    fn key(&self, parts: &Parts) -> String {
+        let caller = match self.policy.key {
+            RateLimitKey::Ip => None,
+            RateLimitKey::User => request_user(parts, &self.limiter.secret).map(|user| format!("user:{}", user.id)),
+            // 只有配置过的 key 单独计数，否则客户端换一个 key 就有新的限额；不把原文写入 Redis
+            RateLimitKey::ApiKey => parts
+                .headers
+                .get(API_KEY_HEADER)
+                .and_then(|key| key.to_str().ok())
+                .filter(|key| self.limiter.api_keys.contains(*key))
+                .map(|key| format!("key:{:x}", Sha256::digest(key.as_bytes()))),
+        };
+        let caller = caller.unwrap_or_else(|| format!("ip:{}", self.limiter.client_ip(parts)));
+        format!("{}{}:{}", RATE_LIMIT_KEY_PREFIX, self.group, caller)
+    }
+
+    fn set_headers(&self, headers: &mut HeaderMap, decision: &Decision) {
+        let policy = format!(
+            "{};w={};burst={}",
+            self.policy.limit,
+            self.policy.period.as_secs(),
+            self.policy.burst
+        );
+        headers.insert(RATELIMIT_LIMIT.clone(), HeaderValue::from(self.policy.burst));
+        headers.insert(RATELIMIT_REMAINING.clone(), HeaderValue::from(decision.remaining));
+        headers.insert(RATELIMIT_RESET.clone(), HeaderValue::from(ceil_secs(decision.reset)));
+        if let Ok(policy) = HeaderValue::from_str(&policy) {
+            headers.insert(RATELIMIT_POLICY.clone(), policy);
+        }
+    }
+
+    fn rejected(&self, decision: &Decision) -> Response {
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+        self.set_headers(response.headers_mut(), decision);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(ceil_secs(decision.retry_after).max(1)));
+        response
+    }
+}
+
+/// 超过限额时返回 429，放行的响应附带 `RateLimit-*` 头。
+///
+/// 计数存储不可用时放行请求并记录日志，不让限流成为单点故障。
+#[derive(Clone)]
+pub(crate) struct RateLimitLayer {
+    limit: Option<Arc<GroupLimit>>,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit { inner, limit: self.limit.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RateLimit<S> {
+    inner: S,
+    limit: Option<Arc<GroupLimit>>,
+}
+
+impl<S> Service<Request> for RateLimit<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // 取走已就绪的服务，克隆出的服务留给下一次调用
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let Some(limit) = self.limit.clone() else {
+            return Box::pin(inner.call(request));
+        };
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let key = limit.key(&parts);
+            let decision = match limit.limiter.store.check(&key, limit.quota).await {
+                Ok(decision) => Some(decision),
+                Err(e) => {
+                    warn!(group = limit.group, "rate limit check failed, allowing request: {}", e);
+                    None
+                }
+            };
+            if let Some(decision) = decision.filter(|d| !d.allowed) {
+                return Ok(limit.rejected(&decision));
+            }
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+            if let Some(decision) = decision {
+                limit.set_headers(response.headers_mut(), &decision);
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use tower::ServiceExt;
+    use memory::MemoryStore;
+
+    fn limited(policy: RateLimitPolicy) -> Router {
+        let limiter = RateLimiter::new(Arc::new(MemoryStore::new()), JwtSecret("secret".into()), true, ["a".into(), "b".into()]);
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route_layer(limiter.layer("test", Some(policy)))
+    }
+
+    async fn send(router: &Router, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::builder().uri("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        router.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    fn header(response: &Response, name: &str) -> String {
+        response.headers()[name].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limit_by_ip() {
+        let router = limited(RateLimitPolicy::new(2, Duration::from_secs(60), RateLimitKey::Ip));
+        let first = [("x-forwarded-for", "1.1.1.1, 10.0.0.1")];
+        let second = [("x-forwarded-for", "2.2.2.2")];
+
+        let response = send(&router, &first).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "ratelimit-limit"), "2");
+        assert_eq!(header(&response, "ratelimit-remaining"), "1");
+        assert_eq!(header(&response, "ratelimit-reset"), "30");
+        assert_eq!(header(&response, "ratelimit-policy"), "2;w=60;burst=2");
+        assert_eq!(send(&router, &first).await.status(), StatusCode::OK);
+
+        let response = send(&router, &first).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, "retry-after"), "30");
+        assert_eq!(header(&response, "ratelimit-remaining"), "0");
+        assert_eq!(send(&router, &second).await.status(), StatusCode::OK);
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(send(&router, &first).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limit_by_user_and_api_key() {
+        let token = |sub: &str| {
+            let claims = serde_json::json!({
+                "sub": sub,
+                "exp": (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp(),
+            });
+            let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+            format!("Bearer {}", token)
+        };
+        let (first, second) = (token("1"), token("2"));
+        let router = limited(RateLimitPolicy::new(1, Duration::from_secs(60), RateLimitKey::User));
+        assert_eq!(send(&router, &[("authorization", &first)]).await.status(), StatusCode::OK);
+        assert_eq!(send(&router, &[("authorization", &first)]).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(send(&router, &[("authorization", &second)]).await.status(), StatusCode::OK);
+        // 没有有效 token 时按 IP 计数
+        let anonymous = [("authorization", "Bearer bad"), ("x-forwarded-for", "3.3.3.3")];
+        assert_eq!(send(&router, &anonymous).await.status(), StatusCode::OK);
+        let response = send(&router, &[("x-forwarded-for", "3.3.3.3")]).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let router = limited(RateLimitPolicy::new(1, Duration::from_secs(60), RateLimitKey::ApiKey));
+        assert_eq!(send(&router, &[(API_KEY_HEADER, "a")]).await.status(), StatusCode::OK);
+        assert_eq!(send(&router, &[(API_KEY_HEADER, "a")]).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(send(&router, &[(API_KEY_HEADER, "b")]).await.status(), StatusCode::OK);
+        // 未配置的 key 与没有 key 的请求一样按 IP 计数
+        let unknown = [(API_KEY_HEADER, "random-1"), ("x-forwarded-for", "4.4.4.4")];
+        assert_eq!(send(&router, &unknown).await.status(), StatusCode::OK);
+        let unknown = [(API_KEY_HEADER, "random-2"), ("x-forwarded-for", "4.4.4.4")];
+        assert_eq!(send(&router, &unknown).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = send(&router, &[("x-forwarded-for", "4.4.4.4")]).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
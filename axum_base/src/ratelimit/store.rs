@@ -0,0 +1,171 @@
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use futures_util::future::BoxFuture;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{RedisResult, Script};
+use tokio::sync::OnceCell;
+use crate::config::{RateLimitPolicy, StoreBackend};
+use crate::error::AppError;
+use crate::telemetry::redis::TracedConnection;
+use super::memory::MemoryStore;
+
+// 限流检查在每个请求的路径上，Redis 不可用时尽快放行
+const REDIS_TIMEOUT: Duration = Duration::from_millis(200);
+
+// GCRA，与 [`gcra`] 的逻辑一致，时间取 Redis 服务端时钟，多个实例之间不受本地时钟偏差影响
+// KEYS: 调用方的 key
+// ARGV: 每次请求占用的毫秒数, 最多连续请求次数
+// 返回: 是否放行, 剩余次数, 恢复到满额的毫秒数, 需要等待的毫秒数
+static GCRA_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        redis.replicate_commands()
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        local emission = tonumber(ARGV[1])
+        local capacity = emission * tonumber(ARGV[2])
+        local tat = tonumber(redis.call('GET', KEYS[1]) or now)
+        if tat < now then
+            tat = now
+        end
+        local new_tat = tat + emission
+        local wait = new_tat - now
+        if wait > capacity then
+            return {0, 0, tat - now, wait - capacity}
+        end
+        redis.call('SET', KEYS[1], new_tat, 'PX', wait)
+        return {1, math.floor((capacity - wait) / emission), wait, 0}
+        ",
+    )
+});
+
+/// GCRA 的参数，每次请求占用 `emission`，最多可以提前占用 `burst` 次
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub emission: Duration,
+    pub burst: u32,
+}
+
+impl From<&RateLimitPolicy> for Quota {
+    fn from(policy: &RateLimitPolicy) -> Self {
+        Self {
+            emission: (policy.period / policy.limit).max(Duration::from_millis(1)),
+            burst: policy.burst,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// 现在还能连续发出的请求数
+    pub remaining: u32,
+    /// 不再请求时恢复到满额的时间
+    pub reset: Duration,
+    /// 被拒绝时需要等待的时间
+    pub retry_after: Duration,
+}
+
+/// GCRA 的一次判定，时间为毫秒。`tat` 为理论到达时间，放行时同时返回新的值
+pub(crate) fn gcra(now: u64, tat: u64, quota: Quota) -> (Decision, Option<u64>) {
+    let emission = quota.emission.as_millis() as u64;
+    let capacity = emission * u64::from(quota.burst);
+    let tat = tat.max(now);
+    let new_tat = tat + emission;
+    let wait = new_tat - now;
+    if wait > capacity {
+        let decision = Decision {
+            allowed: false,
+            remaining: 0,
+            reset: Duration::from_millis(tat - now),
+            retry_after: Duration::from_millis(wait - capacity),
+        };
+        return (decision, None);
+    }
+    let decision = Decision {
+        allowed: true,
+        remaining: ((capacity - wait) / emission) as u32,
+        reset: Duration::from_millis(wait),
+        retry_after: Duration::ZERO,
+    };
+    (decision, Some(new_tat))
+}
+
+/// 限流计数的存储，多实例部署使用 Redis，单节点和测试可以换成进程内实现
+pub trait RateLimitStore: Send + Sync {
+    /// 计入一次请求并返回是否放行，被拒绝的请求不占用额度
+    fn check<'a>(&'a self, key: &'a str, quota: Quota) -> BoxFuture<'a, Result<Decision, AppError>>;
+}
+
+/// 按配置创建限流存储
+pub fn from_config(backend: StoreBackend, client: redis::Client) -> Arc<dyn RateLimitStore> {
+    match backend {
+        StoreBackend::Redis => Arc::new(RedisStore::new(client)),
+        StoreBackend::Memory => Arc::new(MemoryStore::new()),
+    }
+}
+
+pub struct RedisStore {
+    client: redis::Client,
+    conn: OnceCell<ConnectionManager>,
+}
+
+impl RedisStore {
+    pub fn new(client: redis::Client) -> Self {
+        Self { client, conn: OnceCell::new() }
+    }
+
+    async fn conn(&self) -> RedisResult<TracedConnection> {
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(0)
+            .set_connection_timeout(REDIS_TIMEOUT)
+            .set_response_timeout(REDIS_TIMEOUT);
+        self.conn
+            .get_or_try_init(|| self.client.get_connection_manager_with_config(config))
+            .await
+            .cloned()
+            .map(TracedConnection::new)
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    fn check<'a>(&'a self, key: &'a str, quota: Quota) -> BoxFuture<'a, Result<Decision, AppError>> {
+        Box::pin(async move {
+            let mut conn = self.conn().await?;
+            let (allowed, remaining, reset, retry_after): (u8, u32, u64, u64) = GCRA_SCRIPT
+                .key(key)
+                .arg(quota.emission.as_millis() as u64)
+                .arg(quota.burst)
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(Decision {
+                allowed: allowed == 1,
+                remaining,
+                reset: Duration::from_millis(reset),
+                retry_after: Duration::from_millis(retry_after),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimitKey;
+    use crate::init::test_utils::TestRedis;
+
+    #[tokio::test]
+    async fn test_redis_store() {
+        let test_redis = TestRedis::new().await;
+        let store = RedisStore::new(test_redis.client.clone());
+        let quota = Quota::from(&RateLimitPolicy::new(2, Duration::from_secs(60), RateLimitKey::Ip));
+
+        let first = store.check("k", quota).await.unwrap();
+        assert!(first.allowed && first.remaining == 1);
+        assert!(store.check("k", quota).await.unwrap().allowed);
+        let denied = store.check("k", quota).await.unwrap();
+        assert!(!denied.allowed);
+        assert!(denied.retry_after > Duration::from_secs(29) && denied.retry_after <= Duration::from_secs(30));
+        assert!(store.check("other", quota).await.unwrap().allowed);
+    }
+}
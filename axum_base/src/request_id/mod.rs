@@ -0,0 +1,217 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use axum::http::{HeaderValue, Request, Response};
+use futures_util::future::BoxFuture;
+use tonic::codegen::http as grpc_http;
+use tower::{Layer, Service};
+use tracing::{info_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::constant::REQUEST_ID_HEADER;
+use crate::telemetry;
+
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// 一次请求的标识，随 HTTP 响应、gRPC metadata 和 Kafka 消息头在服务间传递
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(Arc<str>);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(format!("{:032x}", rand::random::<u128>()).into())
+    }
+
+    /// 只接受不超过 128 字节的可见 ASCII，避免把任意内容写进日志和下游的消息头
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let valid = !value.is_empty() && value.len() <= MAX_LEN && value.iter().all(u8::is_ascii_graphic);
+        valid.then(|| Self(String::from_utf8_lossy(value).into()))
+    }
+
+    /// 没有收到或收到的无效时生成新的
+    pub fn from_header(value: Option<&[u8]>) -> Self {
+        value.and_then(Self::parse).unwrap_or_else(Self::generate)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// 当前任务所处理请求的标识
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// 在 `future` 内可以通过 [`RequestId::current`] 取得该标识
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0).expect("request ids are visible ASCII")
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// 接受请求头中的 `X-Request-Id`，没有时生成，写回请求头和扩展并在响应中返回。
+///
+/// 需要放在 `TraceLayer` 外层，日志的 span 才能取到标识。
+#[derive(Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<Request<B>> for RequestIdService<S>
+where
+    S: Service<Request<B>, Response = Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let id = RequestId::from_header(request.headers().get(REQUEST_ID_HEADER).map(HeaderValue::as_bytes));
+        request.headers_mut().insert(REQUEST_ID_HEADER, id.header_value());
+        request.extensions_mut().insert(id.clone());
+        let future = CURRENT.sync_scope(id.clone(), || self.inner.call(request));
+        Box::pin(async move {
+            let mut response = id.clone().scope(future).await?;
+            response.headers_mut().insert(REQUEST_ID_HEADER, id.header_value());
+            Ok(response)
+        })
+    }
+}
+
+/// gRPC 服务端使用的 [`RequestIdLayer`]，从 metadata 中读取标识并为每次调用创建 span，
+/// 调用方带有 `traceparent` 时接上它的链路
+#[derive(Clone, Default)]
+pub struct GrpcRequestIdLayer;
+
+impl<S> Layer<S> for GrpcRequestIdLayer {
+    type Service = GrpcRequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcRequestIdService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct GrpcRequestIdService<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<grpc_http::Request<B>> for GrpcRequestIdService<S>
+where
+    S: Service<grpc_http::Request<B>, Response = grpc_http::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = grpc_http::Response<R>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: grpc_http::Request<B>) -> Self::Future {
+        let id = RequestId::from_header(request.headers().get(REQUEST_ID_HEADER).map(|v| v.as_bytes()));
+        let value = grpc_http::HeaderValue::from_str(id.as_str()).expect("request ids are visible ASCII");
+        request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+        let span = info_span!("grpc", otel.kind = "server", path = %request.uri().path(), request_id = %id);
+        span.set_parent(telemetry::extract_grpc(request.headers()));
+        let future = CURRENT.sync_scope(id.clone(), || self.inner.call(request));
+        Box::pin(
+            async move {
+                let mut response = id.scope(future).await?;
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// 客户端拦截器，把当前请求的标识和链路放入发出的 gRPC 请求的 metadata
+// 签名由 tonic 的拦截器决定；目前只有示例客户端使用
+#[allow(dead_code, clippy::result_large_err)]
+pub fn grpc_interceptor(mut request: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+    if let Some(value) = RequestId::current().and_then(|id| id.as_str().parse().ok()) {
+        request.metadata_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    telemetry::inject_grpc(request.metadata_mut());
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+    use crate::error::AppError;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(RequestId::parse(b"abc-123").unwrap().as_str(), "abc-123");
+        assert!(RequestId::parse(b"").is_none());
+        assert!(RequestId::parse(b"has space").is_none());
+        assert!(RequestId::parse("中文".as_bytes()).is_none());
+        assert!(RequestId::parse(&[b'a'; MAX_LEN + 1]).is_none());
+        assert_eq!(RequestId::from_header(None).as_str().len(), 32);
+        assert_ne!(RequestId::generate(), RequestId::generate());
+    }
+
+    #[tokio::test]
+    async fn test_request_id_layer() {
+        let router = Router::new()
+            .route("/", get(|| async { RequestId::current().unwrap().to_string() }))
+            .route("/error", get(|| async { AppError::NotFound("user 1 not found".into()) }))
+            .layer(RequestIdLayer);
+        let send = |uri: &'static str, id: Option<&'static str>| {
+            let router = router.clone();
+            async move {
+                let mut request = Request::builder().uri(uri);
+                if let Some(id) = id {
+                    request = request.header(REQUEST_ID_HEADER, id);
+                }
+                let response = router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+                let id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (id, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        assert_eq!(send("/", Some("abc")).await, ("abc".to_string(), "abc".to_string()));
+        let (id, body) = send("/", Some("bad id")).await;
+        assert_eq!((id.len(), &body), (32, &id));
+        let (id, body) = send("/error", None).await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body, serde_json::json!({ "error": "user 1 not found", "request_id": id }));
+    }
+}
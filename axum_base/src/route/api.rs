@@ -1,40 +1,168 @@
-use crate::controller::user_controller::{create_user, find_user_by_id, login_user, page_user, verify_user};
+use crate::controller::user_controller::{
+    create_user, delete_user, find_user_by_id, list_users, login_user, page_user, search_users, verify_user,
+};
+use crate::controller::analytics::{daily_active_users, daily_signups, top_urls, vote_trend};
+use crate::controller::votes::{list_votes, top_voted};
+use crate::controller::admin::{export_audit_events, get_log_level, list_audit_events, set_log_level};
+use crate::controller::chat::{delete_message, edit_message, message_history, send_message};
+use crate::analytics;
+use crate::cache::cache_stats;
+use crate::config::{
+    AnalyticsConfig, ConsumerConfig, IdempotencyConfig, OutboxConfig, RateLimitConfig, UserEventTopics, VotingConfig,
+};
+use crate::controller::auth::JwtSecret;
 use crate::error::AppError;
 use crate::init::app_state::AppState;
-use axum::routing::{any, get, post};
+use crate::idempotency::IdempotencyLayer;
+use crate::kafka::outbox::OutboxRelay;
+use crate::metrics::{self, metrics_handler, HttpMetricsLayer};
+use crate::ratelimit::RateLimiter;
+use crate::request_id::{RequestId, RequestIdLayer};
+use crate::telemetry;
+use axum::routing::{get, patch, post};
+use axum::extract::FromRef;
+use axum::http::Uri;
 use axum::Router;
 use tower::ServiceBuilder;
-use tower_http::trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer};
+use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer};
 use tower_http::LatencyUnit;
-use tracing::Level;
-use std::sync::Arc;
-use crate::websocket::{broadcast_message, ws_handler, WsManager};
+use tracing::{info_span, Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::websocket::{broadcast_message, presence_handler, ws_handler, ws_stats};
+use crate::websocket::bridge::bridge_stats;
+use crate::websocket::sse::sse_handler;
 
-pub async fn api_router() -> Result<Router, AppError> {
-    let state = AppState::new().await?;
-    let ws_manager = Arc::new(WsManager::new());
+pub async fn api_router(state: AppState) -> Result<Router, AppError> {
+    if let Some(presence) = state.ws.presence() {
+        tokio::spawn(presence.clone().run_listener(state.ws.clone()));
+    }
+    let relay = OutboxRelay::new(
+        state.pool.clone(),
+        state.event_bus.clone(),
+        OutboxConfig::from_env(),
+    );
+    tokio::spawn(relay.run());
+    let bridge = state.ws_bridge.consumer(
+        ConsumerConfig::from_env(),
+        state.event_bus.clone(),
+        state.ws.clone(),
+    )?;
+    if let Some(bridge) = bridge {
+        tokio::spawn(bridge.run());
+    }
+    let analytics = analytics::consumer(
+        AnalyticsConfig::from_env(),
+        ConsumerConfig::from_env(),
+        &VotingConfig::from_env().topic,
+        &UserEventTopics::from_env(),
+        state.event_bus.clone(),
+    )?;
+    if let Some(analytics) = analytics {
+        tokio::spawn(analytics.run());
+    }
+    tokio::spawn(state.leaderboard.clone().run_reconciler(state.pool.clone()));
+    tokio::spawn(metrics::run_upkeep());
+    let limits = RateLimitConfig::from_env();
+    let limiter = RateLimiter::new(
+        state.rate_limit.clone(),
+        JwtSecret::from_ref(&state),
+        limits.trust_forwarded,
+        limits.api_keys.clone(),
+    );
+    // 客户端可能重试的 POST 接口，带 Idempotency-Key 时只执行一次
+    let idempotency = IdempotencyLayer::new(
+        state.idempotency.clone(),
+        JwtSecret::from_ref(&state),
+        IdempotencyConfig::from_env(),
+        limits.trust_forwarded,
+    );
     let user_router = Router::new()
-        .route("/user/{id}", get(find_user_by_id))
-        .route("/user", post(create_user))
+        .route("/user/{id}", get(find_user_by_id).delete(delete_user))
+        .route("/user", post(create_user).layer(idempotency.clone()))
         .route("/user/login", post(login_user))
         .route("/user/verify", post(verify_user))
         .route("/user/page", get(page_user))
-        .with_state(state);
+        .route("/user/list", get(list_users))
+        .route("/user/search", get(search_users))
+        .route("/cache/stats", get(cache_stats))
+        .route_layer(limiter.layer("users", limits.users));
 
     let websocket_router = Router::new()
         .route("/ws", get(ws_handler))
-        .route("/broadcast", post(broadcast_message))
-        .with_state(ws_manager);
-    let app_router = user_router.merge(websocket_router);
+        .route("/ws/stats", get(ws_stats))
+        .route("/ws/bridge/stats", get(bridge_stats))
+        .route("/events", get(sse_handler))
+        .route("/broadcast", post(broadcast_message).layer(idempotency))
+        .route("/presence", get(presence_handler))
+        .route_layer(limiter.layer("websocket", limits.websocket));
+
+    let chat_router = Router::new()
+        .route("/chat/{room}/messages", get(message_history).post(send_message))
+        .route("/chat/messages/{id}", patch(edit_message).delete(delete_message))
+        .route_layer(limiter.layer("chat", limits.chat));
+    let analytics_router = Router::new()
+        .route("/analytics/votes/trend", get(vote_trend))
+        .route("/analytics/votes/top", get(top_urls))
+        .route("/analytics/users/active", get(daily_active_users))
+        .route("/analytics/users/signups", get(daily_signups))
+        .route_layer(limiter.layer("analytics", limits.analytics));
+    let votes_router = Router::new()
+        .route("/votes", get(list_votes))
+        .route("/votes/top", get(top_voted))
+        .route_layer(limiter.layer("votes", limits.votes));
+    let app_router = user_router
+        .merge(websocket_router)
+        .merge(chat_router)
+        .merge(analytics_router)
+        .merge(votes_router)
+        .route("/admin/log-level", get(get_log_level).put(set_log_level))
+        .route("/admin/audit", get(list_audit_events))
+        .route("/admin/audit/export", get(export_audit_events))
+        .route("/metrics", get(metrics_handler))
+        .with_state(state);
     Ok(set_router_layers(app_router))
 }
 
+/// 请求的路径和查询参数，`token` 参数的值不写入日志和链路
+fn redacted_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_string();
+    };
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some(("token", _)) => "token=REDACTED",
+            _ => pair,
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{}", uri.path(), query)
+}
+
+/// 记录请求标识并接上调用方的链路。
+///
+/// 不记录请求头，`Authorization` 等凭据不能出现在日志和链路中。
+fn make_span(request: &axum::extract::Request) -> Span {
+    let request_id = request.extensions().get::<RequestId>().map(RequestId::as_str).unwrap_or_default();
+    let span = info_span!(
+        "request",
+        otel.kind = "server",
+        method = %request.method(),
+        uri = %redacted_uri(request.uri()),
+        version = ?request.version(),
+        request_id,
+    );
+    span.set_parent(telemetry::extract_http(request.headers()));
+    span
+}
+
 pub fn set_router_layers(app: Router) -> Router {
     app.layer(
         ServiceBuilder::new()
+            .layer(RequestIdLayer)
             .layer(
                 TraceLayer::new_for_http()
-                    .make_span_with(DefaultMakeSpan::new().include_headers(true))
+                    .make_span_with(make_span)
                     .on_request(DefaultOnRequest::new().level(Level::INFO))
                     .on_response(
                         DefaultOnResponse::new()
@@ -42,6 +170,19 @@ pub fn set_router_layers(app: Router) -> Router {
                             .latency_unit(LatencyUnit::Micros),
                     ),
             )
-      
+            .layer(HttpMetricsLayer)
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_uri() {
+        let uri: Uri = "/ws?room=lobby&token=eyJhbGc.secret&tokens=1".parse().unwrap();
+        assert_eq!(redacted_uri(&uri), "/ws?room=lobby&token=REDACTED&tokens=1");
+        let uri: Uri = "/users/7".parse().unwrap();
+        assert_eq!(redacted_uri(&uri), "/users/7");
+    }
+}
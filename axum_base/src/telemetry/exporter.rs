@@ -0,0 +1,111 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use futures_util::future::BoxFuture;
+use opentelemetry::trace::{SpanId, SpanKind, Status, TraceError};
+use opentelemetry::{Key, KeyValue, Value};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use serde_json::{json, Map};
+
+/// 每个 span 写一行 JSON，用于本地调试和测试，不需要 collector
+pub struct JsonLinesExporter<W> {
+    writer: W,
+}
+
+impl<W> JsonLinesExporter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl JsonLinesExporter<File> {
+    /// 追加写入 `path`，目录不存在时创建
+    pub fn file(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self::new(OpenOptions::new().create(true).append(true).open(path)?))
+    }
+}
+
+impl<W> fmt::Debug for JsonLinesExporter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLinesExporter").finish_non_exhaustive()
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default()
+}
+
+fn attributes<'a>(attributes: impl IntoIterator<Item = (&'a Key, &'a Value)>) -> serde_json::Value {
+    let map: Map<_, _> = attributes
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(v) => json!(v),
+                Value::I64(v) => json!(v),
+                Value::F64(v) => json!(v),
+                other => json!(other.to_string()),
+            };
+            (key.to_string(), value)
+        })
+        .collect();
+    map.into()
+}
+
+fn key_values(values: &[KeyValue]) -> serde_json::Value {
+    attributes(values.iter().map(|kv| (&kv.key, &kv.value)))
+}
+
+fn to_json(span: &SpanData) -> serde_json::Value {
+    let kind = match span.span_kind {
+        SpanKind::Client => "client",
+        SpanKind::Server => "server",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal => "internal",
+    };
+    let status = match &span.status {
+        Status::Unset => json!("unset"),
+        Status::Ok => json!("ok"),
+        Status::Error { description } => json!({ "error": description }),
+    };
+    let events: Vec<_> = span
+        .events
+        .iter()
+        .map(|event| {
+            json!({
+                "name": event.name,
+                "time_unix_nano": unix_nanos(event.timestamp),
+                "attributes": key_values(&event.attributes),
+            })
+        })
+        .collect();
+    json!({
+        "name": span.name,
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": (span.parent_span_id != SpanId::INVALID).then(|| span.parent_span_id.to_string()),
+        "kind": kind,
+        "start_time_unix_nano": unix_nanos(span.start_time),
+        "end_time_unix_nano": unix_nanos(span.end_time),
+        "attributes": key_values(&span.attributes),
+        "events": events,
+        "status": status,
+        "resource": attributes(span.resource.iter()),
+    })
+}
+
+impl<W: Write + Send + Sync> SpanExporter for JsonLinesExporter<W> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let result = batch
+            .iter()
+            .try_for_each(|span| writeln!(self.writer, "{}", to_json(span)))
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| TraceError::from(e.to_string()));
+        Box::pin(std::future::ready(result))
+    }
+}
@@ -0,0 +1,227 @@
+pub mod exporter;
+pub mod redis;
+
+use axum::http::HeaderMap;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self as sdktrace, Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tonic::codegen::http as grpc_http;
+use tonic::metadata::{MetadataKey, MetadataMap};
+use tracing::{info_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::config::{TelemetryConfig, TraceExporter};
+use exporter::JsonLinesExporter;
+
+/// 按配置安装全局的 tracer provider，未启用导出时返回 `None`
+pub fn init_tracer(config: &TelemetryConfig) -> Result<Option<Tracer>, TraceError> {
+    // 优先沿用上游的采样决定
+    let trace_config = sdktrace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+        .with_resource(Resource::new([KeyValue::new("service.name", config.service_name.clone())]));
+    let provider = match config.exporter {
+        TraceExporter::None => return Ok(None),
+        TraceExporter::Otlp => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(&config.otlp_endpoint),
+                )
+                .with_trace_config(trace_config)
+                .install_batch(runtime::Tokio)?;
+            return Ok(Some(tracer));
+        }
+        TraceExporter::Stdout => TracerProvider::builder()
+            .with_simple_exporter(JsonLinesExporter::new(std::io::stdout()))
+            .with_config(trace_config)
+            .build(),
+        TraceExporter::File => {
+            let exporter = JsonLinesExporter::file(&config.file)
+                .map_err(|e| TraceError::from(format!("failed to open {}: {}", config.file.display(), e)))?;
+            TracerProvider::builder()
+                .with_simple_exporter(exporter)
+                .with_config(trace_config)
+                .build()
+        }
+    };
+    let tracer = provider.tracer("axum_base");
+    global::set_tracer_provider(provider);
+    Ok(Some(tracer))
+}
+
+/// 导出缓冲中剩余的 span，停机前调用
+pub async fn shutdown() {
+    // 关闭时会阻塞等待导出完成
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
+
+/// 一次数据库查询的 span，`name` 按 OpenTelemetry 的约定写作“操作 表名”，如 `SELECT users`
+pub fn db_span(name: &'static str) -> Span {
+    info_span!("db", otel.name = name, otel.kind = "client", db.system = "postgresql")
+}
+
+struct HttpHeaders<'a>(&'a HeaderMap);
+
+impl Extractor for HttpHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+struct GrpcHeaders<'a>(&'a grpc_http::HeaderMap);
+
+impl Extractor for GrpcHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+struct GrpcMetadata<'a>(&'a mut MetadataMap);
+
+impl Injector for GrpcMetadata<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (MetadataKey::from_bytes(key.as_bytes()), value.parse()) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct KafkaHeaders<'a>(&'a [(String, Vec<u8>)]);
+
+impl Extractor for KafkaHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| std::str::from_utf8(v).ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(k, _)| k.as_str()).collect()
+    }
+}
+
+struct KafkaHeadersMut<'a>(&'a mut Vec<(String, Vec<u8>)>);
+
+impl Injector for KafkaHeadersMut<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        // 重试和死信消息复制了原消息的头，替换而不是追加
+        self.0.retain(|(k, _)| k != key);
+        self.0.push((key.to_string(), value.into_bytes()));
+    }
+}
+
+/// 读取 HTTP 请求头中 W3C trace context 的 `traceparent`/`tracestate`
+pub fn extract_http(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HttpHeaders(headers))
+}
+
+/// 读取 gRPC 请求的 trace context
+pub fn extract_grpc(headers: &grpc_http::HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&GrpcHeaders(headers))
+}
+
+/// 把当前 span 的 trace context 写入发出的 gRPC 请求
+pub fn inject_grpc(metadata: &mut MetadataMap) {
+    TraceContextPropagator::new().inject_context(&Span::current().context(), &mut GrpcMetadata(metadata));
+}
+
+/// 读取 Kafka 消息头中的 trace context
+pub fn extract_kafka(headers: &[(String, Vec<u8>)]) -> Context {
+    TraceContextPropagator::new().extract(&KafkaHeaders(headers))
+}
+
+/// 把当前 span 的 trace context 写入 Kafka 消息头，未启用导出时不写入
+pub fn inject_kafka(headers: &mut Vec<(String, Vec<u8>)>) {
+    TraceContextPropagator::new().inject_context(&Span::current().context(), &mut KafkaHeadersMut(headers));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use axum::http::HeaderValue;
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    #[test]
+    fn test_propagation() {
+        let buffer = Buffer::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(JsonLinesExporter::new(buffer.clone()))
+            .build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("test"));
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        // 未启用导出时不写入任何消息头
+        let mut headers = Vec::new();
+        inject_kafka(&mut headers);
+        assert!(headers.is_empty());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut request = HeaderMap::new();
+            let traceparent = format!("00-{}-{}-01", TRACE_ID, PARENT_ID);
+            request.insert("traceparent", HeaderValue::from_str(&traceparent).unwrap());
+            let span = info_span!("request");
+            span.set_parent(extract_http(&request));
+
+            span.in_scope(|| {
+                let _query = db_span("SELECT users").entered();
+                let mut headers = vec![("traceparent".to_string(), b"stale".to_vec())];
+                inject_kafka(&mut headers);
+                assert_eq!(headers.iter().filter(|(k, _)| k == "traceparent").count(), 1);
+                let context = extract_kafka(&headers);
+                let remote = context.span().span_context().clone();
+                assert_eq!(remote.trace_id().to_string(), TRACE_ID);
+                assert!(remote.is_sampled());
+
+                let mut metadata = MetadataMap::new();
+                inject_grpc(&mut metadata);
+                let context = extract_grpc(&metadata.into_headers());
+                assert_eq!(context.span().span_context().span_id(), remote.span_id());
+            });
+        });
+        provider.force_flush();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let spans: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let request = spans.iter().find(|s| s["name"] == "request").unwrap();
+        let query = spans.iter().find(|s| s["name"] == "SELECT users").unwrap();
+        assert_eq!(request["trace_id"], TRACE_ID);
+        assert_eq!(request["parent_span_id"], PARENT_ID);
+        assert_eq!(query["trace_id"], TRACE_ID);
+        assert_eq!(query["parent_span_id"], request["span_id"]);
+        assert_eq!(query["kind"], "client");
+        assert_eq!(query["attributes"]["db.system"], "postgresql");
+    }
+}
@@ -0,0 +1,74 @@
+use std::future::Future;
+use std::time::Instant;
+use metrics::{counter, histogram};
+use redis::aio::{ConnectionLike, ConnectionManager};
+use redis::{Arg, Cmd, Pipeline, RedisFuture, RedisResult, Value};
+use tracing::{info_span, Instrument, Span};
+
+/// 为每条命令创建 span 并记录延迟指标的 Redis 连接
+#[derive(Clone)]
+pub struct TracedConnection {
+    inner: ConnectionManager,
+}
+
+impl TracedConnection {
+    pub fn new(inner: ConnectionManager) -> Self {
+        Self { inner }
+    }
+}
+
+/// 命令名，例如 `GET`、`EVALSHA`；不记录参数，避免把键值写进链路数据
+fn command_name(cmd: &Cmd) -> String {
+    match cmd.args_iter().next() {
+        Some(Arg::Simple(name)) => String::from_utf8_lossy(name).to_uppercase(),
+        _ => "UNKNOWN".to_string(),
+    }
+}
+
+fn redis_span(name: &str, operation: &str) -> Span {
+    info_span!("redis", otel.name = name, otel.kind = "client", db.system = "redis", db.operation = operation)
+}
+
+async fn observe<T>(command: String, future: impl Future<Output = RedisResult<T>>) -> RedisResult<T> {
+    let start = Instant::now();
+    let result = future.await;
+    histogram!("redis_command_duration_seconds", "command" => command.clone()).record(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        counter!("redis_command_errors_total", "command" => command).increment(1);
+    }
+    result
+}
+
+impl ConnectionLike for TracedConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let name = command_name(cmd);
+        let span = redis_span(&name, &name);
+        Box::pin(observe(name, self.inner.req_packed_command(cmd)).instrument(span))
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let commands: Vec<_> = cmd.cmd_iter().map(command_name).collect();
+        let span = redis_span("PIPELINE", &commands.join(" "));
+        Box::pin(observe("PIPELINE".to_string(), self.inner.req_packed_commands(cmd, offset, count)).instrument(span))
+    }
+
+    fn get_db(&self) -> i64 {
+        self.inner.get_db()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name(redis::cmd("get").arg("user:1")), "GET");
+        assert_eq!(command_name(&Cmd::new()), "UNKNOWN");
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use crate::config::{BridgeConfig, BridgeMapping, ConsumerConfig, OffsetReset};
+use crate::kafka::bus::{BusError, EventBus};
+use crate::kafka::consumer::{ConsumerBuilder, ConsumerRuntime, Delivery, HandlerError, Text};
+use super::WsManager;
+
+#[derive(Debug, Default)]
+struct MappingMetrics {
+    forwarded: AtomicU64,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BridgeStats {
+    pub topic: String,
+    pub room: String,
+    pub per_user: bool,
+    /// 已推送到房间的消息数
+    pub forwarded: u64,
+    /// 房间内没有连接而丢弃的消息数
+    pub dropped: u64,
+    /// key 不是用户 id 而转入死信的消息数
+    pub rejected: u64,
+}
+
+/// 把 Kafka topic 的消息转发到 WebSocket 房间。
+///
+/// 消息体按原文推送；`per_user` 映射把消息 key 当作用户 id，只推送给该用户的连接。
+/// 消息进入房间（包括续传缓冲）后才提交 offset，投递语义是至少一次。
+/// 每个节点使用自己的消费组，各自收到全部消息，推送给本节点上的连接；
+/// 新消费组从最新位置开始，不会把 topic 保留的历史当作新消息推送。
+pub struct WsBridge {
+    mappings: Vec<(BridgeMapping, Arc<MappingMetrics>)>,
+    group_id: String,
+}
+
+impl WsBridge {
+    pub fn new(config: BridgeConfig) -> Self {
+        Self {
+            mappings: config
+                .mappings
+                .into_iter()
+                .map(|mapping| (mapping, Arc::default()))
+                .collect(),
+            group_id: format!("{}-{}", config.group_id, config.node_id),
+        }
+    }
+
+    pub fn stats(&self) -> Vec<BridgeStats> {
+        self.mappings
+            .iter()
+            .map(|(mapping, metrics)| BridgeStats {
+                topic: mapping.topic.clone(),
+                room: mapping.room.clone(),
+                per_user: mapping.per_user,
+                forwarded: metrics.forwarded.load(Ordering::Relaxed),
+                dropped: metrics.dropped.load(Ordering::Relaxed),
+                rejected: metrics.rejected.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// 没有配置映射时返回 `None`
+    pub fn consumer(
+        &self,
+        config: ConsumerConfig,
+        bus: Arc<dyn EventBus>,
+        ws: Arc<WsManager>,
+    ) -> Result<Option<ConsumerRuntime>, BusError> {
+        if self.mappings.is_empty() {
+            return Ok(None);
+        }
+        // 新节点不补发历史消息，推送给当前连接的只应是之后发生的事件
+        let config = ConsumerConfig {
+            group_id: self.group_id.clone(),
+            offset_reset: OffsetReset::Latest,
+            ..config
+        };
+        let mut builder = ConsumerBuilder::new(config);
+        for (mapping, metrics) in &self.mappings {
+            let (mapping, metrics, ws) = (mapping.clone(), metrics.clone(), ws.clone());
+            builder = builder.handler(mapping.topic.clone(), move |delivery: Delivery<Text>| {
+                let result = forward(&ws, &mapping, &metrics, delivery);
+                async move { result }
+            });
+        }
+        builder.build(bus).map(Some)
+    }
+}
+
+fn forward(
+    ws: &WsManager,
+    mapping: &BridgeMapping,
+    metrics: &MappingMetrics,
+    delivery: Delivery<Text>,
+) -> Result<(), HandlerError> {
+    let target = if mapping.per_user {
+        let user_id = delivery
+            .message
+            .key
+            .as_deref()
+            .and_then(|key| std::str::from_utf8(key).ok())
+            .and_then(|key| key.parse::<i32>().ok());
+        if user_id.is_none() {
+            metrics.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(HandlerError::Permanent("record key is not a user id".into()));
+        }
+        user_id
+    } else {
+        None
+    };
+
+    let Text(text) = delivery.value;
+    if ws.send_to(&mapping.room, target, text).is_ok() {
+        metrics.forwarded.fetch_add(1, Ordering::Relaxed);
+    } else {
+        metrics.dropped.fetch_add(1, Ordering::Relaxed);
+        debug!(topic = mapping.topic, room = mapping.room, "no websocket subscribers for bridged record");
+    }
+    Ok(())
+}
+
+pub async fn bridge_stats(State(bridge): State<Arc<WsBridge>>) -> Json<Vec<BridgeStats>> {
+    Json(bridge.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::config::WsConfig;
+    use crate::kafka::memory::MemoryBus;
+    use crate::kafka::producer::ProducerRecord;
+
+    #[tokio::test]
+    async fn test_bridge_topics_to_rooms() {
+        let bus = MemoryBus::new(1);
+        let ws = Arc::new(WsManager::new(WsConfig::default()));
+        let mut alerts = ws.subscribe("alerts");
+        let bridge = WsBridge::new(BridgeConfig {
+            mappings: vec![
+                "notifications=alerts:user".parse().unwrap(),
+                "news=feed".parse().unwrap(),
+            ],
+            group_id: "bridge".to_string(),
+            node_id: "node-1".to_string(),
+        });
+
+        // 订阅之前的消息属于历史，新节点不应推送
+        bus.publish(ProducerRecord::new("notifications", "stale").with_key("7")).await.unwrap();
+        bus.publish(ProducerRecord::new("news", "stale")).await.unwrap();
+        let runtime = bridge
+            .consumer(ConsumerConfig::default(), Arc::new(bus.clone()), ws.clone())
+            .unwrap()
+            .unwrap();
+        let consumer = tokio::spawn(runtime.run());
+
+        for record in [
+            ProducerRecord::new("notifications", "for 7").with_key("7"),
+            ProducerRecord::new("notifications", "bad key").with_key("admin"),
+            ProducerRecord::new("news", "nobody listening"),
+        ] {
+            bus.publish(record).await.unwrap();
+        }
+
+        let frame = tokio::time::timeout(Duration::from_secs(5), alerts.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((frame.data.as_ref(), frame.target), ("for 7", Some(7)));
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while bus.messages("notifications.dlq").is_empty()
+                || bus.committed("bridge-node-1", "news", 0).is_none()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let stats = bridge.stats();
+        assert_eq!((stats[0].forwarded, stats[0].rejected), (1, 1));
+        assert_eq!((stats[1].forwarded, stats[1].dropped), (0, 1));
+        assert_eq!(bus.committed("bridge-node-1", "news", 0), Some(2));
+        consumer.abort();
+    }
+}
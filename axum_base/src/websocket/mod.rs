@@ -1,66 +1,419 @@
-use std::sync::Arc;
-use tokio::sync::broadcast;
+pub mod bridge;
+pub mod presence;
+pub mod sse;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
 use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
     response::Response,
-    extract::State,
+    extract::{Query, State},
+    Json,
 };
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::debug;
+use crate::config::{LagPolicy, WsConfig};
+use crate::audit::{diff, Audit, AuditAction};
+use crate::controller::auth::{AdminUser, AuthUser};
+use crate::error::AppError;
+use presence::{Presence, RoomPresence};
+use crate::constant::{
+    WS_CLOSE_IDLE_TIMEOUT, WS_CLOSE_IDLE_TIMEOUT_REASON, WS_CLOSE_LAGGED,
+    WS_CLOSE_LAGGED_REASON, WS_CLOSE_PONG_TIMEOUT, WS_CLOSE_PONG_TIMEOUT_REASON,
+    WS_DEFAULT_ROOM, WS_RESERVED_FRAME_TYPES,
+};
+
+/// 一条广播消息，`id` 在整个 `WsManager` 内单调递增，用作 SSE 的事件 ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub id: u64,
+    pub data: Arc<str>,
+    /// 只投递给该用户的连接，`None` 表示房间内的所有连接
+    pub target: Option<i32>,
+}
+
+impl Frame {
+    fn visible_to(&self, user_id: Option<i32>) -> bool {
+        self.target.is_none() || self.target == user_id
+    }
+}
+
+struct Room {
+    tx: broadcast::Sender<Frame>,
+    state: Mutex<RoomState>,
+}
+
+struct RoomState {
+    /// 最近的消息，用于 SSE 的 `Last-Event-ID` 续传
+    replay: VecDeque<Frame>,
+    last_active: Instant,
+}
 
 #[derive(Clone)]
 pub struct WsManager {
-    tx: broadcast::Sender<String>,
+    rooms: Arc<RwLock<HashMap<String, Arc<Room>>>>,
+    next_id: Arc<AtomicU64>,
+    config: WsConfig,
+    metrics: Arc<WsMetrics>,
+    presence: Option<Presence>,
+}
+
+#[derive(Debug, Default)]
+pub struct WsMetrics {
+    active_connections: AtomicU64,
+    active_sse_streams: AtomicU64,
+    reaped_pong_timeout: AtomicU64,
+    reaped_idle: AtomicU64,
+    reaped_lagged: AtomicU64,
+    lag_events: AtomicU64,
+    missed_messages: AtomicU64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WsMetricsSnapshot {
+    pub active_connections: u64,
+    pub active_sse_streams: u64,
+    pub reaped_pong_timeout: u64,
+    pub reaped_idle: u64,
+    pub reaped_lagged: u64,
+    pub lag_events: u64,
+    pub missed_messages: u64,
+}
+
+impl WsMetrics {
+    pub fn snapshot(&self) -> WsMetricsSnapshot {
+        WsMetricsSnapshot {
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            active_sse_streams: self.active_sse_streams.load(Ordering::Relaxed),
+            reaped_pong_timeout: self.reaped_pong_timeout.load(Ordering::Relaxed),
+            reaped_idle: self.reaped_idle.load(Ordering::Relaxed),
+            reaped_lagged: self.reaped_lagged.load(Ordering::Relaxed),
+            lag_events: self.lag_events.load(Ordering::Relaxed),
+            missed_messages: self.missed_messages.load(Ordering::Relaxed),
+        }
+    }
 }
 
 impl WsManager {
-    pub fn new() -> Self {
-        let (tx, _) = broadcast::channel(100);
-        Self { tx }
+    pub fn new(config: WsConfig) -> Self {
+        Self {
+            rooms: Arc::new(RwLock::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            config,
+            metrics: Arc::new(WsMetrics::default()),
+            presence: None,
+        }
+    }
+
+    pub fn with_presence(mut self, presence: Presence) -> Self {
+        self.presence = Some(presence);
+        self
+    }
+
+    pub fn presence(&self) -> Option<&Presence> {
+        self.presence.as_ref()
     }
 
     pub fn broadcast(&self, message: String) -> Result<(), broadcast::error::SendError<String>> {
-        self.tx.send(message).map(|_| ())
+        self.broadcast_to(WS_DEFAULT_ROOM, message)
+    }
+
+    /// 房间不存在（从未有订阅者或已被清理）时返回错误；
+    /// 房间存在但暂时没有订阅者时消息仍会进入续传缓冲
+    pub fn broadcast_to(
+        &self,
+        room: &str,
+        message: String,
+    ) -> Result<(), broadcast::error::SendError<String>> {
+        self.send_to(room, None, message)
+    }
+
+    /// 与 [`broadcast_to`](Self::broadcast_to) 相同，但只有 `target` 用户的连接会收到
+    pub fn send_to(
+        &self,
+        room: &str,
+        target: Option<i32>,
+        message: String,
+    ) -> Result<(), broadcast::error::SendError<String>> {
+        let Some(room) = self.rooms.read().unwrap().get(room).cloned() else {
+            return Err(broadcast::error::SendError(message));
+        };
+        // 在同一把锁内分配 ID、写入缓冲并发送，保证续传快照与 channel 顺序一致
+        let mut state = room.state.lock().unwrap();
+        let frame = Frame {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            data: message.into(),
+            target,
+        };
+        if self.config.replay_capacity > 0 {
+            if state.replay.len() >= self.config.replay_capacity {
+                state.replay.pop_front();
+            }
+            state.replay.push_back(frame.clone());
+        }
+        state.last_active = Instant::now();
+        let _ = room.tx.send(frame);
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> &WsMetrics {
+        &self.metrics
+    }
+
+    fn subscribe(&self, room: &str) -> broadcast::Receiver<Frame> {
+        self.subscribe_from(room, None).0
+    }
+
+    /// 订阅房间，并返回缓冲中 ID 大于 `last_event_id` 的消息
+    fn subscribe_from(
+        &self,
+        room: &str,
+        last_event_id: Option<u64>,
+    ) -> (broadcast::Receiver<Frame>, Vec<Frame>) {
+        let room = self
+            .rooms
+            .write()
+            .unwrap()
+            .entry(room.to_string())
+            .or_insert_with(|| {
+                Arc::new(Room {
+                    tx: broadcast::channel(self.config.channel_capacity).0,
+                    state: Mutex::new(RoomState {
+                        replay: VecDeque::new(),
+                        last_active: Instant::now(),
+                    }),
+                })
+            })
+            .clone();
+        let state = room.state.lock().unwrap();
+        let rx = room.tx.subscribe();
+        let replay = match last_event_id {
+            Some(last) => state.replay.iter().filter(|f| f.id > last).cloned().collect(),
+            None => Vec::new(),
+        };
+        (rx, replay)
+    }
+
+    /// 清理没有订阅者的房间；仍有续传缓冲的房间保留 `replay_retention` 时长
+    fn release(&self, room: &str) {
+        let mut rooms = self.rooms.write().unwrap();
+        if let Some(current) = rooms.get(room) {
+            current.state.lock().unwrap().last_active = Instant::now();
+        }
+        let retention = self.config.replay_retention;
+        rooms.retain(|_, room| {
+            if room.tx.receiver_count() > 0 {
+                return true;
+            }
+            let state = room.state.lock().unwrap();
+            !state.replay.is_empty() && state.last_active.elapsed() < retention
+        });
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct WsParams {
+    room: Option<String>,
+}
+
+/// 携带 token 的连接会被记录到在线状态，匿名连接只接收广播
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    user: Option<AuthUser>,
+    Query(params): Query<WsParams>,
     State(state): State<Arc<WsManager>>,
 ) -> Response {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+    let room = params.room.unwrap_or_else(|| WS_DEFAULT_ROOM.to_string());
+    let user_id = user.map(|u| u.id);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, room, user_id))
+}
+
+/// 连接被服务端主动断开的原因
+enum Reap {
+    PongTimeout,
+    Idle,
+    Lagged,
+}
+
+/// 连接读取广播消息的来源，`Buffer` 策略下经过连接级队列
+enum Inbox {
+    Direct(broadcast::Receiver<Frame>),
+    Buffered(mpsc::Receiver<Result<Frame, RecvError>>),
 }
 
-async fn handle_socket(mut socket: WebSocket, state: Arc<WsManager>) {
+impl Inbox {
+    async fn recv(&mut self) -> Result<Frame, RecvError> {
+        match self {
+            Inbox::Direct(rx) => rx.recv().await,
+            Inbox::Buffered(queue) => queue.recv().await.unwrap_or(Err(RecvError::Closed)),
+        }
+    }
+}
+
+/// 把广播消息转存到有界队列，队列只剩最后一个位置时写入 `Lagged` 并退出
+async fn buffer_pump(
+    mut rx: broadcast::Receiver<Frame>,
+    queue: mpsc::Sender<Result<Frame, RecvError>>,
+) {
+    loop {
+        let item = match rx.recv().await {
+            Ok(_) if queue.capacity() <= 1 => Err(RecvError::Lagged(1)),
+            item => item,
+        };
+        let done = item.is_err();
+        if queue.send(item).await.is_err() || done {
+            return;
+        }
+    }
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<WsManager>,
+    room: String,
+    user_id: Option<i32>,
+) {
     use futures_util::SinkExt;
     let (mut sender, mut receiver) = socket.split();
+    let config = &state.config;
+    let policy = config.lag_policy(&room);
 
-    // 订阅广播消息
-    let mut rx = state.tx.subscribe();
-
-    // 处理接收到的消息
-    let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
-            if let Message::Text(text) = msg {
-                println!("Received message: {}", text);
-            }
+    // 订阅房间广播
+    let rx = state.subscribe(&room);
+    let (mut inbox, pump) = match policy {
+        LagPolicy::Buffer(capacity) => {
+            // 多预留一个位置用于写入溢出标记
+            let (queue_tx, queue_rx) = mpsc::channel(capacity + 1);
+            let pump = tokio::spawn(buffer_pump(rx, queue_tx));
+            (Inbox::Buffered(queue_rx), Some(pump))
         }
-    });
+        _ => (Inbox::Direct(rx), None),
+    };
+    state.metrics.active_connections.fetch_add(1, Ordering::Relaxed);
+
+    let session = match (&state.presence, user_id) {
+        (Some(presence), Some(user_id)) => Some(presence.join(&room, user_id).await),
+        _ => None,
+    };
+
+    let mut ping = time::interval_at(Instant::now() + config.ping_interval, config.ping_interval);
+    let mut last_activity = Instant::now();
+    // 已发送但尚未收到 Pong 的 Ping 的发送时间
+    let mut ping_sent_at: Option<Instant> = None;
+
+    let reap = loop {
+        let pong_deadline = ping_sent_at.map(|sent| sent + config.pong_timeout);
+        let idle_deadline =
+            (!config.idle_timeout.is_zero()).then(|| last_activity + config.idle_timeout);
+        let deadline = match (pong_deadline, idle_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
 
-    // 处理广播消息
-    let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            let message = Message::Text(msg.to_string().into());
-            if sender.send(message).await.is_err() {
-                break;
+        tokio::select! {
+            // 处理接收到的消息
+            msg = receiver.next() => match msg {
+                Some(Ok(Message::Pong(_))) => ping_sent_at = None,
+                Some(Ok(Message::Text(text))) => {
+                    last_activity = Instant::now();
+                    debug!(room = %room, ?user_id, len = text.len(), "received message");
+                }
+                Some(Ok(Message::Binary(_))) => last_activity = Instant::now(),
+                Some(Ok(Message::Ping(_))) => {}
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+            },
+            // 处理广播消息
+            msg = inbox.recv() => match msg {
+                Ok(frame) if !frame.visible_to(user_id) => {}
+                Ok(frame) => {
+                    last_activity = Instant::now();
+                    if sender.send(Message::Text(frame.data.as_ref().into())).await.is_err() {
+                        break None;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    state.metrics.lag_events.fetch_add(1, Ordering::Relaxed);
+                    state.metrics.missed_messages.fetch_add(missed, Ordering::Relaxed);
+                    debug!(room, missed, ?policy, "websocket subscriber lagged");
+                    if policy != LagPolicy::SkipAndNotify {
+                        break Some(Reap::Lagged);
+                    }
+                    let notice = serde_json::json!({ "type": "lagged", "missed": missed });
+                    if sender.send(Message::Text(notice.to_string().into())).await.is_err() {
+                        break None;
+                    }
+                }
+                Err(RecvError::Closed) => break None,
+            },
+            _ = ping.tick() => {
+                if ping_sent_at.is_none() {
+                    if sender.send(Message::Ping(Default::default())).await.is_err() {
+                        break None;
+                    }
+                    ping_sent_at = Some(Instant::now());
+                }
+                if let Some(session) = &session {
+                    session.refresh().await;
+                }
+            }
+            _ = sleep_until(deadline) => {
+                let now = Instant::now();
+                if pong_deadline.is_some_and(|d| d <= now) {
+                    break Some(Reap::PongTimeout);
+                }
+                if idle_deadline.is_some_and(|d| d <= now) {
+                    break Some(Reap::Idle);
+                }
             }
         }
-    });
+    };
 
-    // 等待任意一个任务完成
-    tokio::select! {
-        _ = &mut recv_task => send_task.abort(),
-        _ = &mut send_task => recv_task.abort(),
+    if let Some(reap) = reap {
+        let (counter, code, reason) = match reap {
+            Reap::PongTimeout => (
+                &state.metrics.reaped_pong_timeout,
+                WS_CLOSE_PONG_TIMEOUT,
+                WS_CLOSE_PONG_TIMEOUT_REASON,
+            ),
+            Reap::Idle => (
+                &state.metrics.reaped_idle,
+                WS_CLOSE_IDLE_TIMEOUT,
+                WS_CLOSE_IDLE_TIMEOUT_REASON,
+            ),
+            Reap::Lagged => (
+                &state.metrics.reaped_lagged,
+                WS_CLOSE_LAGGED,
+                WS_CLOSE_LAGGED_REASON,
+            ),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        debug!(code, reason, "reaping websocket connection");
+        let frame = CloseFrame { code, reason: reason.into() };
+        let _ = sender.send(Message::Close(Some(frame))).await;
+    }
+    if let Some(pump) = pump {
+        pump.abort();
+        // 等任务结束，它持有的订阅被丢弃后 release 才能移除房间
+        let _ = pump.await;
+    }
+    drop(inbox);
+    if let Some(session) = session {
+        session.leave().await;
+    }
+    state.release(&room);
+    state.metrics.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
     }
 }
 
@@ -68,14 +421,252 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<WsManager>) {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BroadcastMessage {
     pub message: String,
+    #[serde(default)]
+    pub room: Option<String>,
+}
+
+/// 消息是否为服务端生成的帧，例如 `chat.message` 或 `presence.join`
+fn is_reserved_frame(message: &str) -> bool {
+    serde_json::from_str::<Value>(message)
+        .ok()
+        .and_then(|value| {
+            let kind = value.get("type")?.as_str()?;
+            Some(WS_RESERVED_FRAME_TYPES.iter().any(|prefix| kind.starts_with(prefix)))
+        })
+        .unwrap_or(false)
 }
 
-// REST API 处理函数
+// REST API 处理函数，仅管理员可用
 pub async fn broadcast_message(
+    _admin: AdminUser,
     State(state): State<Arc<WsManager>>,
+    audit: Audit,
     axum::Json(payload): axum::Json<BroadcastMessage>,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
+    if is_reserved_frame(&payload.message) {
+        return Err(AppError::BadRequest("message uses a reserved frame type".into()));
+    }
+    let room = payload.room.as_deref().unwrap_or(WS_DEFAULT_ROOM);
+    let changes = diff([("message", Value::Null, json!(payload.message))]);
     state
-        .broadcast(payload.message)
-        .map_err(|e| e.to_string())
-} 
\ No newline at end of file
+        .broadcast_to(room, payload.message)
+        .map_err(|_| AppError::NotFound(format!("room {} has no subscribers", room)))?;
+    audit.record(AuditAction::Broadcast, Some(format!("room:{}", room)), changes).await;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresenceQuery {
+    room: Option<String>,
+}
+
+/// 指定 `room` 时只返回该房间，否则返回所有有在线用户的房间
+pub async fn presence_handler(
+    _user: AuthUser,
+    Query(query): Query<PresenceQuery>,
+    State(state): State<Arc<WsManager>>,
+) -> Result<Json<Vec<RoomPresence>>, AppError> {
+    let presence = state
+        .presence()
+        .ok_or_else(|| AppError::Unavailable("presence is not enabled".into()))?;
+    let rooms = match query.room {
+        Some(room) => {
+            let users = presence.room_users(&room).await?;
+            vec![RoomPresence { room, users }]
+        }
+        None => presence.rooms().await?,
+    };
+    Ok(Json(rooms))
+}
+
+pub async fn ws_stats(State(state): State<Arc<WsManager>>) -> Json<WsMetricsSnapshot> {
+    Json(state.metrics().snapshot())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::auth::JwtSecret;
+    use axum::extract::FromRef;
+    use axum::routing::get;
+    use axum::Router;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    #[derive(Clone)]
+    struct TestState {
+        ws: Arc<WsManager>,
+    }
+
+    impl FromRef<TestState> for Arc<WsManager> {
+        fn from_ref(state: &TestState) -> Self {
+            state.ws.clone()
+        }
+    }
+
+    impl FromRef<TestState> for JwtSecret {
+        fn from_ref(_: &TestState) -> Self {
+            JwtSecret("test_secret".into())
+        }
+    }
+
+    async fn serve(config: WsConfig) -> (String, Arc<WsManager>) {
+        let manager = Arc::new(WsManager::new(config));
+        let app = Router::new()
+            .route("/ws", get(ws_handler))
+            .with_state(TestState { ws: manager.clone() });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("ws://{}/ws", addr), manager)
+    }
+
+    /// 等待服务端完成订阅，避免广播早于订阅发生
+    async fn wait_connected(manager: &WsManager, count: u64) {
+        while manager.metrics().snapshot().active_connections < count {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    /// 读取直到收到 Close 帧，返回关闭码
+    async fn close_code<S>(stream: &mut S) -> Option<u16>
+    where
+        S: futures_util::Stream<Item = Result<ClientMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        while let Some(Ok(msg)) = stream.next().await {
+            if let ClientMessage::Close(frame) = msg {
+                return frame.map(|f| f.code.into());
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_reserved_frame() {
+        assert!(is_reserved_frame(r#"{"type":"chat.message","sender_id":1}"#));
+        assert!(is_reserved_frame(r#"{"type":"presence.join","room":"lobby","user_id":1}"#));
+        assert!(is_reserved_frame(r#"{"type":"lagged","missed":3}"#));
+        assert!(!is_reserved_frame(r#"{"type":"announcement"}"#));
+        assert!(!is_reserved_frame("chat.message"));
+    }
+
+    #[tokio::test]
+    async fn test_reap_on_pong_timeout() {
+        let (url, manager) = serve(WsConfig {
+            ping_interval: Duration::from_millis(50),
+            pong_timeout: Duration::from_millis(100),
+            idle_timeout: Duration::ZERO,
+            ..WsConfig::default()
+        })
+        .await;
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        // 不读取消息就不会自动回复 Pong
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(close_code(&mut client).await, Some(WS_CLOSE_PONG_TIMEOUT));
+        assert_eq!(manager.metrics().snapshot().reaped_pong_timeout, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reap_on_idle_timeout() {
+        let (url, manager) = serve(WsConfig {
+            ping_interval: Duration::from_millis(20),
+            pong_timeout: Duration::from_millis(200),
+            idle_timeout: Duration::from_millis(150),
+            ..WsConfig::default()
+        })
+        .await;
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        // 持续读取会自动回复 Pong，但没有数据帧仍会被判定为空闲
+        assert_eq!(close_code(&mut client).await, Some(WS_CLOSE_IDLE_TIMEOUT));
+        let snapshot = manager.metrics().snapshot();
+        assert_eq!(snapshot.reaped_idle, 1);
+        assert_eq!(snapshot.reaped_pong_timeout, 0);
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_is_notified() {
+        let (url, manager) = serve(WsConfig {
+            channel_capacity: 2,
+            ..WsConfig::default()
+        })
+        .await;
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        wait_connected(&manager, 1).await;
+
+        // 单线程运行时下连续发送，订阅者来不及读取
+        for i in 0..10 {
+            manager.broadcast(format!("m{}", i)).unwrap();
+        }
+
+        let mut texts = Vec::new();
+        while texts.len() < 3 {
+            if let Some(Ok(ClientMessage::Text(text))) = client.next().await {
+                texts.push(text.to_string());
+            }
+        }
+        assert_eq!(texts, vec![r#"{"missed":8,"type":"lagged"}"#, "m8", "m9"]);
+        let snapshot = manager.metrics().snapshot();
+        assert_eq!(snapshot.lag_events, 1);
+        assert_eq!(snapshot.missed_messages, 8);
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_is_disconnected() {
+        let mut config = WsConfig {
+            channel_capacity: 2,
+            ..WsConfig::default()
+        };
+        config.lag_policies.insert("alerts".into(), LagPolicy::Disconnect);
+        let (url, manager) = serve(config).await;
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("{}?room=alerts", url))
+            .await
+            .unwrap();
+        wait_connected(&manager, 1).await;
+
+        for i in 0..10 {
+            manager.broadcast_to("alerts", format!("m{}", i)).unwrap();
+        }
+        assert_eq!(close_code(&mut client).await, Some(WS_CLOSE_LAGGED));
+        assert_eq!(manager.metrics().snapshot().reaped_lagged, 1);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_room_released_on_close() {
+        let mut config = WsConfig::default();
+        config.lag_policies.insert("chat".into(), LagPolicy::Buffer(4));
+        let (url, manager) = serve(config).await;
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("{}?room=chat", url))
+            .await
+            .unwrap();
+        wait_connected(&manager, 1).await;
+        assert!(manager.rooms.read().unwrap().contains_key("chat"));
+
+        client.close(None).await.unwrap();
+        while manager.metrics().snapshot().active_connections > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(!manager.rooms.read().unwrap().contains_key("chat"));
+    }
+
+    #[tokio::test]
+    async fn test_buffer_pump_overflow() {
+        let (tx, rx) = broadcast::channel(16);
+        let (queue_tx, mut queue_rx) = mpsc::channel(3);
+        let pump = tokio::spawn(buffer_pump(rx, queue_tx));
+
+        let frames: Vec<Frame> = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, data)| Frame { id: i as u64, data: (*data).into(), target: None })
+            .collect();
+        for frame in &frames {
+            tx.send(frame.clone()).unwrap();
+        }
+        assert_eq!(queue_rx.recv().await, Some(Ok(frames[0].clone())));
+        assert_eq!(queue_rx.recv().await, Some(Ok(frames[1].clone())));
+        assert_eq!(queue_rx.recv().await, Some(Err(RecvError::Lagged(1))));
+        pump.await.unwrap();
+    }
+}
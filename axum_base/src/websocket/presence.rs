@@ -0,0 +1,332 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures_util::StreamExt;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{AsyncCommands, RedisResult, Script};
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::warn;
+use utoipa::ToSchema;
+use crate::constant::{PRESENCE_EVENTS_CHANNEL, PRESENCE_ROOMS_KEY, PRESENCE_ROOM_KEY_PREFIX};
+use crate::telemetry::redis::TracedConnection;
+use super::WsManager;
+
+// 清理过期成员后加入/续期，返回加入前该用户是否已在房间内
+// KEYS: 房间成员 zset, 房间索引 zset
+// ARGV: 成员, 用户前缀, 当前毫秒, 过期毫秒, key 的 TTL 秒数, 房间名
+static JOIN_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[3])
+        local present = 0
+        for _, m in ipairs(redis.call('ZRANGE', KEYS[1], 0, -1)) do
+            if string.sub(m, 1, string.len(ARGV[2])) == ARGV[2] then
+                present = 1
+                break
+            end
+        end
+        redis.call('ZADD', KEYS[1], ARGV[4], ARGV[1])
+        redis.call('EXPIRE', KEYS[1], ARGV[5])
+        redis.call('ZADD', KEYS[2], ARGV[4], ARGV[6])
+        redis.call('EXPIRE', KEYS[2], ARGV[5])
+        return present
+        ",
+    )
+});
+
+// 移除连接，返回该用户在房间内是否还有其他连接
+// KEYS: 房间成员 zset
+// ARGV: 成员, 用户前缀, 当前毫秒
+static LEAVE_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        redis.call('ZREM', KEYS[1], ARGV[1])
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[3])
+        for _, m in ipairs(redis.call('ZRANGE', KEYS[1], 0, -1)) do
+            if string.sub(m, 1, string.len(ARGV[2])) == ARGV[2] then
+                return 1
+            end
+        end
+        return 0
+        ",
+    )
+});
+
+static CONNECTION_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// 区分不同节点上的连接
+static NODE_ID: LazyLock<String> = LazyLock::new(|| {
+    format!("{:x}{:x}", std::process::id(), now_millis())
+});
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn room_key(room: &str) -> String {
+    format!("{}{}", PRESENCE_ROOM_KEY_PREFIX, room)
+}
+
+fn user_prefix(user_id: i32) -> String {
+    format!("{}:", user_id)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PresenceEvent {
+    #[serde(rename = "presence.join")]
+    Join { room: String, user_id: i32 },
+    #[serde(rename = "presence.leave")]
+    Leave { room: String, user_id: i32 },
+}
+
+impl PresenceEvent {
+    fn room(&self) -> &str {
+        match self {
+            PresenceEvent::Join { room, .. } | PresenceEvent::Leave { room, .. } => room,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RoomPresence {
+    pub room: String,
+    pub users: Vec<i32>,
+}
+
+/// 基于 Redis 的在线状态
+///
+/// 每个房间是一个 zset，成员为 `用户ID:节点:序号`，分数为过期时间。
+/// 连接心跳时续期，节点崩溃留下的成员在 TTL 后自然过期，因此多节点共享同一份状态。
+#[derive(Clone)]
+pub struct Presence {
+    client: redis::Client,
+    conn: Arc<OnceCell<ConnectionManager>>,
+    ttl: Duration,
+    timeout: Duration,
+}
+
+impl Presence {
+    pub fn new(client: redis::Client, ttl: Duration, timeout: Duration) -> Self {
+        Self {
+            client,
+            conn: Arc::new(OnceCell::new()),
+            ttl,
+            timeout,
+        }
+    }
+
+    /// 续期和离开在连接的 select 循环里执行，Redis 卡住时必须按超时返回
+    async fn conn(&self) -> RedisResult<TracedConnection> {
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(0)
+            .set_connection_timeout(self.timeout)
+            .set_response_timeout(self.timeout);
+        self.conn
+            .get_or_try_init(|| self.client.get_connection_manager_with_config(config))
+            .await
+            .cloned()
+            .map(TracedConnection::new)
+    }
+
+    pub(crate) async fn join(&self, room: &str, user_id: i32) -> PresenceSession {
+        let session = PresenceSession {
+            presence: self.clone(),
+            room: room.to_string(),
+            user_id,
+            member: format!(
+                "{}{}:{}",
+                user_prefix(user_id),
+                *NODE_ID,
+                CONNECTION_SEQ.fetch_add(1, Ordering::Relaxed)
+            ),
+        };
+        match session.touch().await {
+            Ok(false) => {
+                let event = PresenceEvent::Join { room: room.to_string(), user_id };
+                self.publish(&event).await;
+            }
+            Ok(true) => {}
+            Err(e) => warn!(room, user_id, "failed to join presence: {}", e),
+        }
+        session
+    }
+
+    async fn publish(&self, event: &PresenceEvent) {
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let result: RedisResult<()> = async {
+            let mut conn = self.conn().await?;
+            conn.publish(PRESENCE_EVENTS_CHANNEL, payload).await
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("failed to publish presence event: {}", e);
+        }
+    }
+
+    /// 房间内在线的用户 ID，升序去重
+    pub async fn room_users(&self, room: &str) -> RedisResult<Vec<i32>> {
+        let mut conn = self.conn().await?;
+        let members: Vec<String> = conn
+            .zrangebyscore(room_key(room), now_millis(), "+inf")
+            .await?;
+        let users: BTreeSet<i32> = members
+            .iter()
+            .filter_map(|m| m.split(':').next()?.parse().ok())
+            .collect();
+        Ok(users.into_iter().collect())
+    }
+
+    /// 所有有在线用户的房间
+    pub async fn rooms(&self) -> RedisResult<Vec<RoomPresence>> {
+        let mut conn = self.conn().await?;
+        let now = now_millis();
+        let _: () = conn.zrembyscore(PRESENCE_ROOMS_KEY, "-inf", now).await?;
+        let rooms: Vec<String> = conn.zrange(PRESENCE_ROOMS_KEY, 0, -1).await?;
+        let mut result = Vec::with_capacity(rooms.len());
+        for room in rooms {
+            let users = self.room_users(&room).await?;
+            if !users.is_empty() {
+                result.push(RoomPresence { room, users });
+            }
+        }
+        Ok(result)
+    }
+
+    /// 订阅所有节点发布的在线事件并推送给本节点对应房间的连接
+    pub async fn run_listener(self, ws: Arc<WsManager>) {
+        loop {
+            let mut pubsub = match self.client.get_async_pubsub().await {
+                Ok(pubsub) => pubsub,
+                Err(e) => {
+                    warn!("presence listener failed to connect: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            if let Err(e) = pubsub.subscribe(PRESENCE_EVENTS_CHANNEL).await {
+                warn!("presence listener failed to subscribe: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+            let mut messages = pubsub.on_message();
+            while let Some(msg) = messages.next().await {
+                let Ok(payload) = msg.get_payload::<String>() else {
+                    continue;
+                };
+                if let Ok(event) = serde_json::from_str::<PresenceEvent>(&payload) {
+                    // 房间在本节点没有连接时发送失败，忽略即可
+                    let _ = ws.broadcast_to(event.room(), payload);
+                }
+            }
+            warn!("presence listener disconnected, reconnecting");
+        }
+    }
+}
+
+/// 一个连接在房间内的在线记录
+pub(crate) struct PresenceSession {
+    presence: Presence,
+    room: String,
+    user_id: i32,
+    member: String,
+}
+
+impl PresenceSession {
+    /// 写入或续期，返回写入前该用户是否已在房间内
+    async fn touch(&self) -> RedisResult<bool> {
+        let mut conn = self.presence.conn().await?;
+        let now = now_millis();
+        let ttl = self.presence.ttl;
+        let present: i32 = JOIN_SCRIPT
+            .key(room_key(&self.room))
+            .key(PRESENCE_ROOMS_KEY)
+            .arg(&self.member)
+            .arg(user_prefix(self.user_id))
+            .arg(now)
+            .arg(now + ttl.as_millis() as u64)
+            .arg(ttl.as_secs().max(1) * 2)
+            .arg(&self.room)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(present == 1)
+    }
+
+    pub(crate) async fn refresh(&self) {
+        if let Err(e) = self.touch().await {
+            warn!(room = self.room, user_id = self.user_id, "failed to refresh presence: {}", e);
+        }
+    }
+
+    pub(crate) async fn leave(self) {
+        let result: RedisResult<i32> = async {
+            let mut conn = self.presence.conn().await?;
+            LEAVE_SCRIPT
+                .key(room_key(&self.room))
+                .arg(&self.member)
+                .arg(user_prefix(self.user_id))
+                .arg(now_millis())
+                .invoke_async(&mut conn)
+                .await
+        }
+        .await;
+        match result {
+            Ok(0) => {
+                let event = PresenceEvent::Leave { room: self.room.clone(), user_id: self.user_id };
+                self.presence.publish(&event).await;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(room = self.room, user_id = self.user_id, "failed to leave presence: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::test_utils::TestRedis;
+
+    #[test]
+    fn test_presence_event_format() {
+        let event = PresenceEvent::Join { room: "lobby".into(), user_id: 7 };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({ "type": "presence.join", "room": "lobby", "user_id": 7 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_join_and_leave() {
+        let test_redis = TestRedis::new().await;
+        let presence = Presence::new(
+            test_redis.client.clone(),
+            Duration::from_secs(30),
+            Duration::from_millis(500),
+        );
+
+        let first = presence.join("lobby", 1).await;
+        let second = presence.join("lobby", 1).await;
+        let other = presence.join("lobby", 2).await;
+        assert_eq!(presence.room_users("lobby").await.unwrap(), vec![1, 2]);
+        assert_eq!(
+            presence.rooms().await.unwrap(),
+            vec![RoomPresence { room: "lobby".into(), users: vec![1, 2] }]
+        );
+
+        // 同一用户还有另一个连接时仍然在线
+        first.leave().await;
+        assert_eq!(presence.room_users("lobby").await.unwrap(), vec![1, 2]);
+        second.leave().await;
+        other.leave().await;
+        assert!(presence.room_users("lobby").await.unwrap().is_empty());
+        assert!(presence.rooms().await.unwrap().is_empty());
+    }
+}
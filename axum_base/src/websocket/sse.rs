@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{stream, Stream};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::debug;
+use crate::config::LagPolicy;
+use crate::constant::WS_DEFAULT_ROOM;
+use crate::controller::auth::AuthUser;
+use super::{Frame, WsManager};
+
+#[derive(Debug, Deserialize)]
+pub struct SseParams {
+    room: Option<String>,
+    /// `EventSource` 首次连接无法设置请求头时使用，优先级低于 `Last-Event-ID`
+    last_event_id: Option<u64>,
+}
+
+/// 订阅与 `/ws` 相同的房间广播，作为无法升级 WebSocket 时的降级方案。
+/// 只发给特定用户的消息需要携带 token 才能收到。
+///
+/// 重连时携带 `Last-Event-ID` 会先补发续传缓冲中更新的消息。
+/// 落后策略为 `SkipAndNotify` 时发送 `lagged` 事件，否则结束响应，由客户端带着
+/// `Last-Event-ID` 重连续传。
+pub async fn sse_handler(
+    user: Option<AuthUser>,
+    headers: HeaderMap,
+    Query(params): Query<SseParams>,
+    State(state): State<Arc<WsManager>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let room = params.room.unwrap_or_else(|| WS_DEFAULT_ROOM.to_string());
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .or(params.last_event_id);
+    let keep_alive = KeepAlive::new().interval(state.config.sse_keep_alive);
+    let user_id = user.map(|u| u.id);
+    Sse::new(event_stream(state, room, last_event_id, user_id)).keep_alive(keep_alive)
+}
+
+/// 流被丢弃（客户端断开）时更新计数并释放房间
+struct SseGuard {
+    state: Arc<WsManager>,
+    room: String,
+}
+
+impl Drop for SseGuard {
+    fn drop(&mut self) {
+        self.state.metrics.active_sse_streams.fetch_sub(1, Ordering::Relaxed);
+        self.state.release(&self.room);
+    }
+}
+
+struct SseStream {
+    rx: broadcast::Receiver<Frame>,
+    replay: VecDeque<Frame>,
+    last_id: u64,
+    user_id: Option<i32>,
+    policy: LagPolicy,
+    guard: SseGuard,
+}
+
+/// SSE 的数据中不能出现 `\r`，换行统一为 `\n` 后按行拆成多个 `data:`
+fn to_event(frame: &Frame) -> Event {
+    let data = frame.data.replace("\r\n", "\n").replace('\r', "\n");
+    Event::default().id(frame.id.to_string()).data(data)
+}
+
+fn event_stream(
+    state: Arc<WsManager>,
+    room: String,
+    last_event_id: Option<u64>,
+    user_id: Option<i32>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let (rx, mut replay) = state.subscribe_from(&room, last_event_id);
+    // 不沿用客户端的 ID：进程重启或换了节点后 ID 从 1 重新计数，比它小的新消息也要发送
+    let last_id = replay.last().map_or(0, |frame| frame.id);
+    replay.retain(|frame| frame.visible_to(user_id));
+    state.metrics.active_sse_streams.fetch_add(1, Ordering::Relaxed);
+    let policy = state.config.lag_policy(&room);
+    let initial = SseStream {
+        rx,
+        replay: replay.into(),
+        last_id,
+        user_id,
+        policy,
+        guard: SseGuard { state, room },
+    };
+
+    stream::unfold(initial, |mut st| async move {
+        if let Some(frame) = st.replay.pop_front() {
+            st.last_id = frame.id;
+            return Some((Ok(to_event(&frame)), st));
+        }
+        loop {
+            match st.rx.recv().await {
+                // 订阅与续传快照之间不会有间隙，这里只防御重复
+                Ok(frame) if frame.id <= st.last_id || !frame.visible_to(st.user_id) => continue,
+                Ok(frame) => {
+                    st.last_id = frame.id;
+                    return Some((Ok(to_event(&frame)), st));
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    let metrics = &st.guard.state.metrics;
+                    metrics.lag_events.fetch_add(1, Ordering::Relaxed);
+                    metrics.missed_messages.fetch_add(missed, Ordering::Relaxed);
+                    debug!(room = st.guard.room, missed, "sse subscriber lagged");
+                    if st.policy != LagPolicy::SkipAndNotify {
+                        return None;
+                    }
+                    let notice = serde_json::json!({ "missed": missed }).to_string();
+                    return Some((Ok(Event::default().event("lagged").data(notice)), st));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WsConfig;
+    use axum::http::HeaderValue;
+    use axum::response::IntoResponse;
+    use futures_util::StreamExt;
+    use std::time::Duration;
+
+    /// 读取响应体直到包含 `needle`
+    async fn read_until(body: &mut axum::body::BodyDataStream, buf: &mut String, needle: &str) {
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !buf.contains(needle) {
+                let chunk = body.next().await.unwrap().unwrap();
+                buf.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+        })
+        .await
+        .expect("timed out waiting for sse event");
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_last_event_id() {
+        let manager = Arc::new(WsManager::new(WsConfig::default()));
+        // 另一个订阅者让房间保持存在
+        let _ws = manager.subscribe("feed");
+        for msg in ["a", "b", "c"] {
+            manager.broadcast_to("feed", msg.to_string()).unwrap();
+        }
+        // 只发给其他用户的消息不会出现在匿名连接中
+        manager.send_to("feed", Some(7), "private".to_string()).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("last-event-id", HeaderValue::from_static("1"));
+        let params = SseParams { room: Some("feed".into()), last_event_id: None };
+        let response = sse_handler(None, headers, Query(params), State(manager.clone()))
+            .await
+            .into_response();
+        let mut body = response.into_body().into_data_stream();
+        let mut buf = String::new();
+
+        read_until(&mut body, &mut buf, "data: c").await;
+        assert!(buf.contains("id: 2"));
+        assert!(buf.contains("data: b"));
+        assert!(!buf.contains("data: a"));
+        assert_eq!(manager.metrics().snapshot().active_sse_streams, 1);
+
+        manager.broadcast_to("feed", "d".to_string()).unwrap();
+        read_until(&mut body, &mut buf, "data: d").await;
+        assert!(buf.contains("id: 5"));
+        assert!(!buf.contains("private"));
+
+        drop(body);
+        assert_eq!(manager.metrics().snapshot().active_sse_streams, 0);
+    }
+
+    #[tokio::test]
+    async fn test_last_event_id_from_another_process() {
+        let manager = Arc::new(WsManager::new(WsConfig::default()));
+        let mut headers = HeaderMap::new();
+        headers.insert("last-event-id", HeaderValue::from_static("500"));
+        let params = SseParams { room: Some("feed".into()), last_event_id: None };
+        let response = sse_handler(None, headers, Query(params), State(manager.clone()))
+            .await
+            .into_response();
+        let mut body = response.into_body().into_data_stream();
+        let mut buf = String::new();
+
+        manager.broadcast_to("feed", "after restart".to_string()).unwrap();
+        read_until(&mut body, &mut buf, "data: after restart").await;
+        assert!(buf.contains("id: 1"));
+    }
+
+    #[tokio::test]
+    async fn test_carriage_return() {
+        let manager = Arc::new(WsManager::new(WsConfig::default()));
+        let params = SseParams { room: Some("feed".into()), last_event_id: None };
+        let response = sse_handler(None, HeaderMap::new(), Query(params), State(manager.clone()))
+            .await
+            .into_response();
+        let mut body = response.into_body().into_data_stream();
+        let mut buf = String::new();
+
+        manager.broadcast_to("feed", "a\r\nb".to_string()).unwrap();
+        manager.broadcast_to("feed", "c\rd".to_string()).unwrap();
+        read_until(&mut body, &mut buf, "data: d").await;
+        assert!(buf.contains("data: a\ndata: b\n"));
+        assert!(buf.contains("data: c\ndata: d\n"));
+        assert!(!buf.contains('\r'));
+    }
+}
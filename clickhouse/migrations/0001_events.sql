@@ -0,0 +1,21 @@
+-- 投票事件，vote_id 为 Postgres votes 表的主键，重复投递的行在合并时去重
+CREATE TABLE IF NOT EXISTS vote_events (
+    vote_id Int64,
+    url String,
+    vote Enum8('up' = 0, 'down' = 1),
+    occurred_at DateTime64(3, 'UTC')
+) ENGINE = ReplacingMergeTree
+PARTITION BY toYYYYMM(occurred_at)
+ORDER BY (url, occurred_at, vote_id);
+
+-- 用户生命周期事件，event_id 为发件箱 id，没有时为 topic/分区/offset
+CREATE TABLE IF NOT EXISTS user_events (
+    event_id String,
+    event_type LowCardinality(String),
+    user_id Nullable(Int32),
+    username String,
+    reason LowCardinality(String),
+    occurred_at DateTime64(3, 'UTC')
+) ENGINE = ReplacingMergeTree
+PARTITION BY toYYYYMM(occurred_at)
+ORDER BY (event_type, occurred_at, event_id);
@@ -0,0 +1,20 @@
+-- 事务性发件箱：与业务变更在同一事务中写入，由后台任务按 id 顺序发布到 Kafka
+CREATE TABLE IF NOT EXISTS outbox (
+    id BIGSERIAL PRIMARY KEY,
+    topic VARCHAR(255) NOT NULL,
+    key BYTEA,
+    payload BYTEA NOT NULL,
+    -- 消息头按位置一一对应，值按原始字节保存
+    header_keys TEXT[] NOT NULL DEFAULT '{}',
+    header_values BYTEA[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    sent_at TIMESTAMPTZ,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    -- 转发任务先用短事务认领一批行，在事务外发布，租约过期后其他实例可以重新认领
+    locked_until TIMESTAMPTZ,
+    claimed_by TEXT
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox (id) WHERE sent_at IS NULL;
+CREATE INDEX IF NOT EXISTS outbox_sent_at_idx ON outbox (sent_at) WHERE sent_at IS NOT NULL;
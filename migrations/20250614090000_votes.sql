@@ -0,0 +1,10 @@
+-- Voting gRPC 服务的投票记录，计数以此表为准
+CREATE TABLE IF NOT EXISTS votes (
+    id BIGSERIAL PRIMARY KEY,
+    url TEXT NOT NULL,
+    -- 取值对应 VotingRequest.Vote：0 赞成，1 反对
+    vote SMALLINT NOT NULL CHECK (vote IN (0, 1)),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS votes_url_idx ON votes (url);
@@ -0,0 +1,10 @@
+-- 用户搜索：按词前缀的全文检索和按片段的三元组匹配
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- 邮箱按 @ 和 . 拆成单独的词，便于按域名或本地部分搜索
+ALTER TABLE users ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', username || ' ' || translate(email, '@.', '  '))) STORED;
+
+CREATE INDEX IF NOT EXISTS users_search_idx ON users USING GIN (search);
+CREATE INDEX IF NOT EXISTS users_username_trgm_idx ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_email_trgm_idx ON users USING GIN (email gin_trgm_ops);